@@ -1 +1 @@
-wasm-pack build --target web -d site/pkg --no-pack
+wasm-pack build --target web -d site/pkg --no-pack -- --features serde
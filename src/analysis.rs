@@ -0,0 +1,159 @@
+use std::fmt::Display;
+
+use num_integer::Integer;
+use num_rational::Ratio;
+use num_traits::{CheckedAdd, CheckedMul, ConstZero, ToPrimitive, Zero};
+
+use crate::{
+    polyrhythm::{flatten_rhythm, EventKind, Polyrhythm},
+    rhythm::NoteDuration,
+    time::{Duration, Time},
+    units::WholeNotes,
+};
+
+// every onset of every repetition is collected, so lines that only line up again after very many repetitions are too long to analyze
+const MAX_ANALYZED_ONSETS: i64 = 100_000;
+
+pub struct Analysis {
+    // the shortest length after which every line starts again at the same time, assuming every line repeats
+    pub period: Duration<WholeNotes>,
+    // the times within one period where onsets of two or more lines line up, with the indexes (into Polyrhythm::rhythms) of those lines
+    pub coincidences: Vec<(Time<WholeNotes>, Vec<usize>)>,
+    // the longest duration that every onset time and every line length is a multiple of
+    pub subdivision: Duration<WholeNotes>,
+    tempo: (NoteDuration, u32),
+}
+
+// none if the lines are too long to analyze
+pub fn analyze(polyrhythm: &Polyrhythm) -> Option<Analysis> {
+    // recorded originals do not repeat in any meaningful way, so only notated originals are analyzed, and lines without notes do not repeat either
+    let lines: Vec<_> = polyrhythm
+        .rhythms
+        .iter()
+        .enumerate()
+        .filter_map(|(line_i, line)| line.original.notated().map(|original| (line_i, original.duration(), flatten_rhythm(original))))
+        .filter(|(_, duration, _)| *duration > Duration::ZERO)
+        .collect();
+
+    // long lines with lengths that have nothing in common can have a period that does not fit in a fraction
+    let mut durations = lines.iter().map(|(_, duration, _)| duration.0 .0);
+    let first_duration = durations.next()?;
+    let period = durations.try_fold(first_duration, lcm_ratio)?;
+
+    let subdivision = lines.iter().flat_map(|(_, duration, events)| std::iter::once(duration.0 .0).chain(events.iter().map(|ev| ev.time.0 .0))).try_fold(Ratio::ZERO, gcd_ratio)?;
+
+    let onset_count: i64 = lines.iter().map(|(_, duration, events)| i64::from((period / duration.0 .0).to_integer()) * events.len().max(1) as i64).sum();
+    if onset_count > MAX_ANALYZED_ONSETS {
+        return None;
+    }
+
+    let mut onsets: Vec<(Time<WholeNotes>, usize)> = lines
+        .iter()
+        .flat_map(|(line_i, duration, events)| {
+            let repetitions = (period / duration.0 .0).to_integer();
+            (0..repetitions).flat_map(move |repetition| {
+                events.iter().filter(|ev| ev.kind == EventKind::Start).map(move |ev| {
+                    let time = duration.0 .0.checked_mul(&Ratio::from_integer(repetition))?.checked_add(&ev.time.0 .0)?;
+                    Some((Time(WholeNotes(time)), *line_i))
+                })
+            })
+        })
+        .collect::<Option<_>>()?;
+    onsets.sort_by_key(|(time, _)| *time);
+
+    let mut coincidences: Vec<(Time<WholeNotes>, Vec<usize>)> = Vec::new();
+    for (time, line_i) in onsets {
+        match coincidences.last_mut() {
+            Some((last_time, lines)) if *last_time == time => {
+                if !lines.contains(&line_i) {
+                    lines.push(line_i);
+                }
+            }
+            _ => coincidences.push((time, vec![line_i])),
+        }
+    }
+    coincidences.retain(|(_, lines)| lines.len() >= 2);
+
+    Some(Analysis { period: Duration(WholeNotes(period)), coincidences, subdivision: Duration(WholeNotes(subdivision)), tempo: polyrhythm.tempo })
+}
+
+// for fractions in lowest terms, lcm(a/b, c/d) = lcm(a, c) / gcd(b, d) and gcd(a/b, c/d) = gcd(a, c) / lcm(b, d)
+// the lcm is worked out in i64, where it always fits, and is none if it does not fit back in i32
+fn lcm_ratio(a: Ratio<i32>, b: Ratio<i32>) -> Option<Ratio<i32>> {
+    Some(Ratio::new(i32::try_from(i64::from(*a.numer()).lcm(&i64::from(*b.numer()))).ok()?, a.denom().gcd(b.denom())))
+}
+fn gcd_ratio(a: Ratio<i32>, b: Ratio<i32>) -> Option<Ratio<i32>> {
+    if a.is_zero() {
+        Some(b)
+    } else if b.is_zero() {
+        Some(a)
+    } else {
+        Some(Ratio::new(a.numer().gcd(b.numer()), i32::try_from(i64::from(*a.denom()).lcm(&i64::from(*b.denom()))).ok()?))
+    }
+}
+
+impl Display for Analysis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cycle: {} ({}s), smallest subdivision: {}, coincidences at: ", self.period.0, self.period.0.to_seconds(self.tempo).0.to_f64().unwrap(), self.subdivision.0)?;
+        if self.coincidences.is_empty() {
+            write!(f, "none")
+        } else {
+            let times = self.coincidences.iter().map(|(time, lines)| format!("{} ({})", time.0 .0, lines.iter().map(|line_i| (line_i + 1).to_string()).collect::<Vec<_>>().join(", "))).collect::<Vec<_>>();
+            write!(f, "{}", times.join("; "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyze_source(source: &str) -> Option<Analysis> {
+        analyze(&crate::parse::parse(source).unwrap_or_else(|err| panic!("could not parse {source:?}: {}", crate::parse::parse_error_text(&err))))
+    }
+
+    fn coincidences(analysis: &Analysis) -> Vec<(Ratio<i32>, Vec<usize>)> {
+        analysis.coincidences.iter().map(|(time, lines)| (time.0 .0, lines.clone())).collect()
+    }
+
+    #[test]
+    fn analyzes_3_against_2() {
+        let analysis = analyze_source("tempo 4 = 60; poly 3:2 over 1;").expect("3:2 should be analyzed");
+        assert_eq!(analysis.period.0 .0, Ratio::new(1, 1));
+        assert_eq!(analysis.subdivision.0 .0, Ratio::new(1, 6));
+        assert_eq!(coincidences(&analysis), [(Ratio::ZERO, vec![0, 1])]);
+        assert_eq!(analysis.to_string(), "cycle: 1 whole notes (4s), smallest subdivision: 1/6 whole notes, coincidences at: 0 (1, 2)");
+    }
+
+    #[test]
+    fn repeats_lines_of_different_lengths_until_they_line_up() {
+        // a dotted half of a quarter and a half against half notes, which line up again after two and three repetitions
+        let analysis = analyze_source("tempo 4 = 60; {4 2}; {2};").expect("the lines should be analyzed");
+        assert_eq!(analysis.period.0 .0, Ratio::new(3, 2));
+        assert_eq!(analysis.subdivision.0 .0, Ratio::new(1, 4));
+        assert_eq!(coincidences(&analysis), [(Ratio::ZERO, vec![0, 1]), (Ratio::new(1, 1), vec![0, 1])]);
+        assert_eq!(analysis.to_string(), "cycle: 3/2 whole notes (6s), smallest subdivision: 1/4 whole notes, coincidences at: 0 (1, 2); 1 (1, 2)");
+    }
+
+    #[test]
+    fn only_counts_onsets() {
+        // the rest in the first line does not line up with the note in the second
+        let analysis = analyze_source("tempo 4 = 60; {4 r4}; {r4 4}; {2};").expect("the lines should be analyzed");
+        assert_eq!(coincidences(&analysis), [(Ratio::ZERO, vec![0, 2])]);
+        assert_eq!(analysis.to_string(), "cycle: 1/2 whole notes (2s), smallest subdivision: 1/4 whole notes, coincidences at: 0 (1, 3)");
+        assert!(analyze_source("tempo 4 = 60; {4 4}; {r4 4};").expect("the lines should be analyzed").to_string().ends_with("coincidences at: 1/4 (1, 2)"));
+    }
+
+    #[test]
+    fn leaves_out_recorded_lines() {
+        let analysis = analyze_source("tempo 4 = 60; recorded 0s 0.5s; {2}; {4};").expect("the notated lines should be analyzed");
+        assert_eq!(analysis.period.0 .0, Ratio::new(1, 2));
+        assert_eq!(coincidences(&analysis), [(Ratio::ZERO, vec![1, 2])]);
+        assert!(analyze_source("tempo 4 = 60; recorded 0s 0.5s;").is_none());
+    }
+
+    #[test]
+    fn does_not_analyze_lines_that_take_too_long_to_line_up() {
+        assert!(analyze_source("tempo 4 = 60; {rep 400 64}; {1009/1};").is_none());
+    }
+}
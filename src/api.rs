@@ -0,0 +1,201 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use num_traits::ToPrimitive;
+
+use wasm_bindgen::{prelude::wasm_bindgen, JsValue};
+use web_sys::{
+    js_sys::{Array, Error, Object, Reflect, Uint8Array},
+    HtmlCanvasElement,
+};
+
+use crate::{analysis, drawing, lilypond, midi, musicxml, parse, polyrhythm::Polyrhythm};
+
+// functions for using the engine from javascript without the site, which all take the source code of a polyrhythm
+// functions that need a parsed polyrhythm throw an Error with the parse error message if the source does not parse
+
+thread_local! {
+    // the font metadata only has to be fetched once for every url, not for every render
+    static FONTS: RefCell<HashMap<String, Rc<drawing::Font>>> = RefCell::new(HashMap::new());
+    // the same for the font files that are embedded into pdfs
+    static MUSIC_FONTS: RefCell<HashMap<String, Rc<Vec<u8>>>> = RefCell::new(HashMap::new());
+}
+
+// returns { ok: boolean, diagnostics: [{ message: string, start?: number, end?: number }], analysis?: string }
+#[wasm_bindgen]
+pub fn parse(source: &str) -> JsValue {
+    let result = Object::new();
+    let diagnostics = Array::new();
+    match parse::parse(source) {
+        Ok(polyrhythm) => {
+            set(&result, "ok", &true.into());
+            if let Some(polyrhythm_analysis) = analysis::analyze(&polyrhythm) {
+                set(&result, "analysis", &polyrhythm_analysis.to_string().into());
+            }
+        }
+        Err(err) => {
+            set(&result, "ok", &false.into());
+            let diagnostic = Object::new();
+            set(&diagnostic, "message", &parse::parse_error_text(&err).into());
+            if let Some((start, end)) = parse::parse_error_span(&err) {
+                set(&diagnostic, "start", &(start as u32).into());
+                set(&diagnostic, "end", &(end as u32).into());
+            }
+            diagnostics.push(&diagnostic);
+        }
+    }
+    set(&result, "diagnostics", &diagnostics);
+    result.into()
+}
+
+// draws the polyrhythm onto the canvas, resizing it to fit, and returns the same result as parse
+// the font metadata is fetched from fontMetadataUrl (or the site's location for it) the first time this is called with that url
+// if maxWidth is given, the rhythms are broken into systems that fit in that many pixels, and zoom scales everything from the default size
+#[wasm_bindgen]
+pub async fn render(source: String, canvas: HtmlCanvasElement, font_metadata_url: Option<String>, max_width: Option<f64>, zoom: Option<f64>) -> Result<JsValue, JsValue> {
+    let layout_options = layout_options(zoom)?;
+    let font = font(font_metadata_url.as_deref()).await;
+    if let Ok(polyrhythm) = parse::parse(&source) {
+        let polyrhythm_analysis = analysis::analyze(&polyrhythm);
+        drawing::draw(&canvas, &font, &polyrhythm, polyrhythm_analysis.as_ref(), None, max_width, &layout_options);
+    }
+    Ok(parse(&source))
+}
+
+// the zoom has to be a positive number, since anything else cannot be drawn
+fn layout_options(zoom: Option<f64>) -> Result<drawing::LayoutOptions, JsValue> {
+    match zoom {
+        Some(zoom) if !(zoom.is_finite() && zoom > 0.0) => Err(Error::new(&format!("invalid zoom: {zoom}")).into()),
+        zoom => Ok(drawing::LayoutOptions::zoomed(zoom.unwrap_or(1.0))),
+    }
+}
+
+// the error in seconds of every approximation, as an array with an array of errors for every line
+#[wasm_bindgen]
+pub fn score(source: &str) -> Result<Array, JsValue> {
+    let polyrhythm = parse_or_throw(source)?;
+    Ok(polyrhythm
+        .rhythms
+        .iter()
+        .map(|line| line.approximations.iter().map(|approx| JsValue::from(crate::polyrhythm::score_error(polyrhythm.tempo, &line.original, approx).0.to_f64().unwrap())).collect::<Array>())
+        .collect())
+}
+
+// the signed deviation in milliseconds of every event of every approximation, as an array for every line with an array for every approximation
+#[wasm_bindgen]
+pub fn deviations(source: &str) -> Result<Array, JsValue> {
+    let polyrhythm = parse_or_throw(source)?;
+    Ok(polyrhythm
+        .rhythms
+        .iter()
+        .map(|line| {
+            line.approximations
+                .iter()
+                .map(|approx| crate::polyrhythm::event_deviations(polyrhythm.tempo, &line.original, approx).iter().map(|deviation| JsValue::from(deviation.millis)).collect::<Array>())
+                .collect::<Array>()
+        })
+        .collect())
+}
+
+// a pdf of the polyrhythm as a Uint8Array
+// options can have paper ("a4", "a3", "letter" or "legal"), marginMm, title, composer, pageNumbers, zoom, fontMetadataUrl and fontUrl (the Bravura otf, which is embedded)
+#[wasm_bindgen(js_name = toPdf)]
+pub async fn to_pdf(source: String, options: Option<Object>) -> Result<Uint8Array, JsValue> {
+    let polyrhythm = parse_or_throw(&source)?;
+    let options = options.unwrap_or_default();
+    let option = |key: &str| Reflect::get(&options, &key.into()).ok().filter(|value| !value.is_undefined() && !value.is_null());
+
+    let mut page_options = drawing::PageOptions::default();
+    if let Some(paper) = option("paper").and_then(|paper| paper.as_string()) {
+        page_options.paper = drawing::PaperSize::from_name(&paper).ok_or_else(|| Error::new(&format!("unknown paper size {paper}")))?;
+    }
+    if let Some(margin) = option("marginMm").and_then(|margin| margin.as_f64()) {
+        page_options.margins = drawing::Margins::uniform(margin * drawing::MILLIMETER);
+    }
+    page_options.title = option("title").and_then(|title| title.as_string()).filter(|title| !title.is_empty());
+    page_options.composer = option("composer").and_then(|composer| composer.as_string()).filter(|composer| !composer.is_empty());
+    if let Some(page_numbers) = option("pageNumbers").and_then(|page_numbers| page_numbers.as_bool()) {
+        page_options.page_numbers = page_numbers;
+    }
+    page_options.layout = layout_options(option("zoom").and_then(|zoom| zoom.as_f64()))?;
+
+    let pdf = pdf(&polyrhythm, &page_options, option("fontMetadataUrl").and_then(|url| url.as_string()).as_deref(), option("fontUrl").and_then(|url| url.as_string()).as_deref()).await.map_err(|err| Error::new(&err))?;
+    Ok(Uint8Array::from(&pdf[..]))
+}
+
+#[wasm_bindgen(js_name = toMidi)]
+pub fn to_midi(source: &str) -> Result<Uint8Array, JsValue> {
+    Ok(Uint8Array::from(&midi::to_midi(&parse_or_throw(source)?)[..]))
+}
+
+#[wasm_bindgen(js_name = toMusicXml)]
+pub fn to_musicxml(source: &str) -> Result<String, JsValue> {
+    Ok(musicxml::to_musicxml(&parse_or_throw(source)?))
+}
+
+#[wasm_bindgen(js_name = toLilyPond)]
+pub fn to_lilypond(source: &str) -> Result<String, JsValue> {
+    Ok(lilypond::to_lilypond(&parse_or_throw(source)?))
+}
+
+#[cfg(feature = "serde")]
+#[wasm_bindgen(js_name = toJson)]
+pub fn to_json(source: &str) -> Result<String, JsValue> {
+    Ok(crate::json::to_json(&parse_or_throw(source)?))
+}
+
+#[cfg(feature = "serde")]
+#[wasm_bindgen(js_name = eventsJson)]
+pub fn events_json(source: &str) -> Result<String, JsValue> {
+    Ok(crate::json::events_json(&parse_or_throw(source)?))
+}
+
+// the source code for a polyrhythm exported with toJson
+#[cfg(feature = "serde")]
+#[wasm_bindgen(js_name = fromJson)]
+pub fn from_json(json: &str) -> Result<String, JsValue> {
+    crate::json::from_json(json).map(|polyrhythm| polyrhythm.to_string()).map_err(|err| Error::new(&err.to_string()).into())
+}
+
+// the source code for a line with a rhythm read from a MusicXML part (diagnostics are left out)
+#[wasm_bindgen(js_name = fromMusicXml)]
+pub fn from_musicxml(xml: &str) -> Result<String, JsValue> {
+    musicxml::from_musicxml(xml).map(|imported| format!("{};", imported.rhythm)).map_err(|err| Error::new(&err.to_string()).into())
+}
+
+// also used by the site's pdf export, so both share the fonts once they are fetched
+pub async fn pdf(polyrhythm: &Polyrhythm, options: &drawing::PageOptions, font_metadata_url: Option<&str>, font_url: Option<&str>) -> Result<Vec<u8>, String> {
+    let font = font(font_metadata_url).await;
+    let font_url = font_url.unwrap_or(drawing::BRAVURA_OTF_URL);
+    let music_font = match MUSIC_FONTS.with(|music_fonts| music_fonts.borrow().get(font_url).cloned()) {
+        Some(music_font) => music_font,
+        None => {
+            let window = web_sys::window().expect("global window does not exists");
+            let music_font = Rc::new(drawing::Font::load_bravura_otf(&window, font_url).await);
+            MUSIC_FONTS.with(|cached| cached.borrow_mut().insert(font_url.to_string(), music_font.clone()));
+            music_font
+        }
+    };
+    let polyrhythm_analysis = analysis::analyze(polyrhythm);
+    drawing::to_pdf(&font, &music_font, polyrhythm, polyrhythm_analysis.as_ref(), options).map_err(|err| err.to_string())
+}
+
+async fn font(font_metadata_url: Option<&str>) -> Rc<drawing::Font> {
+    let font_metadata_url = font_metadata_url.unwrap_or(drawing::BRAVURA_METADATA_URL);
+    match FONTS.with(|fonts| fonts.borrow().get(font_metadata_url).cloned()) {
+        Some(font) => font,
+        None => {
+            let window = web_sys::window().expect("global window does not exists");
+            let font = Rc::new(drawing::Font::load_bravura(&window, font_metadata_url).await);
+            FONTS.with(|cached| cached.borrow_mut().insert(font_metadata_url.to_string(), font.clone()));
+            font
+        }
+    }
+}
+
+fn parse_or_throw(source: &str) -> Result<Polyrhythm, JsValue> {
+    parse::parse(source).map_err(|err| Error::new(&parse::parse_error_text(&err)).into())
+}
+
+fn set(object: &Object, key: &str, value: &JsValue) {
+    Reflect::set(object, &key.into(), value).expect("setting a property on a plain object should not fail");
+}
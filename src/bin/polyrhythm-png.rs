@@ -0,0 +1,75 @@
+use std::{fs, io::Read, process::ExitCode};
+
+use polyrhythm::headless::{self, LayoutOptions, RasterOptions};
+
+// the paths that the site serves the font from, so this works from the repository without any options
+const DEFAULT_FONT_METADATA: &str = "site/fonts/bravura/redist/bravura_metadata.json";
+const DEFAULT_MUSIC_FONT: &str = "site/fonts/bravura/redist/otf/Bravura.otf";
+
+const USAGE: &str = "usage: polyrhythm-png <source file, or - for stdin> <output png> [--width <pixels>] [--zoom <factor>] [--scale <factor>] [--transparent] [--metadata <bravura_metadata.json>] [--font <Bravura.otf>] [--text-font <otf or ttf>]";
+
+struct Args {
+    source: String,
+    output: String,
+    font_metadata: String,
+    music_font: String,
+    text_font: Option<String>,
+    options: RasterOptions,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut positional = Vec::new();
+    let mut font_metadata = DEFAULT_FONT_METADATA.to_string();
+    let mut music_font = DEFAULT_MUSIC_FONT.to_string();
+    let mut text_font = None;
+    let mut options = RasterOptions::default();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--width" => options.max_width = Some(value()?.parse().map_err(|_| "--width has to be a number".to_string())?),
+            "--zoom" => {
+                let zoom: f64 = value()?.parse().map_err(|_| "--zoom has to be a number".to_string())?;
+                if !(zoom.is_finite() && zoom > 0.0) {
+                    return Err("--zoom has to be positive".to_string());
+                }
+                options.layout = LayoutOptions::zoomed(zoom);
+            }
+            "--scale" => options.scale = value()?.parse().map_err(|_| "--scale has to be a number".to_string())?,
+            "--transparent" => options.transparent = true,
+            "--metadata" => font_metadata = value()?,
+            "--font" => music_font = value()?,
+            "--text-font" => text_font = Some(value()?),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {arg}")),
+            _ => positional.push(arg),
+        }
+    }
+    let [source, output] = <[String; 2]>::try_from(positional).map_err(|_| "expected a source file and an output file".to_string())?;
+    Ok(Args { source, output, font_metadata, music_font, text_font, options })
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let source = if args.source == "-" {
+        let mut source = String::new();
+        std::io::stdin().read_to_string(&mut source).map_err(|err| format!("could not read stdin: {err}"))?;
+        source
+    } else {
+        fs::read_to_string(&args.source).map_err(|err| format!("could not read {}: {err}", args.source))?
+    };
+    let font_metadata = fs::read_to_string(&args.font_metadata).map_err(|err| format!("could not read {}: {err}", args.font_metadata))?;
+    let music_font = fs::read(&args.music_font).map_err(|err| format!("could not read {}: {err}", args.music_font))?;
+    let text_font = args.text_font.as_ref().map(|path| fs::read(path).map_err(|err| format!("could not read {path}: {err}"))).transpose()?;
+
+    let png = headless::to_png(&source, &font_metadata, &music_font, text_font.as_deref(), &args.options)?;
+    fs::write(&args.output, png).map_err(|err| format!("could not write {}: {err}", args.output))
+}
+
+fn main() -> ExitCode {
+    let result = parse_args(std::env::args().skip(1)).map_err(|err| format!("{err}\n{USAGE}")).and_then(run);
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -4,76 +4,220 @@ use wasm_bindgen::JsCast;
 use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
 
 use crate::{
+    analysis::Analysis,
     drawing::coord::{pixel::STAFF_SPACE_PIXELS, Pixels, Point, StaffSpaces},
-    polyrhythm::{self, Polyrhythm},
-    rhythm::{NoteDuration, NoteDurationKind, Rhythm},
-    time::Time,
+    polyrhythm::{self, Event, Original, Polyrhythm, Pulse, StaffStyle},
+    resultant::{self, Resultant},
+    rhythm::{NoteDuration, NoteDurationKind, Rhythm, SpannedSegment, TupletDisplay},
+    time::{Duration, Time},
     units::WholeNotes,
 };
 
-pub use drawing::Font;
+pub use drawing::{Font, BRAVURA_METADATA_URL, BRAVURA_OTF_URL};
+pub use layout::LayoutOptions;
+pub use page::{Margins, PageOptions, PaperSize, MILLIMETER};
+pub use pdf::to_pdf;
+#[cfg(feature = "raster")]
+pub use raster::{to_png, RasterOptions};
+use drawing::{Color, Renderer};
 
 mod coord;
 #[allow(clippy::module_inception)]
 mod drawing;
 mod layout;
+mod page;
+mod pdf;
+#[cfg(feature = "raster")]
+mod raster;
 
 const STAFF_HEIGHT: Pixels = Pixels(STAFF_SPACE_PIXELS.0 * 4.0);
+const LABEL_TEXT_HEIGHT: Pixels = Pixels(STAFF_SPACE_PIXELS.0 * 1.2);
 
 const DEFAULT_STAFF_LINE_THICKNESS: StaffSpaces = StaffSpaces(1.0 / 8.0);
 const DEFAULT_SLUR_MIDPOINT_THICKNESS: StaffSpaces = StaffSpaces(0.22);
 const DEFAULT_STEM_THICKNESS: StaffSpaces = StaffSpaces(3.0 / 25.0);
 const DEFAULT_BEAT_LINE_THICKNESS: StaffSpaces = StaffSpaces(1.0 / 25.0);
+const DEFAULT_DOWNBEAT_LINE_THICKNESS: StaffSpaces = StaffSpaces(2.0 / 25.0);
+const DEFAULT_SUBDIVISION_LINE_THICKNESS: StaffSpaces = StaffSpaces(1.0 / 50.0);
 const DEFAULT_CORRESPONDENCE_LINE_THICKNESS: StaffSpaces = StaffSpaces(2.0 / 25.0);
+const DEFAULT_COINCIDENCE_LINE_THICKNESS: StaffSpaces = StaffSpaces(4.0 / 25.0);
 
-pub fn draw(canvas: &HtmlCanvasElement, font: &Font, polyrhythm: &Polyrhythm) {
+const RECORDED_ONSET_TICK_HEIGHT: StaffSpaces = StaffSpaces(1.0);
+
+const AUGMENTATION_DOT_GLYPH_SPACING: StaffSpaces = StaffSpaces(0.5);
+const AUGMENTATION_DOT_SPACING: StaffSpaces = StaffSpaces(0.3);
+
+const TUPLET_LABEL_HEIGHT: StaffSpaces = StaffSpaces(4.5);
+const TUPLET_LABEL_SPACING: StaffSpaces = StaffSpaces(2.0);
+// tuplet labels below the staff are written from their baseline, so they need to be this much further away
+const TUPLET_LABEL_TEXT_HEIGHT: StaffSpaces = StaffSpaces(1.5);
+
+// the rests of two voices on one staff are moved apart so that they do not collide
+const VOICE_REST_OFFSET: StaffSpaces = StaffSpaces(2.0);
+
+// notes written in the part of the source code that is selected are drawn in this color
+const HIGHLIGHT_COLOR: Color = Color::ROYAL_BLUE;
+
+// approximated notes are colored from green (no deviation) to red (this deviation or more)
+const MAX_COLORED_DEVIATION_MILLIS: f64 = 100.0;
+
+// a note or rest that was drawn, with the range of the source code that it was written with and, for approximations, how far it is from the original
+pub struct DrawnNote {
+    pos: Point<Pixels>,
+    stems_down: bool,
+    span: Option<(usize, usize)>,
+    deviation_text: Option<String>,
+}
+
+// rhythms that share a staff are drawn as an upper voice with stems up and a lower voice with stems down
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Voice {
+    Only,
+    Upper,
+    Lower,
+}
+
+impl Voice {
+    fn stems_down(self) -> bool {
+        self == Voice::Lower
+    }
+}
+
+impl DrawnNote {
+    pub fn span(&self) -> Option<(usize, usize)> {
+        self.span
+    }
+
+    pub fn deviation_text(&self) -> Option<&str> {
+        self.deviation_text.as_deref()
+    }
+
+    pub fn is_highlighted(&self, highlight: Option<(usize, usize)>) -> bool {
+        touches(self.span, highlight)
+    }
+}
+
+fn touches(span: Option<(usize, usize)>, highlight: Option<(usize, usize)>) -> bool {
+    matches!((span, highlight), (Some((start, end)), Some((highlight_start, highlight_end))) if start <= highlight_end && highlight_start <= end)
+}
+
+// the note or rest drawn at x, y on the canvas, if there is one
+pub fn note_at(notes: &[DrawnNote], x: f64, y: f64) -> Option<&DrawnNote> {
+    // the notehead is to the right of the note position and the stem goes up from it, or down for lower voices
+    let hit = |note: &&DrawnNote| {
+        let (above, below) = if note.stems_down { (StaffSpaces(1.0), StaffSpaces(4.0)) } else { (StaffSpaces(4.0), StaffSpaces(1.0)) };
+        x >= (note.pos.x - StaffSpaces(0.5).into()).0 && x <= (note.pos.x + StaffSpaces(2.0).into()).0 && y >= (note.pos.y - above.into()).0 && y <= (note.pos.y + below.into()).0
+    };
+    notes.iter().filter(hit).min_by(|a, b| (a.pos.x.0 - x).abs().total_cmp(&(b.pos.x.0 - x).abs()))
+}
+
+// notes whose source range touches highlight are drawn highlighted, and every note that has a source range is returned for hit testing, in unscaled pixels
+// if max_width is given, the rhythms are broken into systems that fit in that many pixels
+// the canvas has as many pixels as the screen has for it, so that it is not blurry on high density screens
+pub fn draw(canvas: &HtmlCanvasElement, font: &Font, polyrhythm: &Polyrhythm, analysis: Option<&Analysis>, highlight: Option<(usize, usize)>, max_width: Option<f64>, options: &LayoutOptions) -> Vec<DrawnNote> {
     let ctx: CanvasRenderingContext2d = canvas.get_context("2d").expect("could not get canvas context").unwrap().dyn_into().expect("2d canvas context should be CanvasRenderingContext2d");
 
-    let layout_metrics = layout::LayoutMetrics::calculate(polyrhythm);
+    let resultant = if polyrhythm.show_resultant { resultant::resultant(polyrhythm).ok() } else { None };
 
-    drawing::set_canvas_size_and_clear(canvas, &ctx, layout_metrics.canvas_width(), layout_metrics.canvas_height());
+    let layout_metrics = layout::LayoutMetrics::calculate(polyrhythm, resultant.as_ref().map(|resultant| &resultant.rhythm), max_width.map(Pixels), options);
 
-    draw_tempo(&ctx, &layout_metrics, font, polyrhythm.tempo);
+    let device_pixel_ratio = web_sys::window().map(|window| window.device_pixel_ratio()).unwrap_or(1.0);
+    drawing::set_canvas_size_and_clear(canvas, &ctx, layout_metrics.canvas_width(), layout_metrics.canvas_height(), layout_metrics.scale(), device_pixel_ratio);
 
+    draw_score(&ctx, &layout_metrics, font, polyrhythm, resultant.as_ref(), analysis, highlight)
+}
+
+// the zoom at which the whole score fits in width on one system
+pub fn zoom_to_fit(polyrhythm: &Polyrhythm, width: f64) -> f64 {
+    let resultant = if polyrhythm.show_resultant { resultant::resultant(polyrhythm).ok() } else { None };
+    let layout_metrics = layout::LayoutMetrics::calculate(polyrhythm, resultant.as_ref().map(|resultant| &resultant.rhythm), None, &LayoutOptions::default());
+    width / layout_metrics.canvas_width().0
+}
+
+// draws everything onto a renderer that is already the size of the layout
+#[allow(clippy::too_many_arguments)]
+fn draw_score(
+    ctx: &dyn Renderer,
+    layout_metrics: &layout::LayoutMetrics,
+    font: &Font,
+    polyrhythm: &Polyrhythm,
+    resultant: Option<&Resultant>,
+    analysis: Option<&Analysis>,
+    highlight: Option<(usize, usize)>,
+) -> Vec<DrawnNote> {
+    draw_tempo(ctx, layout_metrics, font, polyrhythm.tempo);
+
+    let mut drawn_notes = Vec::new();
     let mut rhythm_i = 0;
     if let Some(pulse) = &polyrhythm.pulse {
-        draw_rhythm(&ctx, &layout_metrics, font, rhythm_i, pulse);
-        draw_pulse(&ctx, &layout_metrics, pulse);
+        drawn_notes.extend(draw_rhythm(ctx, layout_metrics, font, rhythm_i, &pulse.rhythm, Voice::Only, highlight, &[]));
+        draw_pulse(ctx, layout_metrics, pulse, 0, layout_metrics.staff_count() - 1);
         rhythm_i += 1;
     }
 
+    if let Some(poly) = &polyrhythm.poly {
+        // the generated lines come first and have no approximations, so they are drawn on consecutive staves starting at rhythm_i
+        draw_composite_grid(ctx, layout_metrics, &poly.composite_grid(), rhythm_i, rhythm_i + poly.components.len() - 1);
+    }
+
+    let mut original_indexes = Vec::new();
     for line in polyrhythm.rhythms.iter() {
-        let original_flattened = polyrhythm::flatten_rhythm(&line.original);
+        let original_flattened = line.original.flatten(polyrhythm.tempo);
         let original_i = rhythm_i;
+        original_indexes.push(original_i);
+        let overlaid = polyrhythm.overlays(line);
 
-        draw_rhythm(&ctx, &layout_metrics, font, rhythm_i, &line.original);
+        match &line.original {
+            Original::Notated(original) => drawn_notes.extend(draw_rhythm(ctx, layout_metrics, font, rhythm_i, original, if overlaid { Voice::Upper } else { Voice::Only }, highlight, &[])),
+            Original::Recorded(_) => draw_recorded(ctx, layout_metrics, font, rhythm_i, &original_flattened),
+        }
 
         rhythm_i += 1;
 
-        for approx in &line.approximations {
-            draw_rhythm(&ctx, &layout_metrics, font, rhythm_i, approx);
+        for (approx_i, approx) in line.approximations.iter().enumerate() {
+            // the overlaid approximation is drawn below the original on its staff, where its notes are already next to the original ones, so it has no correspondence lines
+            let (staff_i, voice) = if overlaid && approx_i == 0 { (original_i, Voice::Lower) } else { (rhythm_i, Voice::Only) };
+
+            let deviations = polyrhythm::event_deviations(polyrhythm.tempo, &line.original, approx);
+            drawn_notes.extend(draw_rhythm(ctx, layout_metrics, font, staff_i, approx, voice, highlight, &deviations));
 
             let approx_error = polyrhythm::score_error(polyrhythm.tempo, &line.original, approx);
-            drawing::fill_text(&ctx, font, &format!("error: {}s", approx_error.0.to_f64().unwrap()), layout_metrics.error_text_pos(rhythm_i));
-
-            let approx_flattened = polyrhythm::flatten_rhythm(approx);
-
-            for (original_ev, approx_ev) in original_flattened.iter().zip(approx_flattened) {
-                drawing::line(
-                    &ctx,
-                    layout_metrics.note_position(original_ev.time, original_i),
-                    layout_metrics.note_position(approx_ev.time, rhythm_i),
-                    "grey",
-                    DEFAULT_CORRESPONDENCE_LINE_THICKNESS.into(),
-                );
+            drawing::fill_text(ctx, font, &format!("error: {}s", approx_error.0.to_f64().unwrap()), layout_metrics.error_text_pos(staff_i));
+
+            if voice == Voice::Lower {
+                continue;
+            }
+
+            for deviation in &deviations {
+                draw_line_across_systems(ctx, layout_metrics, (deviation.original, original_i), (deviation.approximated, rhythm_i), deviation_color(deviation.millis), DEFAULT_CORRESPONDENCE_LINE_THICKNESS.into());
             }
 
             rhythm_i += 1;
         }
+
+        if let Some(pulse) = &line.pulse {
+            draw_pulse(ctx, layout_metrics, pulse, original_i, rhythm_i - 1);
+        }
     }
+
+    if let Some(resultant) = resultant {
+        draw_resultant(ctx, layout_metrics, font, rhythm_i, resultant);
+    }
+
+    if let Some(analysis) = analysis {
+        draw_coincidences(ctx, layout_metrics, analysis, &original_indexes);
+    }
+
+    draw_staff_labels(ctx, layout_metrics, font, &layout::staff_labels(polyrhythm, resultant.is_some()));
+
+    drawn_notes
 }
 
-fn draw_tempo(ctx: &CanvasRenderingContext2d, layout_metrics: &layout::LayoutMetrics, font: &Font, (dur, bpm): (NoteDuration, u32)) {
+fn draw_tempo(ctx: &dyn Renderer, layout_metrics: &layout::LayoutMetrics, font: &Font, (dur, bpm): (NoteDuration, u32)) {
+    drawing::fill_text(ctx, font, &format!("{} = {}", metronome_note_text(dur), bpm), layout_metrics.tempo_marking_pos());
+}
+
+fn metronome_note_text(dur: NoteDuration) -> String {
     let mut dur_sym = match dur.kind {
         NoteDurationKind::Whole => smufl::Glyph::MetNoteWhole,
         NoteDurationKind::Half => smufl::Glyph::MetNoteHalfUp,
@@ -90,44 +234,265 @@ fn draw_tempo(ctx: &CanvasRenderingContext2d, layout_metrics: &layout::LayoutMet
     .codepoint()
     .to_string();
 
-    if dur.dotted {
+    for _ in 0..dur.dots {
         dur_sym.push(smufl::Glyph::MetAugmentationDot.codepoint())
     }
 
-    drawing::fill_text(ctx, font, &format!("{} = {}", dur_sym, bpm), layout_metrics.tempo_marking_pos());
+    dur_sym
 }
 
-fn draw_rhythm(ctx: &CanvasRenderingContext2d, layout_metrics: &layout::LayoutMetrics, font: &Font, rhythm_index: usize, rhythm: &Rhythm) {
-    draw_staff_line(ctx, layout_metrics, font, rhythm_index);
+// deviations are given for approximations, to color their notes by how far they are from the original
+#[allow(clippy::too_many_arguments)]
+fn draw_rhythm(
+    ctx: &dyn Renderer,
+    layout_metrics: &layout::LayoutMetrics,
+    font: &Font,
+    rhythm_index: usize,
+    rhythm: &Rhythm,
+    voice: Voice,
+    highlight: Option<(usize, usize)>,
+    deviations: &[polyrhythm::EventDeviation],
+) -> Vec<DrawnNote> {
+    // the upper voice of a shared staff draws the staff
+    if voice != Voice::Lower {
+        draw_staff_line(ctx, layout_metrics, font, rhythm_index);
+    }
+    let mut drawn_notes = Vec::new();
+    // the deviations are in time order like the notes, so they are walked together
+    let mut deviations = deviations.iter().peekable();
+    // the notes that a note is tied to belong to its event, so they are colored like it
+    let mut tied_deviation = None;
     for note in flatten_rhythm(rhythm) {
-        let note_pos = layout_metrics.note_position(note.time, rhythm_index);
+        // rests stay on the middle line when the noteheads are moved on percussion staves
+        let note_pos = if note.is_rest { layout_metrics.note_position(note.time, rhythm_index) } else { layout_metrics.notehead_position(note.time, rhythm_index) };
+        let deviation = match tied_deviation.take() {
+            Some(deviation) => Some(deviation),
+            None => {
+                while deviations.next_if(|deviation| deviation.approximated < note.time).is_some() {}
+                deviations.next_if(|deviation| deviation.approximated == note.time)
+            }
+        };
+        if note.tied_to_next {
+            tied_deviation = deviation;
+        }
+        let is_highlighted = touches(note.span, highlight);
+        let color = match deviation {
+            _ if is_highlighted => HIGHLIGHT_COLOR,
+            Some(deviation) => deviation_color(deviation.millis),
+            None => Color::BLACK,
+        };
         if note.is_rest {
-            draw_rest(ctx, font, note.duration, note_pos);
+            draw_rest(ctx, font, layout_metrics.staff_style(), voice, note.duration, note_pos, color);
         } else {
-            draw_note(ctx, font, note.duration, note.tied_to_next, note_pos);
+            draw_note(ctx, font, voice, note.duration, note.tied_to_next, note_pos, color);
+        }
+        let deviation_text = deviation.map(|deviation| {
+            format!(
+                "original: {:.3}s, approximated: {:.3}s, difference: {:+.1}ms",
+                deviation.original_seconds.0 .0.to_f64().unwrap(),
+                deviation.approximated_seconds.0 .0.to_f64().unwrap(),
+                deviation.millis
+            )
+        });
+        if note.span.is_some() || deviation_text.is_some() {
+            drawn_notes.push(DrawnNote { pos: note_pos, stems_down: voice.stems_down(), span: note.span, deviation_text });
+        }
+    }
+    for tuplet in flatten_tuplets(rhythm) {
+        draw_tuplet_label(ctx, layout_metrics, font, rhythm_index, voice, &tuplet);
+    }
+    drawn_notes
+}
+
+// recorded onsets have no notated durations, so they are drawn as ticks through the staff line
+fn draw_recorded(ctx: &dyn Renderer, layout_metrics: &layout::LayoutMetrics, font: &Font, rhythm_index: usize, onsets: &[Event]) {
+    draw_staff_line(ctx, layout_metrics, font, rhythm_index);
+    for onset in onsets {
+        let pos = layout_metrics.notehead_position(onset.time, rhythm_index);
+        drawing::line(
+            ctx,
+            pos - Point::new(Pixels(0.0), RECORDED_ONSET_TICK_HEIGHT.into()),
+            pos + Point::new(Pixels(0.0), RECORDED_ONSET_TICK_HEIGHT.into()),
+            Color::BLACK,
+            font.metadata.engraving_defaults.stem_thickness.unwrap_or(DEFAULT_STEM_THICKNESS).into(),
+        );
+    }
+}
+
+fn draw_tuplet_label(ctx: &dyn Renderer, layout_metrics: &layout::LayoutMetrics, font: &Font, rhythm_index: usize, voice: Voice, tuplet: &FlattenedTuplet) {
+    fn number_text(number: u32) -> String {
+        const DIGITS: [smufl::Glyph; 10] = [
+            smufl::Glyph::Tuplet0,
+            smufl::Glyph::Tuplet1,
+            smufl::Glyph::Tuplet2,
+            smufl::Glyph::Tuplet3,
+            smufl::Glyph::Tuplet4,
+            smufl::Glyph::Tuplet5,
+            smufl::Glyph::Tuplet6,
+            smufl::Glyph::Tuplet7,
+            smufl::Glyph::Tuplet8,
+            smufl::Glyph::Tuplet9,
+        ];
+        number.to_string().chars().map(|digit| DIGITS[digit.to_digit(10).expect("number should only have digits") as usize].codepoint()).collect()
+    }
+
+    let text = match tuplet.display {
+        TupletDisplay::Number(actual) => number_text(actual),
+        TupletDisplay::Ratio(actual, normal) => format!("{}{}{}", number_text(actual), smufl::Glyph::TupletColon.codepoint(), number_text(normal)),
+        TupletDisplay::RatioWithNote(actual, normal, note_duration) => format!("{}{}{}{}", number_text(actual), smufl::Glyph::TupletColon.codepoint(), number_text(normal), metronome_note_text(note_duration)),
+    };
+
+    // centered over the notes of the tuplet, beyond the stems, with tuplets that contain other tuplets placed further out than the inner ones
+    // tuplets that are broken across systems are labelled on the system they start on
+    let system = layout_metrics.system_of(tuplet.start);
+    // and raised with the noteheads when they are above the middle line
+    let start = layout_metrics.position_in_system(system, tuplet.start, rhythm_index);
+    let end_x = Pixels(layout_metrics.position_in_system(system, tuplet.last_note, rhythm_index).x.0.min(layout_metrics.system_end_x(system).0));
+    let notehead_y = layout_metrics.notehead_position(tuplet.start, rhythm_index).y;
+    let height = StaffSpaces(TUPLET_LABEL_HEIGHT.0 + TUPLET_LABEL_SPACING.0 * tuplet.level as f64);
+    let y = if voice.stems_down() { Pixels(start.y.0.max(notehead_y.0)) + height.into() + TUPLET_LABEL_TEXT_HEIGHT.into() } else { Pixels(start.y.0.min(notehead_y.0)) - height.into() };
+    drawing::fill_text_centered(ctx, font, &text, Point::new((start.x + end_x) / 2.0, y));
+}
+
+fn draw_resultant(ctx: &dyn Renderer, layout_metrics: &layout::LayoutMetrics, font: &Font, rhythm_index: usize, resultant: &Resultant) {
+    draw_rhythm(ctx, layout_metrics, font, rhythm_index, &resultant.rhythm, Voice::Only, None, &[]);
+
+    // label every onset with the (1-based) numbers of the lines that it comes from
+    for (time, lines) in &resultant.sources {
+        let label = lines.iter().map(|line_i| (line_i + 1).to_string()).collect::<Vec<_>>().join(",");
+        drawing::fill_label(ctx, font, &label, layout_metrics.note_position(*time, rhythm_index) - Point::new(Pixels(0.0), STAFF_HEIGHT));
+    }
+}
+
+// a hue from green to red, for a deviation in either direction
+fn deviation_color(millis: f64) -> Color {
+    let amount = (millis.abs() / MAX_COLORED_DEVIATION_MILLIS).min(1.0);
+    Color::from_hue(120.0 * (1.0 - amount))
+}
+
+fn draw_coincidences(ctx: &dyn Renderer, layout_metrics: &layout::LayoutMetrics, analysis: &Analysis, original_indexes: &[usize]) {
+    for (time, lines) in &analysis.coincidences {
+        if *time >= layout_metrics.end_time() {
+            // the coincidences cover an entire cycle, which can be longer than what is drawn
+            break;
+        }
+
+        let top = lines.iter().map(|line_i| original_indexes[*line_i]).min().expect("coincidence should have at least 2 lines");
+        let bottom = lines.iter().map(|line_i| original_indexes[*line_i]).max().expect("coincidence should have at least 2 lines");
+        drawing::line(
+            ctx,
+            layout_metrics.note_position(*time, top) - Point::new(Pixels(0.0), STAFF_HEIGHT / 4.0),
+            layout_metrics.note_position(*time, bottom) + Point::new(Pixels(0.0), STAFF_HEIGHT / 4.0),
+            Color::RED,
+            DEFAULT_COINCIDENCE_LINE_THICKNESS.into(),
+        );
+    }
+}
+
+// the pulse is repeated until the end of the score, with lines across the staves from first_rhythm_index to last_rhythm_index
+fn draw_pulse(ctx: &dyn Renderer, layout_metrics: &layout::LayoutMetrics, pulse: &Pulse, first_rhythm_index: usize, last_rhythm_index: usize) {
+    let onsets: Vec<_> = flatten_rhythm(&pulse.rhythm).into_iter().map(|event| event.time).collect();
+    let cycle = pulse.rhythm.duration();
+    let end_time = layout_metrics.end_time();
+    // pulses from json are not checked like parsed ones, so one without notes (which would never get to the end) is left out and the subdivisions are kept in range
+    if cycle == Duration::ZERO {
+        return;
+    }
+    let subdivisions = pulse.subdivisions.clamp(*Pulse::SUBDIVISIONS.start(), *Pulse::SUBDIVISIONS.end());
+
+    let mut cycle_start = Time::ZERO;
+    while cycle_start < end_time {
+        for (onset_i, onset) in onsets.iter().enumerate() {
+            let time = cycle_start + *onset;
+            if time >= end_time {
+                break;
+            }
+            let (color, thickness) = if onset_i == 0 && pulse.downbeats { (Color::BLACK, DEFAULT_DOWNBEAT_LINE_THICKNESS) } else { (Color::GREY, DEFAULT_BEAT_LINE_THICKNESS) };
+            draw_pulse_line(ctx, layout_metrics, time, first_rhythm_index, last_rhythm_index, color, thickness.into());
+
+            let next = onsets.get(onset_i + 1).map(|next| cycle_start + *next).unwrap_or(cycle_start + cycle);
+            for subdivision in 1..subdivisions {
+                let subdivision_time = time + (next - time) * Ratio::new(subdivision as i32, subdivisions as i32);
+                if subdivision_time < end_time {
+                    draw_pulse_line(ctx, layout_metrics, subdivision_time, first_rhythm_index, last_rhythm_index, Color::LIGHT_GREY, DEFAULT_SUBDIVISION_LINE_THICKNESS.into());
+                }
+            }
+        }
+        cycle_start += cycle;
+    }
+}
+
+// from the middle line of the first staff to the middle line of the last staff, and half a staff beyond
+fn draw_pulse_line(ctx: &dyn Renderer, layout_metrics: &layout::LayoutMetrics, time: Time<WholeNotes>, first_rhythm_index: usize, last_rhythm_index: usize, color: Color, thickness: Pixels) {
+    let top = layout_metrics.note_position(time, first_rhythm_index) - Point::new(Pixels(0.0), STAFF_HEIGHT / 2.0);
+    let bottom = layout_metrics.note_position(time, last_rhythm_index) + Point::new(Pixels(0.0), STAFF_HEIGHT / 2.0);
+    drawing::line(ctx, top, bottom, color, thickness)
+}
+
+fn draw_composite_grid(ctx: &dyn Renderer, layout_metrics: &layout::LayoutMetrics, grid: &[Time<WholeNotes>], first_rhythm_index: usize, last_rhythm_index: usize) {
+    for time in grid {
+        let top = layout_metrics.note_position(*time, first_rhythm_index) - Point::new(Pixels(0.0), STAFF_HEIGHT / 2.0);
+        let bottom = layout_metrics.note_position(*time, last_rhythm_index) + Point::new(Pixels(0.0), STAFF_HEIGHT / 2.0);
+        drawing::line(ctx, top, bottom, Color::LIGHT_GREY, DEFAULT_BEAT_LINE_THICKNESS.into())
+    }
+}
+
+// every system has the labels, so that the staves can be told apart anywhere in the score
+fn draw_staff_labels(ctx: &dyn Renderer, layout_metrics: &layout::LayoutMetrics, font: &Font, labels: &[Option<String>]) {
+    drawing::set_fill_color(ctx, Color::BLACK);
+    for system in 0..layout_metrics.system_count() {
+        for (rhythm_index, label) in labels.iter().enumerate() {
+            if let Some(label) = label {
+                // the middle of lowercase letters on the middle of the staff
+                drawing::fill_label(ctx, font, label, layout_metrics.label_pos(system, rhythm_index) + Point::new(Pixels(0.0), LABEL_TEXT_HEIGHT / 4.0));
+            }
         }
     }
 }
 
-fn draw_pulse(ctx: &CanvasRenderingContext2d, layout_metrics: &layout::LayoutMetrics, pulse: &Rhythm) {
-    for note in flatten_rhythm(pulse) {
-        let x = layout_metrics.time_to_x(note.time);
-        drawing::line(ctx, Point::new(x, Pixels(0.0)), Point::new(x, Pixels(500.0)), "grey", DEFAULT_BEAT_LINE_THICKNESS.into())
+// percussion staves have five lines around the middle line
+fn draw_staff_line(ctx: &dyn Renderer, layout_metrics: &layout::LayoutMetrics, font: &Font, rhythm_index: usize) {
+    let line_offsets: &[f64] = match layout_metrics.staff_style() {
+        StaffStyle::OneLine => &[0.0],
+        StaffStyle::Percussion => &[-2.0, -1.0, 0.0, 1.0, 2.0],
+    };
+    for system in 0..layout_metrics.system_count() {
+        let middle_y = layout_metrics.position_in_system(system, Time::ZERO, rhythm_index).y;
+        for offset in line_offsets {
+            let y = middle_y + StaffSpaces(*offset).into();
+            drawing::line(
+                ctx,
+                Point::new(layout_metrics.system_start_x(), y),
+                Point::new(layout_metrics.system_end_x(system), y),
+                Color::BLACK,
+                font.metadata.engraving_defaults.staff_line_thickness.unwrap_or(DEFAULT_STAFF_LINE_THICKNESS).into(),
+            );
+        }
     }
 }
 
-fn draw_staff_line(ctx: &CanvasRenderingContext2d, layout_metrics: &layout::LayoutMetrics, font: &Font, rhythm_index: usize) {
-    let y = layout_metrics.rhythm_index_to_y(rhythm_index);
-    drawing::line(
-        ctx,
-        Point::new(Pixels(0.0), y),
-        Point::new(layout_metrics.canvas_width(), y),
-        "black",
-        font.metadata.engraving_defaults.staff_line_thickness.unwrap_or(DEFAULT_STAFF_LINE_THICKNESS).into(),
-    );
+// a line between two staves at two times, which is continued from the end of one system to the start of the next if the times are on different systems
+fn draw_line_across_systems(ctx: &dyn Renderer, layout_metrics: &layout::LayoutMetrics, from: (Time<WholeNotes>, usize), to: (Time<WholeNotes>, usize), color: Color, thickness: Pixels) {
+    let first_system = layout_metrics.system_of(from.0.min(to.0));
+    let last_system = layout_metrics.system_of(from.0.max(to.0));
+    for system in first_system..=last_system {
+        let start = layout_metrics.position_in_system(system, from.0, from.1);
+        let end = layout_metrics.position_in_system(system, to.0, to.1);
+        if first_system == last_system {
+            drawing::line(ctx, start, end, color, thickness);
+            continue;
+        }
+        // the part of the line that is within the system
+        let clip = |x: Pixels| {
+            let x = x.0.clamp(layout_metrics.system_start_x().0, layout_metrics.system_end_x(system).0);
+            Point::new(Pixels(x), Pixels(start.y.0 + (end.y.0 - start.y.0) * (x - start.x.0) / (end.x.0 - start.x.0)))
+        };
+        drawing::line(ctx, clip(start.x), clip(end.x), color, thickness);
+    }
 }
 
-fn draw_rest(ctx: &CanvasRenderingContext2d, font: &Font, duration: NoteDuration, pos: Point<Pixels>) {
+// pos is on the middle line of the staff
+fn draw_rest(ctx: &dyn Renderer, font: &Font, staff_style: StaffStyle, voice: Voice, duration: NoteDuration, pos: Point<Pixels>, color: Color) {
     let glyph = match duration.kind {
         NoteDurationKind::Whole => smufl::Glyph::RestWhole,
         NoteDurationKind::Half => smufl::Glyph::RestHalf,
@@ -141,15 +506,36 @@ fn draw_rest(ctx: &CanvasRenderingContext2d, font: &Font, duration: NoteDuration
         NoteDurationKind::Nd512 => smufl::Glyph::Rest512th,
         NoteDurationKind::Nd1024 => smufl::Glyph::Rest1024th,
     };
-    drawing::draw_glyph(ctx, font, glyph, pos);
 
-    if duration.dotted {
-        drawing::draw_glyph(ctx, font, smufl::Glyph::AugmentationDot, pos + Point::new(StaffSpaces(1.0), StaffSpaces(-0.5)).into());
-        // TODO: adjust x position
-    }
+    let pos = match voice {
+        Voice::Only => pos,
+        Voice::Upper => pos - Point::new(Pixels(0.0), VOICE_REST_OFFSET.into()),
+        Voice::Lower => pos + Point::new(Pixels(0.0), VOICE_REST_OFFSET.into()),
+    };
+
+    // whole rests hang from the line above the middle line (the only line on one-line staves) and half rests sit on the middle line, or where these would be for rests moved by their voice
+    // fonts do not agree on where the origins of these are, so they are placed by their bounding boxes
+    // shorter rests have their origin on the middle line
+    let line_above = match staff_style {
+        StaffStyle::OneLine => pos.y,
+        StaffStyle::Percussion => pos.y - StaffSpaces(1.0).into(),
+    };
+    let bbox = font.metadata.bounding_boxes.get(glyph);
+    let (glyph_y, line_y) = match duration.kind {
+        NoteDurationKind::Whole => (line_above + bbox.map(|bbox| bbox.ne.y()).unwrap_or(StaffSpaces(0.0)).into(), line_above),
+        NoteDurationKind::Half => (pos.y + bbox.map(|bbox| bbox.sw.y()).unwrap_or(StaffSpaces(0.0)).into(), pos.y),
+        _ => (pos.y, pos.y),
+    };
+    let glyph_pos = Point::new(pos.x, glyph_y);
+
+    drawing::set_fill_color(ctx, color);
+    drawing::draw_glyph(ctx, font, glyph, glyph_pos);
+
+    draw_augmentation_dots(ctx, font, glyph, glyph_pos, line_y, duration.dots);
+    drawing::set_fill_color(ctx, Color::BLACK);
 }
 
-fn draw_note(ctx: &CanvasRenderingContext2d, font: &Font, duration: NoteDuration, tied_to_next: bool, pos: Point<Pixels>) {
+fn draw_note(ctx: &dyn Renderer, font: &Font, voice: Voice, duration: NoteDuration, tied_to_next: bool, pos: Point<Pixels>, color: Color) {
     let notehead = match duration.kind {
         NoteDurationKind::Whole => smufl::Glyph::NoteheadWhole,
         NoteDurationKind::Half => smufl::Glyph::NoteheadHalf,
@@ -159,61 +545,74 @@ fn draw_note(ctx: &CanvasRenderingContext2d, font: &Font, duration: NoteDuration
     let notehead_anchors = font.metadata.anchors.get(notehead).unwrap();
     let notehead_origin: Point<_> = notehead_anchors.notehead_origin.map(Point::from).unwrap_or(Point::new(StaffSpaces(0.0), StaffSpaces(0.0))).into(); // TODO: make Point::ZERO work for staff spaces too
 
+    drawing::set_fill_color(ctx, color);
     drawing::draw_glyph(ctx, font, notehead, pos - notehead_origin);
 
-    if let Some(stem_start_offset) = notehead_anchors.stem_up_se {
+    // stems go up from the right of the notehead, or down from the left of it in lower voices
+    let (stem_start, stem_extension, stem_direction) = if voice.stems_down() { (notehead_anchors.stem_down_nw, notehead_anchors.stem_down_sw, 1.0) } else { (notehead_anchors.stem_up_se, notehead_anchors.stem_up_nw, -1.0) };
+    if let Some(stem_start_offset) = stem_start {
         let stemstart_offset = Point::<Pixels>::from(Point::<StaffSpaces>::from(stem_start_offset));
         const STEM_LENGTH: StaffSpaces = StaffSpaces(3.5);
+        let stem = Point::new(Pixels(0.0), Pixels::from(STEM_LENGTH) * stem_direction);
         // draw the stem
-        let stem_up_extension = notehead_anchors.stem_up_nw.map(Point::<StaffSpaces>::from).map(Point::<Pixels>::from).unwrap_or(Point::ZERO);
+        let stem_extension = stem_extension.map(Point::<StaffSpaces>::from).map(Point::<Pixels>::from).unwrap_or(Point::ZERO);
 
-        drawing::line(
-            ctx,
-            pos + stemstart_offset,
-            pos + stemstart_offset - Point::new(Pixels(0.0), STEM_LENGTH.into()) + stem_up_extension,
-            "black",
-            font.metadata.engraving_defaults.stem_thickness.unwrap_or(DEFAULT_STEM_THICKNESS).into(),
-        );
+        drawing::line(ctx, pos + stemstart_offset, pos + stemstart_offset + stem + stem_extension, color, font.metadata.engraving_defaults.stem_thickness.unwrap_or(DEFAULT_STEM_THICKNESS).into());
 
-        let flag_glyph = match duration.kind {
+        let flag_glyphs = match duration.kind {
             NoteDurationKind::Whole => None,
             NoteDurationKind::Half => None,
             NoteDurationKind::Quarter => None,
-            NoteDurationKind::Eigth => Some(smufl::Glyph::Flag8thUp),
-            NoteDurationKind::Sixteenth => Some(smufl::Glyph::Flag16thUp),
-            NoteDurationKind::Nd32 => Some(smufl::Glyph::Flag32ndUp),
-            NoteDurationKind::Nd64 => Some(smufl::Glyph::Flag64thUp),
-            NoteDurationKind::Nd128 => Some(smufl::Glyph::Flag128thUp),
-            NoteDurationKind::Nd256 => Some(smufl::Glyph::Flag256thUp),
-            NoteDurationKind::Nd512 => Some(smufl::Glyph::Flag512thUp),
-            NoteDurationKind::Nd1024 => Some(smufl::Glyph::Flag1024thUp),
+            NoteDurationKind::Eigth => Some((smufl::Glyph::Flag8thUp, smufl::Glyph::Flag8thDown)),
+            NoteDurationKind::Sixteenth => Some((smufl::Glyph::Flag16thUp, smufl::Glyph::Flag16thDown)),
+            NoteDurationKind::Nd32 => Some((smufl::Glyph::Flag32ndUp, smufl::Glyph::Flag32ndDown)),
+            NoteDurationKind::Nd64 => Some((smufl::Glyph::Flag64thUp, smufl::Glyph::Flag64thDown)),
+            NoteDurationKind::Nd128 => Some((smufl::Glyph::Flag128thUp, smufl::Glyph::Flag128thDown)),
+            NoteDurationKind::Nd256 => Some((smufl::Glyph::Flag256thUp, smufl::Glyph::Flag256thDown)),
+            NoteDurationKind::Nd512 => Some((smufl::Glyph::Flag512thUp, smufl::Glyph::Flag512thDown)),
+            NoteDurationKind::Nd1024 => Some((smufl::Glyph::Flag1024thUp, smufl::Glyph::Flag1024thDown)),
         };
 
-        if let Some(flag_glyph) = flag_glyph {
-            drawing::fill_text(ctx, font, &flag_glyph.codepoint().to_string(), pos - notehead_origin + stemstart_offset - Point::new(Pixels(0.0), STEM_LENGTH.into()));
+        if let Some((flag_up, flag_down)) = flag_glyphs {
+            let flag_glyph = if voice.stems_down() { flag_down } else { flag_up };
+            drawing::fill_text(ctx, font, &flag_glyph.codepoint().to_string(), pos - notehead_origin + stemstart_offset + stem);
         }
     }
 
     if tied_to_next {
-        draw_tie(ctx, font, pos + Point::new(StaffSpaces(0.0), StaffSpaces(0.1)).into(), pos + Point::new(StaffSpaces(3.0), StaffSpaces(0.1)).into());
+        // ties curve away from the stems
+        let tie_offset = StaffSpaces(-0.1 * stem_direction);
+        draw_tie(ctx, font, pos + Point::new(StaffSpaces(0.0), tie_offset).into(), pos + Point::new(StaffSpaces(3.0), tie_offset).into(), -stem_direction, color);
         // TODO: adjust offset from notehead
         // TODO: tie to the next note, not to a hardcoded offset
     }
 
-    if duration.dotted {
-        drawing::draw_glyph(ctx, font, smufl::Glyph::AugmentationDot, pos + Point::new(StaffSpaces(1.0), StaffSpaces(-0.5)).into());
-        // TODO: adjust x position
+    draw_augmentation_dots(ctx, font, notehead, pos - notehead_origin, pos.y, duration.dots);
+    drawing::set_fill_color(ctx, Color::BLACK);
+}
+
+// draws the dots to the right of a glyph that was drawn with its origin at glyph_pos, in the space above line_y
+fn draw_augmentation_dots(ctx: &dyn Renderer, font: &Font, glyph: smufl::Glyph, glyph_pos: Point<Pixels>, line_y: Pixels, dots: u8) {
+    let glyph_right = font.metadata.bounding_boxes.get(glyph).map(|bbox| bbox.ne.x()).unwrap_or(StaffSpaces(1.0));
+    let (dot_left, dot_width) = font.metadata.bounding_boxes.get(smufl::Glyph::AugmentationDot).map(|bbox| (bbox.sw.x(), StaffSpaces(bbox.ne.x().0 - bbox.sw.x().0))).unwrap_or((StaffSpaces(0.0), StaffSpaces(0.4)));
+
+    let y = line_y - Pixels::from(StaffSpaces(0.5));
+    for dot_i in 0..dots {
+        // the glyph is positioned by its origin, so the left side of its bounding box needs to be subtracted to put the left side of the dot where it should go
+        let dot_x = StaffSpaces(glyph_right.0 + AUGMENTATION_DOT_GLYPH_SPACING.0 + (dot_width.0 + AUGMENTATION_DOT_SPACING.0) * dot_i as f64 - dot_left.0);
+        drawing::draw_glyph(ctx, font, smufl::Glyph::AugmentationDot, Point::new(glyph_pos.x + dot_x.into(), y));
     }
 }
 
-fn draw_tie(ctx: &CanvasRenderingContext2d, font: &Font, start: Point<Pixels>, end: Point<Pixels>) {
+// direction is 1 for ties that curve down and -1 for ties that curve up
+fn draw_tie(ctx: &dyn Renderer, font: &Font, start: Point<Pixels>, end: Point<Pixels>, direction: f64, color: Color) {
     let dx = end.x - start.x;
 
-    let cp1 = Point::new(start.x + dx / 3.0, start.y + Pixels(25.0));
-    let cp2 = Point::new(end.x - dx / 3.0, end.y + Pixels(25.0));
+    let cp1 = Point::new(start.x + dx / 3.0, start.y + Pixels(25.0) * direction);
+    let cp2 = Point::new(end.x - dx / 3.0, end.y + Pixels(25.0) * direction);
 
     // TODO: do varying line width
-    drawing::bezier(ctx, start, cp1, cp2, end, "black", font.metadata.engraving_defaults.slur_midpoint_thickness.unwrap_or(DEFAULT_SLUR_MIDPOINT_THICKNESS).into());
+    drawing::bezier(ctx, start, cp1, cp2, end, color, font.metadata.engraving_defaults.slur_midpoint_thickness.unwrap_or(DEFAULT_SLUR_MIDPOINT_THICKNESS).into());
 }
 
 struct FlattenedNote {
@@ -221,38 +620,50 @@ struct FlattenedNote {
     is_rest: bool,
     duration: NoteDuration,
     tied_to_next: bool,
+    span: Option<(usize, usize)>,
 }
 fn flatten_rhythm(r: &Rhythm) -> Vec<FlattenedNote> {
     let mut current_time = Time::ZERO;
 
     let mut notes = Vec::new();
 
-    for segment in &r.segments {
+    for SpannedSegment { segment, span } in &r.segments {
+        let span = *span;
         match segment {
             crate::rhythm::RhythmSegment::Note(dur) => {
-                notes.push(FlattenedNote { time: current_time, is_rest: false, duration: *dur, tied_to_next: false });
+                notes.push(FlattenedNote { time: current_time, is_rest: false, duration: *dur, tied_to_next: false, span });
                 current_time += segment.duration();
             }
             crate::rhythm::RhythmSegment::TiedNote(durs) => {
                 let (last, firsts) = durs.split_last().expect("cannot have 0 notes in tied notes");
                 for dur in firsts {
-                    notes.push(FlattenedNote { time: current_time, is_rest: false, duration: *dur, tied_to_next: true });
+                    notes.push(FlattenedNote { time: current_time, is_rest: false, duration: *dur, tied_to_next: true, span });
                     current_time += dur.to_duration();
                 }
-                notes.push(FlattenedNote { time: current_time, is_rest: false, duration: *last, tied_to_next: false });
+                notes.push(FlattenedNote { time: current_time, is_rest: false, duration: *last, tied_to_next: false, span });
                 current_time += last.to_duration();
             }
             crate::rhythm::RhythmSegment::Rest(dur) => {
-                notes.push(FlattenedNote { time: current_time, is_rest: true, duration: *dur, tied_to_next: false });
+                notes.push(FlattenedNote { time: current_time, is_rest: true, duration: *dur, tied_to_next: false, span });
+                current_time += segment.duration();
+            }
+            crate::rhythm::RhythmSegment::FreeNote(dur) => {
+                notes.push(FlattenedNote { time: current_time, is_rest: false, duration: NoteDuration::nearest(*dur), tied_to_next: false, span });
+                current_time += segment.duration();
+            }
+            crate::rhythm::RhythmSegment::FreeRest(dur) => {
+                notes.push(FlattenedNote { time: current_time, is_rest: true, duration: NoteDuration::nearest(*dur), tied_to_next: false, span });
                 current_time += segment.duration();
             }
-            crate::rhythm::RhythmSegment::Tuplet { actual, normal, note_duration: _, rhythm, do_not_construct: _ } => {
+            crate::rhythm::RhythmSegment::Tuplet { actual, normal, note_duration: _, display: _, rhythm, do_not_construct: _ } => {
                 for flattened_subnote in flatten_rhythm(rhythm).into_iter() {
                     notes.push(FlattenedNote {
                         time: flattened_subnote.time * Ratio::new(*normal as i32, *actual as i32) + current_time,
                         is_rest: flattened_subnote.is_rest,
                         duration: flattened_subnote.duration,
                         tied_to_next: flattened_subnote.tied_to_next,
+                        // notes in tuplets that were not parsed are traced back to the whole tuplet
+                        span: flattened_subnote.span.or(span),
                     })
                 }
                 current_time += segment.duration();
@@ -262,3 +673,213 @@ fn flatten_rhythm(r: &Rhythm) -> Vec<FlattenedNote> {
 
     notes
 }
+
+struct FlattenedTuplet {
+    start: Time<WholeNotes>,
+    last_note: Time<WholeNotes>,
+    // 0 for tuplets that do not contain other tuplets, otherwise 1 more than the highest level of the tuplets inside it
+    level: usize,
+    display: TupletDisplay,
+}
+fn flatten_tuplets(r: &Rhythm) -> Vec<FlattenedTuplet> {
+    let mut current_time = Time::ZERO;
+
+    let mut tuplets = Vec::new();
+
+    for SpannedSegment { segment, span: _ } in &r.segments {
+        if let crate::rhythm::RhythmSegment::Tuplet { actual, normal, note_duration: _, display, rhythm, do_not_construct: _ } = segment {
+            let scale = Ratio::new(*normal as i32, *actual as i32);
+
+            let inner_tuplets: Vec<_> = flatten_tuplets(rhythm)
+                .into_iter()
+                .map(|inner| FlattenedTuplet { start: inner.start * scale + current_time, last_note: inner.last_note * scale + current_time, level: inner.level, display: inner.display })
+                .collect();
+            let last_note = flatten_rhythm(rhythm).last().expect("tuplet should have at least one note").time * scale + current_time;
+            let level = inner_tuplets.iter().map(|inner| inner.level + 1).max().unwrap_or(0);
+
+            tuplets.push(FlattenedTuplet { start: current_time, last_note, level, display: *display });
+            tuplets.extend(inner_tuplets);
+        }
+        current_time += segment.duration();
+    }
+
+    tuplets
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    // the small font that the golden tests draw with
+    const FONT_METADATA: &str = include_str!("../tests/fonts/test_music_font_metadata.json");
+
+    // the ends, color and thickness of a line
+    type RecordedLine = (Point<Pixels>, Point<Pixels>, Color, Pixels);
+
+    // keeps the lines and text that are drawn, so that tests can check where they are
+    #[derive(Default)]
+    struct Recorder {
+        lines: RefCell<Vec<RecordedLine>>,
+        texts: RefCell<Vec<(String, Point<Pixels>)>>,
+    }
+
+    impl Renderer for Recorder {
+        fn line(&self, p1: Point<Pixels>, p2: Point<Pixels>, color: Color, thickness: Pixels) {
+            self.lines.borrow_mut().push((p1, p2, color, thickness));
+        }
+
+        fn bezier(&self, _start: Point<Pixels>, _cp1: Point<Pixels>, _cp2: Point<Pixels>, _end: Point<Pixels>, _color: Color, _thickness: Pixels) {}
+
+        fn set_fill_color(&self, _color: Color) {}
+
+        fn fill_text(&self, _font: &Font, text: &str, pos: Point<Pixels>, _align: drawing::TextAlign) {
+            self.texts.borrow_mut().push((text.to_string(), pos));
+        }
+
+        fn fill_label(&self, _font: &Font, _text: &str, _pos: Point<Pixels>) {}
+    }
+
+    fn parse(source: &str) -> Polyrhythm {
+        crate::parse::parse(source).unwrap_or_else(|err| panic!("could not parse {source:?}: {}", crate::parse::parse_error_text(&err)))
+    }
+
+    // the lines drawn for the pulse as (time, color, thickness, top, bottom), checking that every line is where its time is
+    fn pulse_lines(polyrhythm: &Polyrhythm, pulse: &Pulse, first_rhythm_index: usize, last_rhythm_index: usize) -> Vec<(Ratio<i32>, Color, Pixels, Pixels, Pixels)> {
+        let layout_metrics = layout::LayoutMetrics::calculate(polyrhythm, None, None, &LayoutOptions::default());
+        let recorder = Recorder::default();
+        draw_pulse(&recorder, &layout_metrics, pulse, first_rhythm_index, last_rhythm_index);
+        let times: Vec<_> = (0..64).map(|sixtyfourths| Time(WholeNotes(Ratio::new(sixtyfourths, 64)))).collect();
+        recorder
+            .lines
+            .into_inner()
+            .into_iter()
+            .map(|(top, bottom, color, thickness)| {
+                assert!(top.x == bottom.x, "pulse lines should be vertical");
+                let time = times.iter().find(|time| layout_metrics.note_position(**time, first_rhythm_index).x == top.x).expect("pulse lines should be drawn at a 64th");
+                (time.0 .0, color, thickness, top.y, bottom.y)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn draws_pulses_until_the_end_of_the_score() {
+        let polyrhythm = parse("tempo 4 = 60; pulse {4 4}; {1};");
+        let pulse = polyrhythm.pulse.as_ref().expect("there should be a pulse");
+        let lines = pulse_lines(&polyrhythm, pulse, 0, 1);
+        let times: Vec<_> = lines.iter().map(|line| line.0).collect();
+        assert_eq!(times, [0, 1, 2, 3].map(|quarters| Ratio::new(quarters, 4)));
+        assert!(lines.iter().all(|line| line.1 == Color::GREY && line.2 == DEFAULT_BEAT_LINE_THICKNESS.into()));
+    }
+
+    #[test]
+    fn draws_downbeats_stronger_and_subdivisions_lighter() {
+        let polyrhythm = parse("tempo 4 = 60; pulse {4 4} subdivide 2 downbeats; {1};");
+        let pulse = polyrhythm.pulse.as_ref().expect("there should be a pulse");
+        let lines: Vec<_> = pulse_lines(&polyrhythm, pulse, 0, 1).into_iter().map(|(time, color, thickness, _, _)| (time, color, thickness)).collect();
+        let downbeat = (Color::BLACK, Pixels::from(DEFAULT_DOWNBEAT_LINE_THICKNESS));
+        let beat = (Color::GREY, Pixels::from(DEFAULT_BEAT_LINE_THICKNESS));
+        let subdivision = (Color::LIGHT_GREY, Pixels::from(DEFAULT_SUBDIVISION_LINE_THICKNESS));
+        let expected = [(0, downbeat), (1, subdivision), (2, beat), (3, subdivision), (4, downbeat), (5, subdivision), (6, beat), (7, subdivision)];
+        assert_eq!(lines.len(), expected.len());
+        for ((time, color, thickness), (eighths, (expected_color, expected_thickness))) in lines.into_iter().zip(expected) {
+            assert_eq!(time, Ratio::new(eighths, 8));
+            assert!(color == expected_color && thickness == expected_thickness, "the line at {time} is drawn wrong");
+        }
+    }
+
+    #[test]
+    fn spans_the_staves_that_the_pulse_applies_to() {
+        let polyrhythm = parse("tempo 4 = 60; pulse {2}; {1} approx {2 2}; {1} pulse {4};");
+        let layout_metrics = layout::LayoutMetrics::calculate(&polyrhythm, None, None, &LayoutOptions::default());
+        let staff_middle = |rhythm_index| layout_metrics.note_position(Time::ZERO, rhythm_index).y;
+
+        // the pulse of the polyrhythm goes from the pulse's staff to the last line's, and not up into the tempo marking
+        let lines = pulse_lines(&polyrhythm, polyrhythm.pulse.as_ref().expect("there should be a pulse"), 0, 3);
+        assert!(lines.iter().all(|line| line.3 == staff_middle(0) - STAFF_HEIGHT / 2.0 && line.4 == staff_middle(3) + STAFF_HEIGHT / 2.0));
+        assert!(lines.iter().all(|line| line.3.0 > layout_metrics.tempo_marking_pos().y.0));
+        // a line's own pulse only goes across that line's staff
+        let lines = pulse_lines(&polyrhythm, polyrhythm.rhythms[1].pulse.as_ref().expect("the second line should have a pulse"), 3, 3);
+        assert_eq!(lines.len(), 4);
+        assert!(lines.iter().all(|line| line.3 == staff_middle(3) - STAFF_HEIGHT / 2.0 && line.4 == staff_middle(3) + STAFF_HEIGHT / 2.0));
+    }
+
+    #[test]
+    fn rejects_pulses_that_cannot_be_drawn() {
+        let error = |source: &str| match crate::parse::parse(source) {
+            Ok(_) => panic!("{source:?} should not parse"),
+            Err(err) => crate::parse::parse_error_text(&err),
+        };
+        assert_eq!(error("tempo 4 = 60; pulse rep 0 4; {1};"), "invalid pulse: it has no notes, so it cannot repeat");
+        assert_eq!(error("tempo 4 = 60; {1} pulse rep 0 4;"), "invalid pulse: it has no notes, so it cannot repeat");
+        assert_eq!(error("tempo 4 = 60; pulse {4} subdivide 0; {1};"), "invalid subdivision: 0 (should be from 1 to 32)");
+        assert_eq!(error("tempo 4 = 60; pulse {4} subdivide 33; {1};"), "invalid subdivision: 33 (should be from 1 to 32)");
+        let polyrhythm = parse("tempo 4 = 60; pulse {4} subdivide 32 downbeats; {1};");
+        assert_eq!(polyrhythm.pulse.as_ref().map(|pulse| pulse.to_string()).as_deref(), Some("4 subdivide 32 downbeats"));
+    }
+
+    // where the rest glyph is drawn for a rest whose staff's middle line is at y = 0
+    fn rest_y(staff_style: StaffStyle, voice: Voice, kind: NoteDurationKind) -> StaffSpaces {
+        let font = Font::from_metadata(FONT_METADATA).expect("could not read the test font metadata");
+        let recorder = Recorder::default();
+        draw_rest(&recorder, &font, staff_style, voice, NoteDuration { kind, dots: 0 }, Point::new(Pixels(0.0), Pixels(0.0)), Color::BLACK);
+        let texts = recorder.texts.into_inner();
+        assert_eq!(texts.len(), 1, "only the rest should be drawn");
+        StaffSpaces(texts[0].1.y.0 / STAFF_SPACE_PIXELS.0)
+    }
+
+    #[test]
+    fn places_rests_on_the_lines_of_their_staff() {
+        // the test font's whole rest hangs from its origin and its half rest sits on it
+        assert_eq!(rest_y(StaffStyle::Percussion, Voice::Only, NoteDurationKind::Whole).0, -1.0);
+        assert_eq!(rest_y(StaffStyle::OneLine, Voice::Only, NoteDurationKind::Whole).0, 0.0);
+        for staff_style in [StaffStyle::Percussion, StaffStyle::OneLine] {
+            assert_eq!(rest_y(staff_style, Voice::Only, NoteDurationKind::Half).0, 0.0);
+            assert_eq!(rest_y(staff_style, Voice::Only, NoteDurationKind::Quarter).0, 0.0);
+        }
+        // voices move their rests out of each other's way, keeping them on lines
+        assert_eq!(rest_y(StaffStyle::Percussion, Voice::Upper, NoteDurationKind::Whole).0, -3.0);
+        assert_eq!(rest_y(StaffStyle::Percussion, Voice::Lower, NoteDurationKind::Half).0, 2.0);
+        assert_eq!(rest_y(StaffStyle::OneLine, Voice::Upper, NoteDurationKind::Quarter).0, -2.0);
+    }
+
+    // draws the whole score, returning the staves that the drawn notes are on, whether their stems go down, and the lines from one staff to another
+    fn draw_staves(source: &str) -> (layout::LayoutMetrics, Vec<(usize, bool)>, Vec<(usize, usize)>, Recorder) {
+        let polyrhythm = parse(source);
+        let font = Font::from_metadata(FONT_METADATA).expect("could not read the test font metadata");
+        let layout_metrics = layout::LayoutMetrics::calculate(&polyrhythm, None, None, &LayoutOptions::default());
+        let recorder = Recorder::default();
+        let drawn_notes = draw_score(&recorder, &layout_metrics, &font, &polyrhythm, None, None, None);
+        let staff_middles: Vec<_> = (0..layout_metrics.staff_count()).map(|staff| layout_metrics.note_position(Time::ZERO, staff).y).collect();
+        let staff_of = |y: Pixels| staff_middles.iter().position(|middle| *middle == y);
+        let notes = drawn_notes.iter().map(|note| (staff_of(note.pos.y).expect("notes should be on the middle line of a staff"), note.stems_down)).collect();
+        let across = recorder.lines.borrow().iter().filter_map(|(p1, p2, _, _)| Some((staff_of(p1.y)?, staff_of(p2.y)?))).filter(|(from, to)| from != to).collect();
+        (layout_metrics, notes, across, recorder)
+    }
+
+    #[test]
+    fn overlays_the_first_approximation_on_the_original() {
+        let (layout_metrics, notes, across, recorder) = draw_staves("tempo 4 = 60; overlay; {4 4} approx {8. 16 4} approx {4 4};");
+        assert_eq!(layout_metrics.staff_count(), 2);
+        // the original is the upper voice and the first approximation the lower one, and the second approximation gets the next staff to itself
+        assert_eq!(notes, [(0, false), (0, false), (0, true), (0, true), (0, true), (1, false), (1, false)]);
+        // only the second approximation has lines back to the original's notes
+        assert_eq!(across, [(0, 1), (0, 1)]);
+        let error_texts: Vec<_> = recorder.texts.borrow().iter().filter(|(text, _)| text.starts_with("error: ")).map(|(_, pos)| *pos).collect();
+        assert!(error_texts == [layout_metrics.error_text_pos(0), layout_metrics.error_text_pos(1)]);
+    }
+
+    #[test]
+    fn only_overlays_lines_with_approximations() {
+        let (layout_metrics, notes, across, _) = draw_staves("tempo 4 = 60; overlay; {4 4}; {2} approx {4 4};");
+        assert_eq!(layout_metrics.staff_count(), 2);
+        assert_eq!(notes, [(0, false), (0, false), (1, false), (1, true), (1, true)]);
+        assert!(across.is_empty());
+
+        let (layout_metrics, notes, across, _) = draw_staves("tempo 4 = 60; {2} approx {4 4};");
+        assert_eq!(layout_metrics.staff_count(), 2);
+        assert_eq!(notes, [(0, false), (1, false), (1, false)]);
+        assert_eq!(across, [(0, 1)]);
+    }
+}
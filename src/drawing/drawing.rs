@@ -4,7 +4,7 @@ use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
 
 use crate::drawing::{
     coord::{Pixels, Point},
-    STAFF_HEIGHT,
+    LABEL_TEXT_HEIGHT, STAFF_HEIGHT,
 };
 
 #[allow(clippy::manual_non_exhaustive)]
@@ -15,48 +15,157 @@ pub struct Font {
     _dont_construct: (),
 }
 
+// where the site serves the metadata from, relative to the page
+pub const BRAVURA_METADATA_URL: &str = "fonts/bravura/redist/bravura_metadata.json";
+// the font file itself, which is embedded into pdfs
+pub const BRAVURA_OTF_URL: &str = "fonts/bravura/redist/otf/Bravura.otf";
+
 impl Font {
-    pub async fn load_bravura(window: &web_sys::Window) -> Font {
+    // the page also has to provide the Bravura font itself through a @font-face rule
+    pub async fn load_bravura(window: &web_sys::Window, metadata_url: &str) -> Font {
         let fetch: web_sys::Response =
-            JsFuture::from(window.fetch_with_str("fonts/bravura/redist/bravura_metadata.json")).await.expect("could not load metadata for font").dyn_into().expect("fetch result should be a response");
+            JsFuture::from(window.fetch_with_str(metadata_url)).await.expect("could not load metadata for font").dyn_into().expect("fetch result should be a response");
         let metadata_contents =
             JsFuture::from(fetch.text().expect("metadata fetch response has no text")).await.expect("could not get text from metadata response").as_string().expect("fetch text should be a string");
-        let metadata = smufl::Metadata::from_reader(metadata_contents.as_bytes()).expect("could not parse metadata for font");
-        Font { music_font_selector: format!("{}px Bravura", STAFF_HEIGHT.0), text_font_selector: metadata.engraving_defaults.text_font_family.join(", "), metadata, _dont_construct: () }
+        Font::from_metadata(&metadata_contents).expect("could not parse metadata for font")
+    }
+
+    // for drawing without a browser, where the metadata is read from a file instead
+    pub fn from_metadata(metadata_contents: &str) -> Result<Font, String> {
+        let metadata = smufl::Metadata::from_reader(metadata_contents.as_bytes()).map_err(|err| err.to_string())?;
+        Ok(Font { music_font_selector: format!("{}px Bravura", STAFF_HEIGHT.0), text_font_selector: format!("{}px {}", LABEL_TEXT_HEIGHT.0, metadata.engraving_defaults.text_font_family.iter().map(String::as_str).chain(["serif"]).collect::<Vec<_>>().join(", ")), metadata, _dont_construct: () })
+    }
+
+    pub async fn load_bravura_otf(window: &web_sys::Window, url: &str) -> Vec<u8> {
+        let fetch: web_sys::Response = JsFuture::from(window.fetch_with_str(url)).await.expect("could not load font").dyn_into().expect("fetch result should be a response");
+        let contents = JsFuture::from(fetch.array_buffer().expect("font fetch response has no array buffer")).await.expect("could not get array buffer from font response");
+        web_sys::js_sys::Uint8Array::new(&contents).to_vec()
+    }
+}
+
+// a color that every renderer can draw with
+#[derive(Copy, Clone, PartialEq)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color { red: 0, green: 0, blue: 0 };
+    pub const GREY: Color = Color { red: 128, green: 128, blue: 128 };
+    pub const LIGHT_GREY: Color = Color { red: 211, green: 211, blue: 211 };
+    pub const RED: Color = Color { red: 255, green: 0, blue: 0 };
+    pub const ROYAL_BLUE: Color = Color { red: 65, green: 105, blue: 225 };
+
+    // the same as the css color hsl(hue, 80%, 40%)
+    pub fn from_hue(hue: f64) -> Color {
+        const SATURATION: f64 = 0.8;
+        const LIGHTNESS: f64 = 0.4;
+        let chroma = (1.0 - (2.0 * LIGHTNESS - 1.0).abs()) * SATURATION;
+        let channel = |n: f64| {
+            let k = (n + hue / 30.0) % 12.0;
+            let value = LIGHTNESS - chroma / 2.0 * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0);
+            (value * 255.0).round() as u8
+        };
+        Color { red: channel(0.0), green: channel(8.0), blue: channel(4.0) }
+    }
+
+    pub fn to_css(self) -> String {
+        format!("rgb({}, {}, {})", self.red, self.green, self.blue)
     }
 }
 
-pub fn set_canvas_size_and_clear(canvas: &HtmlCanvasElement, ctx: &CanvasRenderingContext2d, width: Pixels, height: Pixels) {
-    canvas.set_width(width.0 as u32);
-    canvas.set_height(height.0 as u32);
-    ctx.clear_rect(0.0, 0.0, width.0, height.0);
+pub enum TextAlign {
+    Start,
+    Center,
+}
+
+// everything that the score is drawn with, so that it can be drawn onto a canvas or into other formats
+pub trait Renderer {
+    fn line(&self, p1: Point<Pixels>, p2: Point<Pixels>, color: Color, thickness: Pixels);
+    fn bezier(&self, start: Point<Pixels>, cp1: Point<Pixels>, cp2: Point<Pixels>, end: Point<Pixels>, color: Color, thickness: Pixels);
+    // the color of all text drawn after this
+    fn set_fill_color(&self, color: Color);
+    // text in the music font, which can contain both smufl glyphs and ordinary characters
+    fn fill_text(&self, font: &Font, text: &str, pos: Point<Pixels>, align: TextAlign);
+    // text in the text font
+    fn fill_label(&self, font: &Font, text: &str, pos: Point<Pixels>);
 }
 
-pub fn line(ctx: &CanvasRenderingContext2d, p1: Point<Pixels>, p2: Point<Pixels>, color: &str, thickness: Pixels) {
-    ctx.set_stroke_style(&color.into());
-    ctx.set_line_width(thickness.0);
+pub fn line(ctx: &dyn Renderer, p1: Point<Pixels>, p2: Point<Pixels>, color: Color, thickness: Pixels) {
+    ctx.line(p1, p2, color, thickness)
+}
 
-    ctx.begin_path();
-    ctx.move_to(p1.x.0, p1.y.0);
-    ctx.line_to(p2.x.0, p2.y.0);
-    ctx.stroke();
+// the color of everything drawn with fill_text, fill_label and draw_glyph after this
+pub fn set_fill_color(ctx: &dyn Renderer, color: Color) {
+    ctx.set_fill_color(color)
 }
 
-// TODO: be able to select music or text
-pub fn fill_text(ctx: &CanvasRenderingContext2d, font: &Font, text: &str, pos: Point<Pixels>) {
-    ctx.set_font(&font.music_font_selector);
-    ctx.fill_text(text, pos.x.0, pos.y.0).unwrap()
+pub fn fill_text(ctx: &dyn Renderer, font: &Font, text: &str, pos: Point<Pixels>) {
+    ctx.fill_text(font, text, pos, TextAlign::Start)
 }
-pub fn draw_glyph(ctx: &CanvasRenderingContext2d, font: &Font, glyph: smufl::Glyph, pos: Point<Pixels>) {
+pub fn fill_text_centered(ctx: &dyn Renderer, font: &Font, text: &str, pos: Point<Pixels>) {
+    ctx.fill_text(font, text, pos, TextAlign::Center)
+}
+pub fn fill_label(ctx: &dyn Renderer, font: &Font, text: &str, pos: Point<Pixels>) {
+    ctx.fill_label(font, text, pos)
+}
+pub fn draw_glyph(ctx: &dyn Renderer, font: &Font, glyph: smufl::Glyph, pos: Point<Pixels>) {
     fill_text(ctx, font, &glyph.codepoint().to_string(), pos)
 }
 
-pub fn bezier(ctx: &CanvasRenderingContext2d, start: Point<Pixels>, cp1: Point<Pixels>, cp2: Point<Pixels>, end: Point<Pixels>, color: &str, thickness: Pixels) {
-    ctx.set_stroke_style(&color.into());
-    ctx.set_line_width(thickness.0);
+pub fn bezier(ctx: &dyn Renderer, start: Point<Pixels>, cp1: Point<Pixels>, cp2: Point<Pixels>, end: Point<Pixels>, color: Color, thickness: Pixels) {
+    ctx.bezier(start, cp1, cp2, end, color, thickness)
+}
+
+// the canvas is shown at the scaled size, but has pixel_ratio times as many pixels, and everything drawn on it is scaled to match
+pub fn set_canvas_size_and_clear(canvas: &HtmlCanvasElement, ctx: &CanvasRenderingContext2d, width: Pixels, height: Pixels, scale: f64, pixel_ratio: f64) {
+    canvas.set_width((width.0 * scale * pixel_ratio).ceil() as u32);
+    canvas.set_height((height.0 * scale * pixel_ratio).ceil() as u32);
+    let style = canvas.style();
+    style.set_property("width", &format!("{}px", width.0 * scale)).expect("could not set canvas width");
+    style.set_property("height", &format!("{}px", height.0 * scale)).expect("could not set canvas height");
+    // resizing the canvas already clears it and resets the transform
+    ctx.set_transform(scale * pixel_ratio, 0.0, 0.0, scale * pixel_ratio, 0.0, 0.0).expect("could not scale canvas");
+}
+
+impl Renderer for CanvasRenderingContext2d {
+    fn line(&self, p1: Point<Pixels>, p2: Point<Pixels>, color: Color, thickness: Pixels) {
+        self.set_stroke_style(&color.to_css().into());
+        self.set_line_width(thickness.0);
+
+        self.begin_path();
+        self.move_to(p1.x.0, p1.y.0);
+        self.line_to(p2.x.0, p2.y.0);
+        self.stroke();
+    }
+
+    fn bezier(&self, start: Point<Pixels>, cp1: Point<Pixels>, cp2: Point<Pixels>, end: Point<Pixels>, color: Color, thickness: Pixels) {
+        self.set_stroke_style(&color.to_css().into());
+        self.set_line_width(thickness.0);
+
+        self.begin_path();
+        self.move_to(start.x.0, start.y.0);
+        self.bezier_curve_to(cp1.x.0, cp1.y.0, cp2.x.0, cp2.y.0, end.x.0, end.y.0);
+        self.stroke();
+    }
+
+    fn set_fill_color(&self, color: Color) {
+        self.set_fill_style_str(&color.to_css());
+    }
+
+    fn fill_text(&self, font: &Font, text: &str, pos: Point<Pixels>, align: TextAlign) {
+        self.set_font(&font.music_font_selector);
+        if let TextAlign::Center = align {
+            self.set_text_align("center");
+        }
+        self.fill_text(text, pos.x.0, pos.y.0).unwrap();
+        self.set_text_align("start");
+    }
 
-    ctx.begin_path();
-    ctx.move_to(start.x.0, start.y.0);
-    ctx.bezier_curve_to(cp1.x.0, cp1.y.0, cp2.x.0, cp2.y.0, end.x.0, end.y.0);
-    ctx.stroke();
+    fn fill_label(&self, font: &Font, text: &str, pos: Point<Pixels>) {
+        self.set_font(&font.text_font_selector);
+        self.fill_text(text, pos.x.0, pos.y.0).unwrap()
+    }
 }
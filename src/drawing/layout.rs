@@ -1,12 +1,15 @@
 use crate::{
-    drawing::coord::{pixel::STAFF_SPACE_PIXELS, Pixels, Point},
-    polyrhythm::Polyrhythm,
-    rhythm::Rhythm,
+    drawing::{
+        coord::{pixel::STAFF_SPACE_PIXELS, Pixels, Point},
+        LABEL_TEXT_HEIGHT,
+    },
+    polyrhythm::{flatten_rhythm, Polyrhythm, RhythmLine, StaffPosition, StaffStyle},
+    rhythm::{Rhythm, SpannedSegment},
     time::{Duration, Time},
     units::WholeNotes,
 };
 use num_rational::Ratio;
-use num_traits::ToPrimitive;
+use num_traits::{ConstZero, ToPrimitive};
 use smufl::StaffSpaces;
 
 pub struct LayoutMetrics {
@@ -15,24 +18,80 @@ pub struct LayoutMetrics {
 
     error_text_x: Pixels,
     whole_note_width: Pixels,
+    end_time: Time<WholeNotes>,
     rhythm_height: Pixels,
+    staff_count: usize,
+    // the time at which every system starts, starting with 0
+    system_starts: Vec<Time<WholeNotes>>,
+    tempo_marking_height: Pixels,
+    scale: f64,
+    // the space left of the staves for their labels
+    left_margin: Pixels,
+    staff_style: StaffStyle,
+    // how many half staff spaces above the middle line the noteheads of every staff are
+    notehead_steps: Vec<i32>,
 }
 
-const MIN_NOTE_SPACING: StaffSpaces = StaffSpaces(3.0);
-const ERROR_DISPLAY_WIDTH: Pixels = Pixels(300.0);
-const TEMPO_MARKING_HEIGHT: Pixels = Pixels(80.0);
+// labels are measured without the font, as if every character was a bit wider than most are
+const LABEL_CHARACTER_WIDTH: f64 = 0.6;
+const LABEL_SPACING: StaffSpaces = StaffSpaces(1.5);
+// short pulses under long rhythms would make too many places to break at to go through on every draw
+const MAX_BREAK_CANDIDATES: usize = 10_000;
+
+#[derive(Copy, Clone)]
+pub struct LayoutOptions {
+    // everything is laid out with staff spaces of STAFF_SPACE_PIXELS and then drawn scaled so that a staff space is this big
+    pub staff_space: Pixels,
+    pub min_note_spacing: StaffSpaces,
+    // these are in unscaled pixels, so they grow and shrink with the staff space
+    pub error_display_width: Pixels,
+    pub tempo_marking_height: Pixels,
+}
+
+impl Default for LayoutOptions {
+    fn default() -> Self {
+        LayoutOptions { staff_space: STAFF_SPACE_PIXELS, min_note_spacing: StaffSpaces(3.0), error_display_width: Pixels(300.0), tempo_marking_height: Pixels(80.0) }
+    }
+}
+
+impl LayoutOptions {
+    // the default layout scaled by zoom
+    pub fn zoomed(zoom: f64) -> LayoutOptions {
+        LayoutOptions { staff_space: STAFF_SPACE_PIXELS * zoom, ..LayoutOptions::default() }
+    }
+
+    // how much bigger the layout is drawn than it is laid out
+    pub fn scale(&self) -> f64 {
+        self.staff_space.0 / STAFF_SPACE_PIXELS.0
+    }
+}
 
 impl LayoutMetrics {
-    pub fn calculate(polyrhythm: &Polyrhythm) -> LayoutMetrics {
-        let rhythm_height = STAFF_SPACE_PIXELS * 7.0;
+    // if max_width is given, the rhythms are broken into systems at pulse boundaries (or beats, without a pulse) so that they fit in it when scaled
+    pub fn calculate(polyrhythm: &Polyrhythm, resultant: Option<&Rhythm>, max_width: Option<Pixels>, options: &LayoutOptions) -> LayoutMetrics {
+        let scale = options.scale();
+        // in staff spaces, so that staves are as far apart for every staff space size once the layout is scaled
+        // five-line staves need room for notes on the top line and their stems
+        let rhythm_height = Pixels::from(match polyrhythm.staff {
+            StaffStyle::OneLine => StaffSpaces(7.0),
+            StaffStyle::Percussion => StaffSpaces(10.0),
+        });
 
-        let all_rhythms = polyrhythm.pulse.iter().chain(polyrhythm.rhythms.iter().flat_map(|rhythm_line| std::iter::once(&rhythm_line.original).chain(rhythm_line.approximations.iter())));
+        // recorded originals have no notated rhythm, so they only take up a staff and do not affect the spacing
+        let all_rhythms = polyrhythm.pulse.iter().map(|pulse| &pulse.rhythm).chain(polyrhythm.rhythms.iter().flat_map(|rhythm_line| rhythm_line.original.notated().into_iter().chain(rhythm_line.approximations.iter()))).chain(resultant);
+        let staff_count = polyrhythm.pulse.iter().count() + polyrhythm.rhythms.iter().map(|rhythm_line| line_staff_count(polyrhythm, rhythm_line)).sum::<usize>() + resultant.iter().count();
+
+        let longest_label = staff_labels(polyrhythm, resultant.is_some()).iter().flatten().map(|label| label.chars().count()).max();
+        let left_margin = match longest_label {
+            Some(characters) => LABEL_TEXT_HEIGHT * LABEL_CHARACTER_WIDTH * characters as f64 + Pixels::from(LABEL_SPACING) * 2.0,
+            None => Pixels(0.0),
+        };
 
         let whole_note_width = {
             fn flatten_rhythm_to_durations(r: &Rhythm) -> Vec<Duration<WholeNotes>> {
                 let mut notes = Vec::new();
 
-                for segment in &r.segments {
+                for SpannedSegment { segment, span: _ } in &r.segments {
                     match segment {
                         crate::rhythm::RhythmSegment::Note(dur) => {
                             notes.push(dur.to_duration());
@@ -45,7 +104,10 @@ impl LayoutMetrics {
                         crate::rhythm::RhythmSegment::Rest(dur) => {
                             notes.push(dur.to_duration());
                         }
-                        crate::rhythm::RhythmSegment::Tuplet { actual, normal, note_duration: _, rhythm, do_not_construct: _ } => {
+                        crate::rhythm::RhythmSegment::FreeNote(dur) | crate::rhythm::RhythmSegment::FreeRest(dur) => {
+                            notes.push(*dur);
+                        }
+                        crate::rhythm::RhythmSegment::Tuplet { actual, normal, note_duration: _, display: _, rhythm, do_not_construct: _ } => {
                             for flattened_subdur in flatten_rhythm_to_durations(rhythm).into_iter() {
                                 notes.push(flattened_subdur * Ratio::new(*normal as i32, *actual as i32))
                             }
@@ -58,16 +120,66 @@ impl LayoutMetrics {
             let all_durations = all_rhythms.clone().flat_map(flatten_rhythm_to_durations);
             let shortest_duration = all_durations.min().unwrap_or(Duration::WHOLE_NOTE); // just an arbitrary default duration in case there are no notes
 
-            Pixels::from(MIN_NOTE_SPACING) / shortest_duration.0 .0.to_f64().unwrap()
+            Pixels::from(options.min_note_spacing) / shortest_duration.0 .0.to_f64().unwrap()
         };
 
-        let longest_rhythm = all_rhythms.clone().map(|rhy| rhy.duration()).max().unwrap_or(Duration::WHOLE_NOTE); // also another arbitrary default
-        let canvas_width = whole_note_width * longest_rhythm.0 .0.to_f64().unwrap() + ERROR_DISPLAY_WIDTH;
-        let canvas_height = rhythm_height * (all_rhythms.count() + 1) as f64 + TEMPO_MARKING_HEIGHT;
+        let recorded_ends = polyrhythm.rhythms.iter().map(|rhythm_line| Duration(rhythm_line.original.end(polyrhythm.tempo).0));
+        let longest_rhythm = all_rhythms.clone().map(|rhy| rhy.duration()).chain(recorded_ends).max().unwrap_or(Duration::WHOLE_NOTE); // also another arbitrary default
+        let end_time = Time::ZERO + longest_rhythm;
+
+        let mut system_starts = vec![Time::ZERO];
+        if let Some(max_width) = max_width {
+            // at least one note has to fit on a system
+            let max_system_duration = (max_width / scale - options.error_display_width - left_margin).0.max(Pixels::from(options.min_note_spacing).0) / whole_note_width.0;
+            // the pulse (or the beat of the tempo) repeats until the end of the longest rhythm, where a pulse without notes (which json can have) would never get there
+            let (cycle, pulse_offsets) = match polyrhythm.pulse.as_ref().filter(|pulse| pulse.rhythm.duration() > Duration::ZERO) {
+                Some(pulse) => (pulse.rhythm.duration(), flatten_rhythm(&pulse.rhythm).into_iter().map(|ev| Duration(ev.time.0)).collect()),
+                None => (polyrhythm.tempo.0.to_duration(), vec![Duration::ZERO]),
+            };
+            // with too many, the systems are only broken at the start of every so many cycles
+            let cycle_count = (longest_rhythm.0 .0 / cycle.0 .0).ceil().to_integer() as usize;
+            let (cycle, pulse_offsets) = if cycle_count.saturating_mul(pulse_offsets.len()) > MAX_BREAK_CANDIDATES {
+                (cycle * Ratio::from_integer(cycle_count.div_ceil(MAX_BREAK_CANDIDATES) as i32), vec![Duration::ZERO])
+            } else {
+                (cycle, pulse_offsets)
+            };
+            let mut break_candidates = Vec::new();
+            let mut cycle_start = Time::ZERO;
+            while cycle_start < end_time {
+                break_candidates.extend(pulse_offsets.iter().map(|offset| cycle_start + *offset).filter(|time| *time > Time::ZERO && *time < end_time));
+                cycle_start += cycle;
+            }
+            break_candidates.sort();
+            break_candidates.dedup();
+            break_candidates.push(end_time);
+
+            // every system is filled with as many pulses as fit, and a pulse that does not fit on a system by itself gets its own system
+            let mut previous_candidate = None;
+            for candidate in break_candidates {
+                let system_start = *system_starts.last().expect("there is always a first system");
+                if (candidate - system_start).0 .0.to_f64().unwrap() > max_system_duration {
+                    match previous_candidate.filter(|previous| *previous > system_start) {
+                        Some(previous) => system_starts.push(previous),
+                        None if candidate < end_time => system_starts.push(candidate),
+                        None => {}
+                    }
+                }
+                previous_candidate = Some(candidate);
+            }
+        }
 
-        let error_text_x = whole_note_width * longest_rhythm.0 .0.to_f64().unwrap();
+        let widest_system = system_starts.iter().zip(system_starts.iter().skip(1).chain([&end_time])).map(|(start, end)| (*end - *start).0 .0.to_f64().unwrap()).fold(0.0, f64::max);
+        let canvas_width = left_margin + whole_note_width * widest_system + options.error_display_width;
+        let canvas_height = rhythm_height * ((staff_count + 1) * system_starts.len()) as f64 + options.tempo_marking_height;
 
-        LayoutMetrics { canvas_width, canvas_height, error_text_x, whole_note_width, rhythm_height }
+        let error_text_x = left_margin + whole_note_width * widest_system;
+
+        LayoutMetrics { canvas_width, canvas_height, error_text_x, whole_note_width, end_time, rhythm_height, staff_count, system_starts, tempo_marking_height: options.tempo_marking_height, scale, left_margin, staff_style: polyrhythm.staff, notehead_steps: notehead_steps(polyrhythm, resultant.is_some()) }
+    }
+
+    // how much bigger than the laid out size everything is drawn
+    pub fn scale(&self) -> f64 {
+        self.scale
     }
 
     pub fn canvas_width(&self) -> Pixels {
@@ -78,23 +190,232 @@ impl LayoutMetrics {
         self.canvas_height
     }
 
-    pub fn time_to_x(&self, time: Time<WholeNotes>) -> Pixels {
-        self.whole_note_width * time.0 .0.to_f64().unwrap()
+    // the time at which the longest rhythm ends
+    pub fn end_time(&self) -> Time<WholeNotes> {
+        self.end_time
+    }
+
+    pub fn staff_count(&self) -> usize {
+        self.staff_count
+    }
+
+    pub fn system_count(&self) -> usize {
+        self.system_starts.len()
+    }
+
+    // the system that a time is drawn on, where the end of the longest rhythm is on the last system
+    pub fn system_of(&self, time: Time<WholeNotes>) -> usize {
+        self.system_starts.partition_point(|start| *start <= time).saturating_sub(1)
+    }
+
+    // every system starts after the labels
+    pub fn system_start_x(&self) -> Pixels {
+        self.left_margin
+    }
+
+    // the x of the end of the system
+    pub fn system_end_x(&self, system: usize) -> Pixels {
+        let end = self.system_starts.get(system + 1).copied().unwrap_or(self.end_time);
+        self.left_margin + self.whole_note_width * (end - self.system_starts[system]).0 .0.to_f64().unwrap()
+    }
+
+    // where the label of a staff starts, with y on the middle of the staff
+    pub fn label_pos(&self, system: usize, rhythm_index: usize) -> Point<Pixels> {
+        Point::new(Pixels::from(LABEL_SPACING), self.position_in_system(system, self.system_starts[system], rhythm_index).y)
+    }
+
+    // the y range that the system takes up, so that systems can be put on separate pages, where the first system also has the tempo marking above it
+    pub fn system_band(&self, system: usize) -> (Pixels, Pixels) {
+        let system_height = self.rhythm_height * (self.staff_count + 1) as f64;
+        let top = if system == 0 { Pixels(0.0) } else { system_height * system as f64 + self.tempo_marking_height };
+        (top, system_height * (system + 1) as f64 + self.tempo_marking_height)
     }
 
-    pub fn rhythm_index_to_y(&self, index: usize) -> Pixels {
-        self.rhythm_height * (index + 1) as f64 + TEMPO_MARKING_HEIGHT
+    // where the time would be drawn on the system, which is outside of the system if the time is on another system
+    pub fn position_in_system(&self, system: usize, time: Time<WholeNotes>, rhythm_index: usize) -> Point<Pixels> {
+        let x = self.left_margin + self.whole_note_width * (time - self.system_starts[system]).0 .0.to_f64().unwrap();
+        let y = self.rhythm_height * ((self.staff_count + 1) * system + rhythm_index + 1) as f64 + self.tempo_marking_height;
+        Point::new(x, y)
     }
 
     pub fn note_position(&self, time: Time<WholeNotes>, rhythm_index: usize) -> Point<Pixels> {
-        Point::new(self.time_to_x(time), self.rhythm_index_to_y(rhythm_index))
+        self.position_in_system(self.system_of(time), time, rhythm_index)
     }
 
+    pub fn staff_style(&self) -> StaffStyle {
+        self.staff_style
+    }
+
+    // where the notehead of a note is, which is on the middle line of the staff unless the staff is a percussion staff
+    pub fn notehead_position(&self, time: Time<WholeNotes>, rhythm_index: usize) -> Point<Pixels> {
+        self.note_position(time, rhythm_index) - Point::new(Pixels(0.0), Pixels::from(StaffSpaces(0.5)) * self.notehead_steps[rhythm_index] as f64)
+    }
+
+    // the errors are written next to the first system
     pub fn error_text_pos(&self, rhythm_i: usize) -> Point<Pixels> {
-        Point::new(self.error_text_x, self.rhythm_index_to_y(rhythm_i))
+        Point::new(self.error_text_x, self.position_in_system(0, Time::ZERO, rhythm_i).y)
     }
 
     pub fn tempo_marking_pos(&self) -> Point<Pixels> {
-        Point::new(Pixels(0.0), TEMPO_MARKING_HEIGHT)
+        Point::new(Pixels(0.0), self.tempo_marking_height)
+    }
+}
+
+// the label of every staff from top to bottom, where the staves of unnamed originals have none
+pub fn staff_labels(polyrhythm: &Polyrhythm, has_resultant: bool) -> Vec<Option<String>> {
+    let mut labels = Vec::new();
+    if polyrhythm.pulse.is_some() {
+        labels.push(Some("pulse".to_string()));
+    }
+    for line in &polyrhythm.rhythms {
+        let mut approximation_labels = (0..line.approximations.len()).map(|approx_i| line.approximation_label(approx_i));
+        // a shared staff is labelled with both of its rhythms
+        if polyrhythm.overlays(line) {
+            let lower = approximation_labels.next().expect("overlaid line should have an approximation");
+            labels.push(Some(match &line.name {
+                Some(name) => format!("{name} / {lower}"),
+                None => lower,
+            }));
+        } else {
+            labels.push(line.name.clone());
+        }
+        labels.extend(approximation_labels.map(Some));
+    }
+    if has_resultant {
+        labels.push(Some("resultant".to_string()));
+    }
+    labels
+}
+
+// the pulse and the resultant always have their noteheads on the middle line
+fn notehead_steps(polyrhythm: &Polyrhythm, has_resultant: bool) -> Vec<i32> {
+    let mut steps = Vec::new();
+    if polyrhythm.pulse.is_some() {
+        steps.push(0);
+    }
+    for line in &polyrhythm.rhythms {
+        let line_steps = match polyrhythm.staff {
+            StaffStyle::OneLine => 0,
+            StaffStyle::Percussion => line.position.map(StaffPosition::steps).unwrap_or(0),
+        };
+        steps.extend(std::iter::repeat_n(line_steps, line_staff_count(polyrhythm, line)));
+    }
+    if has_resultant {
+        steps.push(0);
+    }
+    steps
+}
+
+// the original and every approximation have their own staff, except for an approximation that is overlaid on the original
+fn line_staff_count(polyrhythm: &Polyrhythm, line: &RhythmLine) -> usize {
+    1 + line.approximations.len() - usize::from(polyrhythm.overlays(line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Polyrhythm {
+        crate::parse::parse(source).unwrap_or_else(|err| panic!("could not parse {source:?}: {}", crate::parse::parse_error_text(&err)))
+    }
+
+    fn layout(source: &str, max_width: Option<f64>, zoom: f64) -> LayoutMetrics {
+        LayoutMetrics::calculate(&parse(source), None, max_width.map(Pixels), &LayoutOptions::zoomed(zoom))
+    }
+
+    #[test]
+    fn zoomed_layout_is_the_default_layout_scaled() {
+        let source = "tempo 4 = 120; pulse rep 8 4; {4 8 8 4 4 4 8 8 4 4 2 4 4 4 4 8 8 8 8 2 4 4};";
+        for (zoom, max_width) in [(1.0, None), (1.0, Some(1200.0)), (2.0, None), (2.0, Some(2400.0)), (0.5, Some(600.0))] {
+            let zoomed = layout(source, max_width, zoom);
+            let default = layout(source, max_width.map(|max_width| max_width / zoom), 1.0);
+            assert_eq!(zoomed.scale(), zoom);
+            assert_eq!(zoomed.canvas_width().0, default.canvas_width().0);
+            assert_eq!(zoomed.canvas_height().0, default.canvas_height().0);
+            assert!(zoomed.system_starts == default.system_starts);
+        }
+        // the same width holds less of a bigger score
+        assert_eq!(layout(source, Some(1200.0), 1.0).system_count(), 2);
+        assert!(layout(source, Some(1200.0), 2.0).system_count() > 2);
+    }
+
+    #[test]
+    fn staves_are_as_far_apart_in_staff_spaces_at_every_zoom() {
+        for (source, staff_spaces) in [("tempo 4 = 60; {4 4};", 7.0), ("tempo 4 = 60; staff percussion; {4 4};", 10.0)] {
+            for zoom in [0.5, 1.0, 3.0] {
+                let layout_metrics = layout(source, None, zoom);
+                assert_eq!((layout_metrics.rhythm_height * layout_metrics.scale()).0, (LayoutOptions::zoomed(zoom).staff_space * staff_spaces).0);
+            }
+        }
+    }
+
+    fn system_starts(source: &str, max_width: Option<f64>) -> Vec<Ratio<i32>> {
+        layout(source, max_width, 1.0).system_starts.iter().map(|start| start.0 .0).collect()
+    }
+
+    // how wide a system has to be for this many quarters, with the labels and the space for errors
+    fn width_for(source: &str, quarters: f64) -> f64 {
+        let layout_metrics = layout(source, None, 1.0);
+        (layout_metrics.left_margin + layout_metrics.whole_note_width * (quarters / 4.0) + LayoutOptions::default().error_display_width).0 + 0.5
+    }
+
+    #[test]
+    fn does_not_break_without_a_max_width_or_when_everything_fits() {
+        let source = "tempo 4 = 60; {rep 8 4};";
+        assert_eq!(system_starts(source, None), [Ratio::ZERO]);
+        assert_eq!(system_starts(source, Some(width_for(source, 8.0))), [Ratio::ZERO]);
+        assert_eq!(system_starts(source, Some(f64::INFINITY)), [Ratio::ZERO]);
+    }
+
+    #[test]
+    fn breaks_at_beats_without_a_pulse() {
+        // three quarter notes fit on a system, and the half notes can only be broken where a beat starts on a note
+        let source = "tempo 4 = 60; {rep 8 4};";
+        assert_eq!(system_starts(source, Some(width_for(source, 3.0))), [0, 3, 6].map(|quarters| Ratio::new(quarters, 4)));
+        let source = "tempo 2 = 60; {4 4 2 2 2};";
+        assert_eq!(system_starts(source, Some(width_for(source, 3.0))), [0, 2, 4, 6].map(|quarters| Ratio::new(quarters, 4)));
+    }
+
+    #[test]
+    fn breaks_at_pulses() {
+        // a pulse of a dotted half and a quarter, so only its notes are places to break
+        let source = "tempo 4 = 60; pulse {2. 4}; {rep 8 4};";
+        assert_eq!(system_starts(source, Some(width_for(source, 3.0))), [0, 3, 4, 7].map(|quarters| Ratio::new(quarters, 4)));
+        assert_eq!(system_starts(source, Some(width_for(source, 5.0))), [Ratio::ZERO, Ratio::new(1, 1)]);
+    }
+
+    #[test]
+    fn puts_pulses_that_do_not_fit_on_systems_of_their_own() {
+        let source = "tempo 4 = 60; pulse {1}; {rep 8 4};";
+        assert_eq!(system_starts(source, Some(width_for(source, 3.0))), [Ratio::ZERO, Ratio::new(1, 1)]);
+        assert!(layout(source, Some(width_for(source, 3.0)), 1.0).canvas_width().0 > width_for(source, 3.0));
+    }
+
+    #[test]
+    fn breaks_long_rhythms_with_short_pulses_at_fewer_places() {
+        // 4000 pulses of 64ths can all be broken at, so 37 of them fit on a system
+        let source = "tempo 4 = 60; pulse {64}; {rep 250 1/4};";
+        let starts = system_starts(source, Some(width_for(source, 37.0 / 16.0)));
+        assert_eq!(starts[..3], [0, 37, 74].map(|pulses| Ratio::new(pulses, 64)));
+        // but 16000 are more than MAX_BREAK_CANDIDATES, so the systems are only broken at every other pulse
+        let source = "tempo 4 = 60; pulse {64}; {rep 1000 1/4};";
+        let starts = system_starts(source, Some(width_for(source, 37.0 / 16.0)));
+        assert_eq!(starts[..3], [0, 36, 72].map(|pulses| Ratio::new(pulses, 64)));
+        assert!(starts.iter().all(|start| (start * 32).is_integer()));
+        assert_eq!(starts.len(), 16000 / 36 + 1);
+    }
+
+    // how far above the middle line each staff's noteheads are, in staff spaces
+    fn notehead_heights(source: &str) -> Vec<f64> {
+        let layout_metrics = layout(source, None, 1.0);
+        (0..layout_metrics.staff_count).map(|staff| (layout_metrics.note_position(Time::ZERO, staff).y.0 - layout_metrics.notehead_position(Time::ZERO, staff).y.0) / STAFF_SPACE_PIXELS.0).collect()
+    }
+
+    #[test]
+    fn puts_noteheads_at_their_staff_position() {
+        // the pulse stays on the middle line, and so do lines without a position and their approximations
+        assert_eq!(notehead_heights("tempo 4 = 60; staff percussion; pulse {4}; {4} at line 5 approx {4}; {4} at space 1; {4};"), [0.0, 2.0, 2.0, -1.5, 0.0]);
+        // one-line staves only have a middle line to put them on
+        assert_eq!(notehead_heights("tempo 4 = 60; {4} at line 5; {4} at space 1;"), [0.0, 0.0]);
     }
 }
@@ -0,0 +1,237 @@
+use std::ops::Range;
+
+use crate::drawing::{
+    coord::Pixels,
+    layout::{LayoutMetrics, LayoutOptions},
+};
+
+// sizes on pages are in points (1/72 inch), like in pdf
+pub const MILLIMETER: f64 = 72.0 / 25.4;
+
+pub const TITLE_SIZE: f64 = 18.0;
+pub const COMPOSER_SIZE: f64 = 11.0;
+pub const PAGE_NUMBER_SIZE: f64 = 9.0;
+// between the header and the score, and between the score and the page numbers
+const HEADER_SPACING: f64 = 12.0;
+const FOOTER_SPACING: f64 = 12.0;
+
+// a pixel of the score is the size of a css pixel at the layout's zoom, unless the systems have to be smaller to fit on the pages
+const POINTS_PER_PIXEL: f64 = 0.75;
+
+#[derive(Copy, Clone)]
+pub struct PaperSize {
+    pub width: f64,
+    pub height: f64,
+}
+
+impl PaperSize {
+    pub const A4: PaperSize = PaperSize { width: 595.28, height: 841.89 };
+    pub const A3: PaperSize = PaperSize { width: 841.89, height: 1190.55 };
+    pub const LETTER: PaperSize = PaperSize { width: 612.0, height: 792.0 };
+    pub const LEGAL: PaperSize = PaperSize { width: 612.0, height: 1008.0 };
+
+    pub fn from_name(name: &str) -> Option<PaperSize> {
+        match name.to_lowercase().as_str() {
+            "a4" => Some(PaperSize::A4),
+            "a3" => Some(PaperSize::A3),
+            "letter" => Some(PaperSize::LETTER),
+            "legal" => Some(PaperSize::LEGAL),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Margins {
+    pub top: f64,
+    pub right: f64,
+    pub bottom: f64,
+    pub left: f64,
+}
+
+impl Margins {
+    pub fn uniform(margin: f64) -> Margins {
+        Margins { top: margin, right: margin, bottom: margin, left: margin }
+    }
+}
+
+pub struct PageOptions {
+    pub paper: PaperSize,
+    pub margins: Margins,
+    // written at the top of the first page
+    pub title: Option<String>,
+    pub composer: Option<String>,
+    pub page_numbers: bool,
+    // the score is zoomed like on the canvas, unless it has to be smaller to fit on the pages
+    pub layout: LayoutOptions,
+}
+
+impl Default for PageOptions {
+    fn default() -> Self {
+        PageOptions { paper: PaperSize::A4, margins: Margins::uniform(15.0 * MILLIMETER), title: None, composer: None, page_numbers: true, layout: LayoutOptions::default() }
+    }
+}
+
+impl PageOptions {
+    // the margins have to leave room on the paper for the score, below the header and above the page numbers
+    pub fn is_valid(&self) -> bool {
+        let Margins { top, right, bottom, left } = self.margins;
+        [self.paper.width, self.paper.height, top, right, bottom, left].iter().all(|size| size.is_finite() && *size >= 0.0) && self.content_width() > 0.0 && self.available_height(true) > 0.0 && self.available_height(false) > 0.0
+    }
+
+    pub fn content_width(&self) -> f64 {
+        self.paper.width - self.margins.left - self.margins.right
+    }
+
+    pub fn header_height(&self) -> f64 {
+        let title = if self.title.is_some() { TITLE_SIZE * 1.4 } else { 0.0 };
+        let composer = if self.composer.is_some() { COMPOSER_SIZE * 1.6 } else { 0.0 };
+        if title + composer > 0.0 {
+            title + composer + HEADER_SPACING
+        } else {
+            0.0
+        }
+    }
+
+    fn footer_height(&self) -> f64 {
+        if self.page_numbers {
+            PAGE_NUMBER_SIZE + FOOTER_SPACING
+        } else {
+            0.0
+        }
+    }
+
+    // the space for systems on a page, below the header on the first page
+    fn available_height(&self, is_first_page: bool) -> f64 {
+        self.paper.height - self.margins.top - self.margins.bottom - self.footer_height() - if is_first_page { self.header_height() } else { 0.0 }
+    }
+
+    // the scale that the score is drawn at, so that every system fits on a page
+    // the height of systems does not depend on where the systems are broken, so the layout can be calculated without max width for this
+    pub fn points_per_pixel(&self, layout_metrics: &LayoutMetrics) -> f64 {
+        let band_height = |system: usize| {
+            let (top, bottom) = layout_metrics.system_band(system);
+            (bottom - top).0
+        };
+        (POINTS_PER_PIXEL * layout_metrics.scale()).min(self.available_height(true) / band_height(0)).min(self.available_height(false) / band_height(1))
+    }
+}
+
+pub struct Page {
+    pub systems: Range<usize>,
+    // the distance from the top of the page to the top of the first system
+    pub top: f64,
+}
+
+// puts as many systems on every page as fit
+pub fn paginate(options: &PageOptions, layout_metrics: &LayoutMetrics, points_per_pixel: f64) -> Vec<Page> {
+    let mut pages = Vec::new();
+    let mut system = 0;
+    while system < layout_metrics.system_count() {
+        let is_first_page = pages.is_empty();
+        let available = Pixels(options.available_height(is_first_page) / points_per_pixel);
+        let first_system = system;
+        let band_top = layout_metrics.system_band(first_system).0;
+        // a page always gets at least one system, even if it is too high for it
+        system += 1;
+        while system < layout_metrics.system_count() && layout_metrics.system_band(system).1 - band_top <= available {
+            system += 1;
+        }
+        let top = options.margins.top + if is_first_page { options.header_height() } else { 0.0 };
+        pages.push(Page { systems: first_system..system, top });
+    }
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(source: &str, max_width: f64) -> LayoutMetrics {
+        let polyrhythm = crate::parse::parse(source).unwrap_or_else(|err| panic!("could not parse {source:?}: {}", crate::parse::parse_error_text(&err)));
+        LayoutMetrics::calculate(&polyrhythm, None, Some(Pixels(max_width)), &LayoutOptions::default())
+    }
+
+    fn systems_per_page(pages: &[Page]) -> Vec<usize> {
+        pages.iter().map(|page| page.systems.len()).collect()
+    }
+
+    const LONG_SOURCE: &str = "tempo 4 = 120; {rep 200 4}; {rep 200 4};";
+
+    #[test]
+    fn puts_as_many_systems_on_a_page_as_fit() {
+        let layout_metrics = layout(LONG_SOURCE, 600.0);
+        let options = PageOptions::default();
+        let pages = paginate(&options, &layout_metrics, POINTS_PER_PIXEL);
+
+        // every system is on exactly one page, in order
+        assert_eq!(pages.first().map(|page| page.systems.start), Some(0));
+        assert_eq!(pages.last().map(|page| page.systems.end), Some(layout_metrics.system_count()));
+        for pair in pages.windows(2) {
+            assert_eq!(pair[0].systems.end, pair[1].systems.start);
+        }
+        // and a page is only broken when the next system does not fit
+        for (page_i, page) in pages.iter().enumerate() {
+            let available = options.available_height(page_i == 0) / POINTS_PER_PIXEL;
+            let height = |end: usize| (layout_metrics.system_band(end - 1).1 - layout_metrics.system_band(page.systems.start).0).0;
+            assert!(height(page.systems.end) <= available);
+            if page.systems.end < layout_metrics.system_count() {
+                assert!(height(page.systems.end + 1) > available);
+            }
+            assert_eq!(page.top, options.margins.top);
+        }
+        assert_eq!(systems_per_page(&pages), vec![4, 4, 4, 4, 4]);
+    }
+
+    #[test]
+    fn leaves_room_for_the_header_on_the_first_page() {
+        let layout_metrics = layout(LONG_SOURCE, 600.0);
+        let without_header = paginate(&PageOptions::default(), &layout_metrics, POINTS_PER_PIXEL);
+        let options = PageOptions { title: Some("title".to_string()), composer: Some("composer".to_string()), ..PageOptions::default() };
+        let pages = paginate(&options, &layout_metrics, POINTS_PER_PIXEL);
+        assert_eq!(systems_per_page(&pages), vec![3, 4, 4, 4, 4, 1]);
+        assert_eq!(pages[0].top, options.margins.top + options.header_height());
+        assert_eq!(pages[1].top, options.margins.top);
+        assert_eq!(systems_per_page(&without_header), vec![4, 4, 4, 4, 4]);
+    }
+
+    #[test]
+    fn puts_systems_that_are_too_high_on_their_own_pages() {
+        let layout_metrics = layout(LONG_SOURCE, 600.0);
+        let pages = paginate(&PageOptions::default(), &layout_metrics, 10.0);
+        assert_eq!(systems_per_page(&pages), vec![1; layout_metrics.system_count()]);
+    }
+
+    #[test]
+    fn puts_a_short_score_on_one_page() {
+        let pages = paginate(&PageOptions::default(), &layout("tempo 4 = 120; {4 4 4 4};", 600.0), POINTS_PER_PIXEL);
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].systems, 0..1);
+    }
+
+    #[test]
+    fn scales_the_score_down_to_fit_a_system_on_a_page() {
+        let layout_metrics = layout(LONG_SOURCE, 600.0);
+        assert_eq!(PageOptions::default().points_per_pixel(&layout_metrics), POINTS_PER_PIXEL);
+        let small = PageOptions { paper: PaperSize { width: 200.0, height: 150.0 }, margins: Margins::uniform(10.0), page_numbers: false, ..PageOptions::default() };
+        let points_per_pixel = small.points_per_pixel(&layout_metrics);
+        assert!(points_per_pixel < POINTS_PER_PIXEL);
+        assert!(paginate(&small, &layout_metrics, points_per_pixel).iter().all(|page| (layout_metrics.system_band(page.systems.end - 1).1 - layout_metrics.system_band(page.systems.start).0).0 * points_per_pixel <= 130.0 + 1e-9));
+    }
+
+    #[test]
+    fn checks_that_the_margins_fit_on_the_paper() {
+        assert!(PageOptions::default().is_valid());
+        assert!(PageOptions { margins: Margins::uniform(0.0), ..PageOptions::default() }.is_valid());
+        // wider than the paper
+        assert!(!PageOptions { margins: Margins::uniform(300.0), ..PageOptions::default() }.is_valid());
+        // higher than the paper, with the header and page numbers
+        assert!(!PageOptions { margins: Margins { top: 400.0, right: 10.0, bottom: 430.0, left: 10.0 }, ..PageOptions::default() }.is_valid());
+        let margins = Margins { top: 400.0, right: 10.0, bottom: 400.0, left: 10.0 };
+        assert!(PageOptions { margins, ..PageOptions::default() }.is_valid());
+        assert!(!PageOptions { margins, title: Some("title".to_string()), ..PageOptions::default() }.is_valid());
+        assert!(!PageOptions { margins: Margins::uniform(-10.0), ..PageOptions::default() }.is_valid());
+        assert!(!PageOptions { margins: Margins::uniform(f64::NAN), ..PageOptions::default() }.is_valid());
+        assert!(!PageOptions { paper: PaperSize { width: f64::INFINITY, height: 100.0 }, ..PageOptions::default() }.is_valid());
+    }
+}
@@ -0,0 +1,335 @@
+use std::{cell::RefCell, collections::BTreeSet, fmt::Display};
+
+use pdf_writer::{Content, Name, Pdf, Rect, Ref, Str};
+
+use crate::{
+    analysis::Analysis,
+    drawing::{
+        coord::{Pixels, Point},
+        drawing::{Color, Font, Renderer, TextAlign},
+        draw_score,
+        layout::LayoutMetrics,
+        page::{self, PageOptions},
+        LABEL_TEXT_HEIGHT, STAFF_HEIGHT,
+    },
+    polyrhythm::Polyrhythm,
+    resultant,
+};
+
+// text that is not in the music font is written in Helvetica, which every pdf reader has, so it does not have to be embedded
+const TEXT_FONT_NAME: Name = Name(b"F1");
+
+// the widths of the printable ascii characters in Helvetica in thousandths of the font size, for centering and aligning text
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, 1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833,
+    722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556, 333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556, 556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260,
+    334, 584,
+];
+const DEFAULT_HELVETICA_WIDTH: u16 = 556;
+
+#[derive(Debug)]
+pub enum PdfError {
+    MusicFont(ttf_parser::FaceParsingError),
+    // the margins are negative or larger than the paper
+    Margins,
+}
+
+impl Display for PdfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PdfError::MusicFont(err) => write!(f, "could not read the music font: {err}"),
+            PdfError::Margins => write!(f, "the margins do not leave room for the score on the paper"),
+        }
+    }
+}
+
+// writes the score onto pages, with the glyphs of the music font that are used embedded as form xobjects
+pub fn to_pdf(font: &Font, music_font: &[u8], polyrhythm: &Polyrhythm, analysis: Option<&Analysis>, options: &PageOptions) -> Result<Vec<u8>, PdfError> {
+    if !options.is_valid() {
+        return Err(PdfError::Margins);
+    }
+    let face = ttf_parser::Face::parse(music_font, 0).map_err(PdfError::MusicFont)?;
+
+    let resultant = if polyrhythm.show_resultant { resultant::resultant(polyrhythm).ok() } else { None };
+    let resultant_rhythm = resultant.as_ref().map(|resultant| &resultant.rhythm);
+    let points_per_pixel = options.points_per_pixel(&LayoutMetrics::calculate(polyrhythm, resultant_rhythm, None, &options.layout));
+    // points per pixel are for unscaled pixels, but the max width is for scaled ones
+    let max_width = Pixels(options.content_width() / points_per_pixel * options.layout.scale());
+    let layout_metrics = LayoutMetrics::calculate(polyrhythm, resultant_rhythm, Some(max_width), &options.layout);
+    let pages = page::paginate(options, &layout_metrics, points_per_pixel);
+
+    let glyphs = RefCell::new(BTreeSet::new());
+    let mut page_contents = Vec::new();
+    for (page_i, page) in pages.iter().enumerate() {
+        let band = (layout_metrics.system_band(page.systems.start).0, layout_metrics.system_band(page.systems.end - 1).1);
+        let mut content = Content::new();
+
+        if page_i == 0 {
+            write_header(&mut content, options);
+        }
+        if options.page_numbers {
+            let number = (page_i + 1).to_string();
+            let x = options.paper.width / 2.0 - helvetica_width(&number, page::PAGE_NUMBER_SIZE) / 2.0;
+            show_text(&mut content, &number, [page::PAGE_NUMBER_SIZE, 0.0, 0.0, page::PAGE_NUMBER_SIZE, x, options.margins.bottom]);
+        }
+
+        // the score is drawn in pixels with y going down, with the top of the page's first system at the top of the page's content
+        content.save_state();
+        content.transform([points_per_pixel, 0.0, 0.0, -points_per_pixel, options.margins.left, options.paper.height - page.top + points_per_pixel * band.0 .0].map(|value| value as f32));
+        // everything that belongs to systems on other pages is cut off
+        content.rect((-options.margins.left / points_per_pixel) as f32, band.0 .0 as f32, (options.paper.width / points_per_pixel) as f32, (band.1 - band.0).0 as f32);
+        content.clip_nonzero();
+        content.end_path();
+
+        let renderer = PdfRenderer { face: &face, content: RefCell::new(content), glyphs: &glyphs, band };
+        draw_score(&renderer, &layout_metrics, font, polyrhythm, resultant.as_ref(), analysis, None);
+        let mut content = renderer.content.into_inner();
+        content.restore_state();
+        page_contents.push(content.finish());
+    }
+
+    let mut pdf = Pdf::new();
+    let mut next_id = Ref::new(1);
+    let catalog_id = next_id.bump();
+    let page_tree_id = next_id.bump();
+    let text_font_id = next_id.bump();
+    let page_ids: Vec<_> = page_contents.iter().map(|_| next_id.bump()).collect();
+    let glyph_ids: Vec<_> = glyphs.into_inner().into_iter().map(|glyph| (glyph, next_id.bump())).collect();
+    let glyph_names: Vec<_> = glyph_ids.iter().map(|(glyph, _)| glyph_name(*glyph)).collect();
+
+    pdf.catalog(catalog_id).pages(page_tree_id);
+    pdf.pages(page_tree_id).kids(page_ids.iter().copied()).count(page_ids.len() as i32);
+    for (page_id, page_content) in page_ids.iter().zip(&page_contents) {
+        let content_id = next_id.bump();
+        let mut pdf_page = pdf.page(*page_id);
+        pdf_page.media_box(Rect::new(0.0, 0.0, options.paper.width as f32, options.paper.height as f32)).parent(page_tree_id).contents(content_id);
+        let mut resources = pdf_page.resources();
+        resources.fonts().pair(TEXT_FONT_NAME, text_font_id);
+        resources.x_objects().pairs(glyph_names.iter().zip(&glyph_ids).map(|(name, (_, glyph_id))| (Name(name.as_bytes()), *glyph_id)));
+        drop(resources);
+        drop(pdf_page);
+        pdf.stream(content_id, page_content);
+    }
+
+    pdf.type1_font(text_font_id).base_font(Name(b"Helvetica")).encoding_predefined(Name(b"WinAnsiEncoding"));
+
+    // the glyph outlines are in font units with y going up
+    for (glyph, glyph_id) in &glyph_ids {
+        let mut outline = GlyphOutline { content: Content::new(), current: (0.0, 0.0) };
+        face.outline_glyph(ttf_parser::GlyphId(*glyph), &mut outline);
+        outline.content.fill_nonzero();
+        let bbox = face.glyph_bounding_box(ttf_parser::GlyphId(*glyph)).unwrap_or(face.global_bounding_box());
+        let data = outline.content.finish();
+        pdf.form_xobject(*glyph_id, &data).bbox(Rect::new(bbox.x_min as f32, bbox.y_min as f32, bbox.x_max as f32, bbox.y_max as f32));
+    }
+
+    Ok(pdf.finish())
+}
+
+// the title centered and the composer right aligned below it
+fn write_header(content: &mut Content, options: &PageOptions) {
+    let mut baseline = options.paper.height - options.margins.top;
+    if let Some(title) = &options.title {
+        baseline -= page::TITLE_SIZE;
+        let x = options.margins.left + options.content_width() / 2.0 - helvetica_width(title, page::TITLE_SIZE) / 2.0;
+        show_text(content, title, [page::TITLE_SIZE, 0.0, 0.0, page::TITLE_SIZE, x, baseline]);
+        baseline -= page::TITLE_SIZE * 0.4;
+    }
+    if let Some(composer) = &options.composer {
+        baseline -= page::COMPOSER_SIZE * 1.6;
+        let x = options.paper.width - options.margins.right - helvetica_width(composer, page::COMPOSER_SIZE);
+        show_text(content, composer, [page::COMPOSER_SIZE, 0.0, 0.0, page::COMPOSER_SIZE, x, baseline]);
+    }
+}
+
+fn show_text(content: &mut Content, text: &str, matrix: [f64; 6]) {
+    content.begin_text();
+    content.set_font(TEXT_FONT_NAME, 1.0);
+    content.set_text_matrix(matrix.map(|value| value as f32));
+    content.show(Str(&win_ansi(text)));
+    content.end_text();
+}
+
+fn helvetica_width(text: &str, size: f64) -> f64 {
+    text.chars().map(|c| HELVETICA_WIDTHS.get((c as usize).wrapping_sub(32)).copied().unwrap_or(DEFAULT_HELVETICA_WIDTH) as f64).sum::<f64>() * size / 1000.0
+}
+
+// winansi has the same characters as latin-1 for everything except some punctuation, and other characters cannot be written with it
+fn win_ansi(text: &str) -> Vec<u8> {
+    text.chars().map(|c| if (' '..='~').contains(&c) || ('\u{a0}'..='\u{ff}').contains(&c) { c as u8 } else { b'?' }).collect()
+}
+
+fn glyph_name(glyph: u16) -> String {
+    format!("G{glyph}")
+}
+
+struct PdfRenderer<'a> {
+    face: &'a ttf_parser::Face<'a>,
+    content: RefCell<Content>,
+    glyphs: &'a RefCell<BTreeSet<u16>>,
+    // the part of the score that is on the page, since anything outside of it does not have to be written
+    band: (Pixels, Pixels),
+}
+
+impl PdfRenderer<'_> {
+    fn is_visible(&self, ys: &[Pixels], extent: Pixels) -> bool {
+        ys.iter().any(|y| *y + extent >= self.band.0 && *y - extent <= self.band.1)
+    }
+
+    // ordinary characters are written in the text font where the music font does not have them, like a browser falls back to another font
+    fn write_text(&self, text: &str, pos: Point<Pixels>, align: TextAlign, size: Pixels, use_music_font: bool) {
+        if !self.is_visible(&[pos.y], size) {
+            return;
+        }
+
+        let scale = size.0 / self.face.units_per_em() as f64;
+        let pieces: Vec<(Option<ttf_parser::GlyphId>, char, f64)> = text
+            .chars()
+            .map(|c| match self.face.glyph_index(c).filter(|_| use_music_font) {
+                Some(glyph) => (Some(glyph), c, self.face.glyph_hor_advance(glyph).unwrap_or(0) as f64 * scale),
+                None => (None, c, helvetica_width(&c.to_string(), size.0)),
+            })
+            .collect();
+        let mut x = match align {
+            TextAlign::Start => pos.x.0,
+            TextAlign::Center => pos.x.0 - pieces.iter().map(|(_, _, width)| width).sum::<f64>() / 2.0,
+        };
+
+        let mut content = self.content.borrow_mut();
+        for (glyph, c, width) in pieces {
+            match glyph {
+                Some(glyph) => {
+                    self.glyphs.borrow_mut().insert(glyph.0);
+                    content.save_state();
+                    // the glyphs have y going up, but the score has y going down
+                    content.transform([scale, 0.0, 0.0, -scale, x, pos.y.0].map(|value| value as f32));
+                    content.x_object(Name(glyph_name(glyph.0).as_bytes()));
+                    content.restore_state();
+                }
+                None => show_text(&mut content, &c.to_string(), [size.0, 0.0, 0.0, -size.0, x, pos.y.0]),
+            }
+            x += width;
+        }
+    }
+}
+
+impl Renderer for PdfRenderer<'_> {
+    fn line(&self, p1: Point<Pixels>, p2: Point<Pixels>, color: Color, thickness: Pixels) {
+        if !self.is_visible(&[p1.y, p2.y], thickness) {
+            return;
+        }
+        let mut content = self.content.borrow_mut();
+        content.set_stroke_rgb(color.red as f32 / 255.0, color.green as f32 / 255.0, color.blue as f32 / 255.0);
+        content.set_line_width(thickness.0 as f32);
+        content.move_to(p1.x.0 as f32, p1.y.0 as f32);
+        content.line_to(p2.x.0 as f32, p2.y.0 as f32);
+        content.stroke();
+    }
+
+    fn bezier(&self, start: Point<Pixels>, cp1: Point<Pixels>, cp2: Point<Pixels>, end: Point<Pixels>, color: Color, thickness: Pixels) {
+        if !self.is_visible(&[start.y, cp1.y, cp2.y, end.y], thickness) {
+            return;
+        }
+        let mut content = self.content.borrow_mut();
+        content.set_stroke_rgb(color.red as f32 / 255.0, color.green as f32 / 255.0, color.blue as f32 / 255.0);
+        content.set_line_width(thickness.0 as f32);
+        content.move_to(start.x.0 as f32, start.y.0 as f32);
+        content.cubic_to(cp1.x.0 as f32, cp1.y.0 as f32, cp2.x.0 as f32, cp2.y.0 as f32, end.x.0 as f32, end.y.0 as f32);
+        content.stroke();
+    }
+
+    fn set_fill_color(&self, color: Color) {
+        self.content.borrow_mut().set_fill_rgb(color.red as f32 / 255.0, color.green as f32 / 255.0, color.blue as f32 / 255.0);
+    }
+
+    fn fill_text(&self, _font: &Font, text: &str, pos: Point<Pixels>, align: TextAlign) {
+        self.write_text(text, pos, align, STAFF_HEIGHT, true);
+    }
+
+    fn fill_label(&self, _font: &Font, text: &str, pos: Point<Pixels>) {
+        self.write_text(text, pos, TextAlign::Start, LABEL_TEXT_HEIGHT, false);
+    }
+}
+
+struct GlyphOutline {
+    content: Content,
+    // quadratic curves have to be turned into cubic ones, which needs the point they start at
+    current: (f32, f32),
+}
+
+impl ttf_parser::OutlineBuilder for GlyphOutline {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.content.move_to(x, y);
+        self.current = (x, y);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.content.line_to(x, y);
+        self.current = (x, y);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (x0, y0) = self.current;
+        self.content.cubic_to(x0 + 2.0 / 3.0 * (x1 - x0), y0 + 2.0 / 3.0 * (y1 - y0), x + 2.0 / 3.0 * (x1 - x), y + 2.0 / 3.0 * (y1 - y), x, y);
+        self.current = (x, y);
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        self.content.cubic_to(x1, y1, x2, y2, x, y);
+        self.current = (x, y);
+    }
+
+    fn close(&mut self) {
+        self.content.close_path();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drawing::page::{Margins, PaperSize};
+
+    // the small font that the golden tests draw with
+    const FONT_METADATA: &str = include_str!("../../tests/fonts/test_music_font_metadata.json");
+    const MUSIC_FONT: &[u8] = include_bytes!("../../tests/fonts/test_music_font.ttf");
+
+    fn pdf(source: &str, options: &PageOptions) -> Result<Vec<u8>, PdfError> {
+        let polyrhythm = crate::parse::parse(source).unwrap_or_else(|err| panic!("could not parse {source:?}: {}", crate::parse::parse_error_text(&err)));
+        let font = Font::from_metadata(FONT_METADATA).expect("could not read the test font metadata");
+        to_pdf(&font, MUSIC_FONT, &polyrhythm, crate::analysis::analyze(&polyrhythm).as_ref(), options)
+    }
+
+    fn count(pdf: &[u8], pattern: &[u8]) -> usize {
+        pdf.windows(pattern.len()).filter(|window| *window == pattern).count()
+    }
+
+    #[test]
+    fn writes_a_pdf_with_a_page_for_every_page_of_systems() {
+        let options = PageOptions { title: Some("title".to_string()), composer: Some("composer".to_string()), ..PageOptions::default() };
+        let pdf = pdf("tempo 4 = 120; {rep 200 4}; {rep 200 4} approx {rep 200 4};", &options).expect("could not write pdf");
+        assert!(pdf.starts_with(b"%PDF-"));
+        assert!(pdf.trim_ascii_end().ends_with(b"%%EOF"));
+        // three systems of three staves fit on every page
+        assert_eq!(count(&pdf, b"/Type /Page\n"), 8);
+        assert_eq!(count(&pdf, b"/Count 8\n"), 1);
+        // the glyphs are embedded, and the header is written in helvetica
+        assert!(count(&pdf, b"/Subtype /Form") > 0);
+        assert_eq!(count(&pdf, b"/BaseFont /Helvetica"), 1);
+        assert!(count(&pdf, b"(title)") == 1 && count(&pdf, b"(composer)") == 1);
+    }
+
+    #[test]
+    fn does_not_write_a_pdf_when_the_margins_do_not_fit() {
+        let options = PageOptions { margins: Margins::uniform(400.0), ..PageOptions::default() };
+        assert!(matches!(pdf("tempo 4 = 120; {4 4};", &options), Err(PdfError::Margins)));
+        let options = PageOptions { paper: PaperSize { width: 100.0, height: 100.0 }, margins: Margins::uniform(-1.0), ..PageOptions::default() };
+        assert!(matches!(pdf("tempo 4 = 120; {4 4};", &options), Err(PdfError::Margins)));
+    }
+
+    #[test]
+    fn does_not_write_a_pdf_without_a_music_font() {
+        let polyrhythm = crate::parse::parse("tempo 4 = 120; {4 4};").ok().expect("could not parse");
+        let font = Font::from_metadata(FONT_METADATA).expect("could not read the test font metadata");
+        assert!(matches!(to_pdf(&font, b"not a font", &polyrhythm, None, &PageOptions::default()), Err(PdfError::MusicFont(_))));
+    }
+}
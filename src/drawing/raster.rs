@@ -0,0 +1,191 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Display,
+};
+
+use tiny_skia::{FillRule, Paint, PathBuilder, Pixmap, Stroke, Transform};
+
+use crate::{
+    analysis::Analysis,
+    drawing::{
+        coord::{Pixels, Point},
+        drawing::{Color, Font, Renderer, TextAlign},
+        draw_score,
+        layout::{LayoutMetrics, LayoutOptions},
+        LABEL_TEXT_HEIGHT, STAFF_HEIGHT,
+    },
+    polyrhythm::Polyrhythm,
+    resultant,
+};
+
+pub struct RasterOptions {
+    // breaks the rhythms into systems like the canvas does
+    pub max_width: Option<f64>,
+    // image pixels per layout pixel, like the device pixel ratio of a screen
+    pub scale: f64,
+    // otherwise the background is white
+    pub transparent: bool,
+    // the max width is for the zoomed layout, like on the canvas
+    pub layout: LayoutOptions,
+}
+
+impl Default for RasterOptions {
+    fn default() -> Self {
+        RasterOptions { max_width: None, scale: 1.0, transparent: false, layout: LayoutOptions::default() }
+    }
+}
+
+#[derive(Debug)]
+pub enum RasterError {
+    MusicFont(ttf_parser::FaceParsingError),
+    TextFont(ttf_parser::FaceParsingError),
+    // the layout is empty or too large for an image
+    Size { width: u32, height: u32 },
+    Encoding(String),
+}
+
+impl Display for RasterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RasterError::MusicFont(err) => write!(f, "could not read the music font: {err}"),
+            RasterError::TextFont(err) => write!(f, "could not read the text font: {err}"),
+            RasterError::Size { width, height } => write!(f, "cannot make an image of {width}x{height} pixels"),
+            RasterError::Encoding(err) => write!(f, "could not encode png: {err}"),
+        }
+    }
+}
+
+// draws the score into a png with the glyph outlines of the music font, so that it can be done without a browser
+// text that the music font does not have is written with the text font, and left out without one
+pub fn to_png(font: &Font, music_font: &[u8], text_font: Option<&[u8]>, polyrhythm: &Polyrhythm, analysis: Option<&Analysis>, options: &RasterOptions) -> Result<Vec<u8>, RasterError> {
+    let music_face = ttf_parser::Face::parse(music_font, 0).map_err(RasterError::MusicFont)?;
+    let text_face = text_font.map(|text_font| ttf_parser::Face::parse(text_font, 0)).transpose().map_err(RasterError::TextFont)?;
+
+    let resultant = if polyrhythm.show_resultant { resultant::resultant(polyrhythm).ok() } else { None };
+    let layout_metrics = LayoutMetrics::calculate(polyrhythm, resultant.as_ref().map(|resultant| &resultant.rhythm), options.max_width.map(Pixels), &options.layout);
+
+    let scale = options.scale * layout_metrics.scale();
+    let width = (layout_metrics.canvas_width().0 * scale).ceil() as u32;
+    let height = (layout_metrics.canvas_height().0 * scale).ceil() as u32;
+    let mut pixmap = Pixmap::new(width, height).ok_or(RasterError::Size { width, height })?;
+    if !options.transparent {
+        pixmap.fill(tiny_skia::Color::WHITE);
+    }
+
+    let renderer = RasterRenderer { music_face: &music_face, text_face: text_face.as_ref(), pixmap: RefCell::new(pixmap), fill_color: Cell::new(Color::BLACK), transform: Transform::from_scale(scale as f32, scale as f32) };
+    draw_score(&renderer, &layout_metrics, font, polyrhythm, resultant.as_ref(), analysis, None);
+    renderer.pixmap.into_inner().encode_png().map_err(|err| RasterError::Encoding(err.to_string()))
+}
+
+fn to_skia_color(color: Color) -> tiny_skia::Color {
+    tiny_skia::Color::from_rgba8(color.red, color.green, color.blue, 255)
+}
+
+fn paint(color: Color) -> Paint<'static> {
+    let mut paint = Paint::default();
+    paint.set_color(to_skia_color(color));
+    paint.anti_alias = true;
+    paint
+}
+
+struct RasterRenderer<'a> {
+    music_face: &'a ttf_parser::Face<'a>,
+    text_face: Option<&'a ttf_parser::Face<'a>>,
+    pixmap: RefCell<Pixmap>,
+    // like a canvas, text is filled with the last color that was set
+    fill_color: Cell<Color>,
+    // from layout pixels to image pixels
+    transform: Transform,
+}
+
+impl RasterRenderer<'_> {
+    fn stroke(&self, path: PathBuilder, color: Color, thickness: Pixels) {
+        if let Some(path) = path.finish() {
+            let stroke = Stroke { width: thickness.0 as f32, ..Stroke::default() };
+            self.pixmap.borrow_mut().stroke_path(&path, &paint(color), &stroke, self.transform, None);
+        }
+    }
+
+    // every character is looked up in the music font first, like the canvas falls back to other fonts for characters the music font does not have
+    fn write_text(&self, text: &str, pos: Point<Pixels>, align: TextAlign, size: Pixels, use_music_font: bool) {
+        let pieces: Vec<(&ttf_parser::Face, ttf_parser::GlyphId, f64)> = text
+            .chars()
+            .filter_map(|c| {
+                let face = [Some(self.music_face).filter(|_| use_music_font), self.text_face].into_iter().flatten().find(|face| face.glyph_index(c).is_some())?;
+                let glyph = face.glyph_index(c)?;
+                let scale = size.0 / face.units_per_em() as f64;
+                Some((face, glyph, face.glyph_hor_advance(glyph).unwrap_or(0) as f64 * scale))
+            })
+            .collect();
+        let mut x = match align {
+            TextAlign::Start => pos.x.0,
+            TextAlign::Center => pos.x.0 - pieces.iter().map(|(_, _, width)| width).sum::<f64>() / 2.0,
+        };
+
+        let paint = paint(self.fill_color.get());
+        let mut pixmap = self.pixmap.borrow_mut();
+        for (face, glyph, width) in pieces {
+            let mut outline = GlyphOutline(PathBuilder::new());
+            face.outline_glyph(glyph, &mut outline);
+            if let Some(path) = outline.0.finish() {
+                // the glyphs have y going up, but the score has y going down
+                let scale = (size.0 / face.units_per_em() as f64) as f32;
+                let transform = self.transform.pre_concat(Transform::from_row(scale, 0.0, 0.0, -scale, x as f32, pos.y.0 as f32));
+                pixmap.fill_path(&path, &paint, FillRule::Winding, transform, None);
+            }
+            x += width;
+        }
+    }
+}
+
+impl Renderer for RasterRenderer<'_> {
+    fn line(&self, p1: Point<Pixels>, p2: Point<Pixels>, color: Color, thickness: Pixels) {
+        let mut path = PathBuilder::new();
+        path.move_to(p1.x.0 as f32, p1.y.0 as f32);
+        path.line_to(p2.x.0 as f32, p2.y.0 as f32);
+        self.stroke(path, color, thickness);
+    }
+
+    fn bezier(&self, start: Point<Pixels>, cp1: Point<Pixels>, cp2: Point<Pixels>, end: Point<Pixels>, color: Color, thickness: Pixels) {
+        let mut path = PathBuilder::new();
+        path.move_to(start.x.0 as f32, start.y.0 as f32);
+        path.cubic_to(cp1.x.0 as f32, cp1.y.0 as f32, cp2.x.0 as f32, cp2.y.0 as f32, end.x.0 as f32, end.y.0 as f32);
+        self.stroke(path, color, thickness);
+    }
+
+    fn set_fill_color(&self, color: Color) {
+        self.fill_color.set(color);
+    }
+
+    fn fill_text(&self, _font: &Font, text: &str, pos: Point<Pixels>, align: TextAlign) {
+        self.write_text(text, pos, align, STAFF_HEIGHT, true);
+    }
+
+    fn fill_label(&self, _font: &Font, text: &str, pos: Point<Pixels>) {
+        self.write_text(text, pos, TextAlign::Start, LABEL_TEXT_HEIGHT, false);
+    }
+}
+
+struct GlyphOutline(PathBuilder);
+
+impl ttf_parser::OutlineBuilder for GlyphOutline {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.0.move_to(x, y);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.0.line_to(x, y);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        self.0.quad_to(x1, y1, x, y);
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        self.0.cubic_to(x1, y1, x2, y2, x, y);
+    }
+
+    fn close(&mut self) {
+        self.0.close();
+    }
+}
@@ -0,0 +1,304 @@
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+use wasm_bindgen::{closure::Closure, prelude::wasm_bindgen, JsCast, JsValue};
+use web_sys::{js_sys::Error, Document, Element, HtmlCanvasElement, HtmlTextAreaElement, MouseEvent};
+
+use crate::{analysis, drawing, parse};
+
+// an editor made of a textarea, a canvas that shows the polyrhythm written in the textarea and a box for parse errors
+// every editor has its own elements, font and listeners, so there can be any number of them on a page
+// clicking a note selects the code it was written with, and the notes written in the selected code are highlighted
+// hovering over an approximated note shows how far it is from the original
+// the rhythms are broken into systems that fit the width of the canvas's parent element, unless a fixed width is set
+// it can be zoomed, or zoomed so that the whole score fits the width of the canvas's parent element on one system
+#[wasm_bindgen]
+pub struct PolyrhythmEditor {
+    state: Rc<EditorState>,
+    // every listener is removed when the editor is freed, since the document and window outlive the editor's own elements and the elements can outlive the editor
+    input_listener: Closure<dyn Fn()>,
+    click_listener: Closure<dyn Fn(MouseEvent)>,
+    mousemove_listener: Closure<dyn Fn(MouseEvent)>,
+    selection_listener: Closure<dyn Fn()>,
+    resize_listener: Closure<dyn Fn()>,
+}
+
+struct EditorState {
+    document: Document,
+    codebox: HtmlTextAreaElement,
+    canvas: HtmlCanvasElement,
+    errors: Element,
+    analysis: Option<Element>,
+    font: drawing::Font,
+    drawn_notes: RefCell<Vec<drawing::DrawnNote>>,
+    // the selected byte range of the code while the textarea has focus
+    selection: Cell<Option<(usize, usize)>>,
+    wrap_width: Cell<Option<f64>>,
+    zoom: Cell<Zoom>,
+    // the zoom that the score was last drawn at, which is not known beforehand when fitting to width
+    drawn_zoom: Cell<f64>,
+}
+
+#[derive(Copy, Clone)]
+enum Zoom {
+    Fixed(f64),
+    FitToWidth,
+}
+
+const ZOOM_STEP: f64 = 1.25;
+const MIN_ZOOM: f64 = 0.25;
+const MAX_ZOOM: f64 = 4.0;
+
+#[wasm_bindgen]
+impl PolyrhythmEditor {
+    // the editor redraws whenever the textarea changes, and also draws whatever is already in it
+    // if analysis is given, the cycle and coincidence analysis is written into it
+    pub async fn create(codebox: HtmlTextAreaElement, canvas: HtmlCanvasElement, errors: Element, analysis: Option<Element>, font_metadata_url: Option<String>) -> PolyrhythmEditor {
+        let window = web_sys::window().expect("global window does not exists");
+        let document = window.document().expect("expecting a document on window");
+        let font = drawing::Font::load_bravura(&window, font_metadata_url.as_deref().unwrap_or(drawing::BRAVURA_METADATA_URL)).await;
+
+        let state = Rc::new(EditorState { document, codebox, canvas, errors, analysis, font, drawn_notes: RefCell::new(Vec::new()), selection: Cell::new(None), wrap_width: Cell::new(None), zoom: Cell::new(Zoom::Fixed(1.0)), drawn_zoom: Cell::new(1.0) });
+
+        let input_listener = Closure::<dyn Fn()>::new({
+            let state = state.clone();
+            move || state.redraw()
+        });
+        state.codebox.add_event_listener_with_callback("input", input_listener.as_ref().unchecked_ref()).expect("could not add event listener on code box input");
+
+        let click_listener = Closure::<dyn Fn(MouseEvent)>::new({
+            let state = state.clone();
+            move |event: MouseEvent| state.select_note_at(event)
+        });
+        state.canvas.add_event_listener_with_callback("click", click_listener.as_ref().unchecked_ref()).expect("could not add event listener on canvas click");
+
+        let mousemove_listener = Closure::<dyn Fn(MouseEvent)>::new({
+            let state = state.clone();
+            move |event: MouseEvent| state.show_deviation_at(event)
+        });
+        state.canvas.add_event_listener_with_callback("mousemove", mousemove_listener.as_ref().unchecked_ref()).expect("could not add event listener on canvas mouse move");
+
+        // textareas do not reliably fire selectionchange themselves, so every editor listens on the document and checks whether its textarea has focus
+        let selection_listener = Closure::<dyn Fn()>::new({
+            let state = state.clone();
+            move || state.update_selection()
+        });
+        state
+            .document
+            .add_event_listener_with_callback("selectionchange", selection_listener.as_ref().unchecked_ref())
+            .expect("could not add event listener on document selection change");
+
+        let resize_listener = Closure::<dyn Fn()>::new({
+            let state = state.clone();
+            move || state.redraw()
+        });
+        window.add_event_listener_with_callback("resize", resize_listener.as_ref().unchecked_ref()).expect("could not add event listener on window resize");
+
+        state.redraw();
+        PolyrhythmEditor { state, input_listener, click_listener, mousemove_listener, selection_listener, resize_listener }
+    }
+
+    pub fn redraw(&self) {
+        self.state.redraw();
+    }
+
+    // the width in pixels to break the rhythms into systems at, which can be Infinity to never break them
+    // without a width, the width of the canvas's parent element is used
+    #[wasm_bindgen(js_name = setWrapWidth)]
+    pub fn set_wrap_width(&self, width: Option<f64>) {
+        self.state.wrap_width.set(width);
+        self.state.redraw();
+    }
+
+    // the zoom is how big everything is drawn compared to the default size
+    // it is clamped to the zooms the editor supports, but NaN and infinities cannot be clamped and throw
+    #[wasm_bindgen(js_name = setZoom)]
+    pub fn set_zoom(&self, zoom: f64) -> Result<(), JsValue> {
+        if !zoom.is_finite() {
+            return Err(Error::new(&format!("invalid zoom: {zoom}")).into());
+        }
+        self.state.set_fixed_zoom(zoom);
+        Ok(())
+    }
+
+    // zooming in or out from fit to width starts at the zoom that fit
+    #[wasm_bindgen(js_name = zoomIn)]
+    pub fn zoom_in(&self) {
+        self.state.set_fixed_zoom(self.zoom() * ZOOM_STEP);
+    }
+
+    #[wasm_bindgen(js_name = zoomOut)]
+    pub fn zoom_out(&self) {
+        self.state.set_fixed_zoom(self.zoom() / ZOOM_STEP);
+    }
+
+    // the whole score is drawn on one system, scaled to the width of the canvas's parent element, until the zoom is set again
+    #[wasm_bindgen(js_name = fitToWidth)]
+    pub fn fit_to_width(&self) {
+        self.state.zoom.set(Zoom::FitToWidth);
+        self.state.redraw();
+    }
+
+    // the zoom that the score was last drawn at
+    pub fn zoom(&self) -> f64 {
+        self.state.drawn_zoom.get()
+    }
+
+    pub fn source(&self) -> String {
+        self.state.codebox.value()
+    }
+
+    #[wasm_bindgen(js_name = setSource)]
+    pub fn set_source(&self, source: &str) {
+        self.state.codebox.set_value(source);
+        self.state.redraw();
+    }
+}
+
+impl Drop for PolyrhythmEditor {
+    fn drop(&mut self) {
+        self.state.codebox.remove_event_listener_with_callback("input", self.input_listener.as_ref().unchecked_ref()).expect("could not remove event listener on code box input");
+        self.state.canvas.remove_event_listener_with_callback("click", self.click_listener.as_ref().unchecked_ref()).expect("could not remove event listener on canvas click");
+        self.state.canvas.remove_event_listener_with_callback("mousemove", self.mousemove_listener.as_ref().unchecked_ref()).expect("could not remove event listener on canvas mouse move");
+        self.state
+            .document
+            .remove_event_listener_with_callback("selectionchange", self.selection_listener.as_ref().unchecked_ref())
+            .expect("could not remove event listener on document selection change");
+        if let Some(window) = web_sys::window() {
+            window.remove_event_listener_with_callback("resize", self.resize_listener.as_ref().unchecked_ref()).expect("could not remove event listener on window resize");
+        }
+    }
+}
+
+impl EditorState {
+    fn set_fixed_zoom(&self, zoom: f64) {
+        self.zoom.set(Zoom::Fixed(zoom.clamp(MIN_ZOOM, MAX_ZOOM)));
+        self.redraw();
+    }
+
+    fn redraw(&self) {
+        let code = self.codebox.value();
+        let parsed = parse::parse(&code);
+        match parsed {
+            Ok(polyrhythm) => {
+                let polyrhythm_analysis = analysis::analyze(&polyrhythm);
+                let (zoom, wrap_width) = match self.zoom.get() {
+                    Zoom::Fixed(zoom) => (zoom, self.wrap_width()),
+                    Zoom::FitToWidth => (self.parent_width().map(|width| drawing::zoom_to_fit(&polyrhythm, width).clamp(MIN_ZOOM, MAX_ZOOM)).unwrap_or(1.0), None),
+                };
+                *self.drawn_notes.borrow_mut() = drawing::draw(&self.canvas, &self.font, &polyrhythm, polyrhythm_analysis.as_ref(), self.selection.get(), wrap_width, &drawing::LayoutOptions::zoomed(zoom));
+                self.drawn_zoom.set(zoom);
+                self.errors.replace_children_with_node(&web_sys::js_sys::Array::new());
+                if let Some(analysis) = &self.analysis {
+                    analysis.set_text_content(polyrhythm_analysis.map(|a| a.to_string()).as_deref());
+                }
+            }
+            Err(err) => {
+                self.errors.replace_children_with_node_1(&parse::parse_error_to_div(&self.document, err).into());
+            }
+        }
+    }
+
+    fn wrap_width(&self) -> Option<f64> {
+        self.wrap_width.get().or_else(|| self.parent_width())
+    }
+
+    fn parent_width(&self) -> Option<f64> {
+        self.canvas.parent_element().map(|parent| parent.client_width() as f64).filter(|width| *width > 0.0)
+    }
+
+    // the canvas can be scaled by css, so the mouse position is converted to canvas pixels and then to the unscaled pixels that the notes were laid out in
+    fn canvas_position(&self, event: &MouseEvent) -> (f64, f64) {
+        let css_scale = if self.canvas.client_width() > 0 { self.canvas.width() as f64 / self.canvas.client_width() as f64 } else { 1.0 };
+        let device_pixel_ratio = web_sys::window().map(|window| window.device_pixel_ratio()).unwrap_or(1.0);
+        let scale = css_scale / (self.drawn_zoom.get() * device_pixel_ratio);
+        (event.offset_x() as f64 * scale, event.offset_y() as f64 * scale)
+    }
+
+    fn select_note_at(&self, event: MouseEvent) {
+        let (x, y) = self.canvas_position(&event);
+        let span = drawing::note_at(&self.drawn_notes.borrow(), x, y).and_then(|note| note.span());
+        if let Some((start, end)) = span {
+            let code = self.codebox.value();
+            self.codebox.focus().expect("could not focus code box");
+            self.codebox.set_selection_range(utf16_offset(&code, start), utf16_offset(&code, end)).expect("could not set code box selection");
+            // the selectionchange event comes later, but the note should be highlighted right away
+            self.update_selection();
+        }
+    }
+
+    // the deviation is shown as the tooltip of the whole canvas, which changes as the mouse moves over it
+    fn show_deviation_at(&self, event: MouseEvent) {
+        let (x, y) = self.canvas_position(&event);
+        let drawn_notes = self.drawn_notes.borrow();
+        self.canvas.set_title(drawing::note_at(&drawn_notes, x, y).and_then(|note| note.deviation_text()).unwrap_or(""));
+    }
+
+    fn update_selection(&self) {
+        let has_focus = self.codebox.is_same_node(self.document.active_element().as_deref());
+        let selection = if has_focus {
+            let code = self.codebox.value();
+            match (self.codebox.selection_start(), self.codebox.selection_end()) {
+                (Ok(Some(start)), Ok(Some(end))) => Some((byte_offset(&code, start), byte_offset(&code, end))),
+                _ => None,
+            }
+        } else {
+            None
+        };
+        let previous = self.selection.replace(selection);
+        // most selection changes, like moving the cursor within a note, do not change which notes are highlighted, so there is nothing to redraw
+        let highlight_changed = selection != previous && self.drawn_notes.borrow().iter().any(|note| note.is_highlighted(selection) != note.is_highlighted(previous));
+        if highlight_changed {
+            self.redraw();
+        }
+    }
+}
+
+// the parser works with byte offsets, but textarea selections are in utf-16 code units
+fn utf16_offset(code: &str, byte_offset: usize) -> u32 {
+    code[..byte_offset].encode_utf16().count() as u32
+}
+
+fn byte_offset(code: &str, utf16_offset: u32) -> usize {
+    let mut units = 0;
+    for (byte_i, c) in code.char_indices() {
+        if units >= utf16_offset as usize {
+            return byte_i;
+        }
+        units += c.len_utf16();
+    }
+    code.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // é is 2 bytes and 1 utf-16 unit, ♩ is 3 bytes and 1 unit, and the g clef is 4 bytes and 2 units
+    const CODE: &str = "\"é♩\" 4 \"\u{1D11E}\" 8;";
+    const G_CLEF: char = '\u{1D11E}';
+
+    #[test]
+    fn converts_byte_offsets_to_utf16() {
+        assert_eq!(utf16_offset(CODE, 0), 0);
+        assert_eq!(utf16_offset(CODE, 1), 1);
+        assert_eq!(utf16_offset(CODE, 3), 2);
+        assert_eq!(utf16_offset(CODE, 6), 3);
+        assert_eq!(utf16_offset(CODE, CODE.find('4').unwrap()), 5);
+        assert_eq!(utf16_offset(CODE, CODE.find('8').unwrap()), 12);
+        assert_eq!(utf16_offset(CODE, CODE.len()), 14);
+    }
+
+    #[test]
+    fn converts_utf16_offsets_to_bytes() {
+        for (byte_i, _) in CODE.char_indices().chain([(CODE.len(), ' ')]) {
+            assert_eq!(byte_offset(CODE, utf16_offset(CODE, byte_i)), byte_i);
+        }
+        // an offset in the middle of a surrogate pair is rounded up to the end of the character
+        let clef = CODE.find(G_CLEF).unwrap();
+        assert_eq!(byte_offset(CODE, utf16_offset(CODE, clef) + 1), clef + G_CLEF.len_utf8());
+        assert_eq!(byte_offset(CODE, 1000), CODE.len());
+    }
+}
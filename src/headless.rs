@@ -0,0 +1,13 @@
+// drawing without a browser, for the command line and anything else that runs natively
+
+pub use crate::drawing::{LayoutOptions, RasterOptions};
+use crate::{analysis, drawing, parse};
+
+// like the javascript api, this takes the source code of a polyrhythm and returns the parse error message if it does not parse
+// font_metadata is the contents of bravura_metadata.json, and music_font and text_font are otf or ttf files
+pub fn to_png(source: &str, font_metadata: &str, music_font: &[u8], text_font: Option<&[u8]>, options: &RasterOptions) -> Result<Vec<u8>, String> {
+    let polyrhythm = parse::parse(source).map_err(|err| parse::parse_error_text(&err))?;
+    let font = drawing::Font::from_metadata(font_metadata).map_err(|err| format!("could not read font metadata: {err}"))?;
+    let polyrhythm_analysis = analysis::analyze(&polyrhythm);
+    drawing::to_png(&font, music_font, text_font, &polyrhythm, polyrhythm_analysis.as_ref(), options).map_err(|err| err.to_string())
+}
@@ -0,0 +1,183 @@
+use std::fmt::Display;
+
+use crate::{polyrhythm::Original, time::Time, units::Seconds};
+
+pub enum ImportError {
+    InvalidCsvValue { line: usize, value: String },
+    InvalidMidi(midly::Error),
+    InvalidMidiTiming,
+    NoSuchTrack(usize),
+    NoOnsets,
+}
+
+// reads onset times in seconds from the first column of a csv file (which can also just be one time per line), skipping a header row if there is one
+pub fn onsets_from_csv(text: &str) -> Result<Vec<Time<Seconds>>, ImportError> {
+    let mut onsets = Vec::new();
+    for (line_i, line) in text.lines().enumerate() {
+        let value = line.split([',', ';', '\t']).next().unwrap_or("").trim();
+        if value.is_empty() {
+            continue;
+        }
+
+        match value.parse::<f64>() {
+            Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => onsets.push(Time(Seconds::from_f64_rounded(seconds))),
+            _ if line_i == 0 => {}
+            _ => return Err(ImportError::InvalidCsvValue { line: line_i + 1, value: value.to_string() }),
+        }
+    }
+
+    relative_to_first_onset(onsets)
+}
+
+// reads the times of the note on events in a track of a midi file
+// if no track is given, the first track that has any notes is used
+pub fn onsets_from_midi(bytes: &[u8], track: Option<usize>) -> Result<Vec<Time<Seconds>>, ImportError> {
+    let smf = midly::Smf::parse(bytes).map_err(ImportError::InvalidMidi)?;
+    // ticks are divided by these, so a file with 0 of them has no times at all
+    let is_valid_timing = match smf.header.timing {
+        midly::Timing::Metrical(ticks_per_beat) => ticks_per_beat.as_int() > 0,
+        midly::Timing::Timecode(_, subframes) => subframes > 0,
+    };
+    if !is_valid_timing {
+        return Err(ImportError::InvalidMidiTiming);
+    }
+
+    fn absolute_ticks<'a, 'b>(track: &'b [midly::TrackEvent<'a>]) -> impl Iterator<Item = (u64, &'b midly::TrackEventKind<'a>)> {
+        track.iter().scan(0, |tick, event| {
+            *tick += event.delta.as_int() as u64;
+            Some((*tick, &event.kind))
+        })
+    }
+    fn is_onset(kind: &midly::TrackEventKind) -> bool {
+        // by convention, a note on with a velocity of 0 is a note off
+        matches!(kind, midly::TrackEventKind::Midi { channel: _, message: midly::MidiMessage::NoteOn { key: _, vel } } if vel.as_int() > 0)
+    }
+
+    // tempo changes usually only appear in the first track, but they apply to every track
+    let mut tempo_changes: Vec<(u64, u32)> = smf
+        .tracks
+        .iter()
+        .flat_map(|track| absolute_ticks(track))
+        .filter_map(|(tick, kind)| match kind {
+            midly::TrackEventKind::Meta(midly::MetaMessage::Tempo(micros_per_beat)) => Some((tick, micros_per_beat.as_int())),
+            _ => None,
+        })
+        .collect();
+    tempo_changes.sort_by_key(|(tick, _)| *tick);
+
+    let tick_to_seconds = |tick: u64| match smf.header.timing {
+        midly::Timing::Metrical(ticks_per_beat) => {
+            let ticks_per_beat = ticks_per_beat.as_int() as f64;
+            let mut seconds = 0.0;
+            let mut last_tick = 0;
+            let mut micros_per_beat = 500_000.0; // 120 bpm, which is what midi files use if there is no tempo change
+            for &(change_tick, change_micros_per_beat) in tempo_changes.iter().take_while(|(change_tick, _)| *change_tick < tick) {
+                seconds += (change_tick - last_tick) as f64 / ticks_per_beat * micros_per_beat / 1_000_000.0;
+                last_tick = change_tick;
+                micros_per_beat = change_micros_per_beat as f64;
+            }
+            seconds + (tick - last_tick) as f64 / ticks_per_beat * micros_per_beat / 1_000_000.0
+        }
+        midly::Timing::Timecode(fps, subframes) => tick as f64 / (fps.as_f32() as f64 * subframes as f64),
+    };
+
+    let track_i = match track {
+        Some(track_i) => track_i,
+        None => smf.tracks.iter().position(|track| track.iter().any(|event| is_onset(&event.kind))).ok_or(ImportError::NoOnsets)?,
+    };
+    let track = smf.tracks.get(track_i).ok_or(ImportError::NoSuchTrack(track_i))?;
+
+    relative_to_first_onset(absolute_ticks(track).filter(|(_, kind)| is_onset(kind)).map(|(tick, _)| Time(Seconds::from_f64_rounded(tick_to_seconds(tick)))).collect())
+}
+
+// recordings usually do not start exactly at the first onset, but the original line should
+fn relative_to_first_onset(mut onsets: Vec<Time<Seconds>>) -> Result<Vec<Time<Seconds>>, ImportError> {
+    onsets.sort();
+    onsets.dedup();
+    let first = *onsets.first().ok_or(ImportError::NoOnsets)?;
+    Ok(onsets.into_iter().map(|onset| Time(onset.0 - first.0)).collect())
+}
+
+// the source code for a line with the onsets as its original
+pub fn recorded_line_source(onsets: &[Time<Seconds>]) -> String {
+    format!("{};", Original::Recorded(onsets.to_vec()))
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::InvalidCsvValue { line, value } => write!(f, "invalid onset time on line {line}: {value} (should be a number of seconds)"),
+            ImportError::InvalidMidi(err) => write!(f, "invalid midi file: {err}"),
+            ImportError::InvalidMidiTiming => write!(f, "invalid midi file: its header has 0 ticks per beat or 0 subframes per frame"),
+            ImportError::NoSuchTrack(track_i) => write!(f, "midi file has no track {track_i}"),
+            ImportError::NoOnsets => write!(f, "no onsets found"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(onsets: &[Time<Seconds>]) -> Vec<i32> {
+        onsets.iter().map(|onset| (onset.0 .0 * num_rational::Ratio::from_integer(1000)).to_integer()).collect()
+    }
+
+    fn error(result: Result<Vec<Time<Seconds>>, ImportError>) -> String {
+        match result {
+            Ok(onsets) => panic!("should not import, but got {:?}", millis(&onsets)),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    fn note_on(delta: u32, vel: u8) -> midly::TrackEvent<'static> {
+        midly::TrackEvent { delta: delta.into(), kind: midly::TrackEventKind::Midi { channel: 0.into(), message: midly::MidiMessage::NoteOn { key: 60.into(), vel: vel.into() } } }
+    }
+
+    fn midi(timing: midly::Timing, tracks: Vec<Vec<midly::TrackEvent<'static>>>) -> Vec<u8> {
+        let mut smf = midly::Smf::new(midly::Header::new(midly::Format::Parallel, timing));
+        smf.tracks = tracks;
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).expect("could not write midi file");
+        bytes
+    }
+
+    #[test]
+    fn reads_csv_onsets() {
+        let onsets = onsets_from_csv("time,velocity\n1.5,100\n\n1.0;80\n2.25\t90\n1.0,70\n").ok().expect("csv should import");
+        assert_eq!(millis(&onsets), [0, 500, 1250]);
+    }
+
+    #[test]
+    fn rejects_bad_csv_values() {
+        assert_eq!(error(onsets_from_csv("0.5\nsoon\n")), "invalid onset time on line 2: soon (should be a number of seconds)");
+        assert_eq!(error(onsets_from_csv("0.5\n-1\n")), "invalid onset time on line 2: -1 (should be a number of seconds)");
+        assert_eq!(error(onsets_from_csv("time\n")), "no onsets found");
+    }
+
+    #[test]
+    fn reads_midi_onsets_with_tempo_changes() {
+        let tempo = |delta: u32, micros_per_beat: u32| midly::TrackEvent { delta: delta.into(), kind: midly::TrackEventKind::Meta(midly::MetaMessage::Tempo(micros_per_beat.into())) };
+        // 120 bpm for the first beat, then 60 bpm; the first track has no notes, and a note on with velocity 0 is a note off
+        let bytes = midi(midly::Timing::Metrical(480.into()), vec![vec![tempo(480, 1_000_000)], vec![note_on(240, 100), note_on(240, 0), note_on(0, 100), note_on(480, 100)]]);
+        assert_eq!(millis(&onsets_from_midi(&bytes, None).ok().expect("midi should import")), [0, 250, 1250]);
+        assert_eq!(error(onsets_from_midi(&bytes, Some(0))), "no onsets found");
+        assert_eq!(error(onsets_from_midi(&bytes, Some(2))), "midi file has no track 2");
+    }
+
+    #[test]
+    fn reads_midi_onsets_with_timecode() {
+        // 25 frames per second with 40 subframes per frame is a tick per millisecond
+        let bytes = midi(midly::Timing::Timecode(midly::Fps::Fps25, 40), vec![vec![note_on(100, 100), note_on(300, 100)]]);
+        assert_eq!(millis(&onsets_from_midi(&bytes, None).ok().expect("midi should import")), [0, 300]);
+    }
+
+    #[test]
+    fn rejects_midi_without_ticks() {
+        let bytes = midi(midly::Timing::Timecode(midly::Fps::Fps25, 0), vec![vec![note_on(100, 100)]]);
+        assert_eq!(error(onsets_from_midi(&bytes, None)), "invalid midi file: its header has 0 ticks per beat or 0 subframes per frame");
+        let bytes = midi(midly::Timing::Metrical(0.into()), vec![vec![note_on(100, 100)]]);
+        assert_eq!(error(onsets_from_midi(&bytes, None)), "invalid midi file: its header has 0 ticks per beat or 0 subframes per frame");
+        assert!(error(onsets_from_midi(b"not midi", None)).starts_with("invalid midi file: "));
+    }
+}
@@ -0,0 +1,244 @@
+use std::fmt::Display;
+
+use num_rational::Ratio;
+use num_traits::{ConstZero, ToPrimitive};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    polyrhythm::{flatten_rhythm, Event, EventKind, Original, Polyrhythm},
+    rhythm::{InvalidTupletRatio, NoteDuration, NoteDurationKind, Rhythm, RhythmSegment, TupletDisplay, TupletError, TupletInnerDurationMismatch},
+    time::{Duration, Time},
+    units::{Seconds, WholeNotes},
+};
+
+pub fn to_json(polyrhythm: &Polyrhythm) -> String {
+    serde_json::to_string_pretty(polyrhythm).expect("polyrhythm should always be serializable")
+}
+
+pub enum JsonError {
+    Invalid(serde_json::Error),
+    // the poly ratio would generate lines that the json does not start with
+    PolyLinesMismatch,
+    // names are written in quotes, which cannot be escaped
+    QuoteInName(String),
+    InvalidTempo(u32),
+    NegativeOnset(f64),
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonError::Invalid(err) => write!(f, "{err}"),
+            JsonError::PolyLinesMismatch => write!(f, "the first lines should be the ones generated from the poly ratio"),
+            JsonError::QuoteInName(name) => write!(f, "invalid name: {name} (names cannot contain \")"),
+            JsonError::InvalidTempo(bpm) => write!(f, "invalid tempo: {bpm} (should be from {} to {})", Polyrhythm::TEMPO.start(), Polyrhythm::TEMPO.end()),
+            JsonError::NegativeOnset(seconds) => write!(f, "invalid onset: {seconds}s (should not be negative)"),
+        }
+    }
+}
+
+// json is checked for what the grammar would not allow, so that it can always be written back as source code
+pub fn from_json(json: &str) -> Result<Polyrhythm, JsonError> {
+    let mut polyrhythm: Polyrhythm = serde_json::from_str(json).map_err(JsonError::Invalid)?;
+    if !Polyrhythm::TEMPO.contains(&polyrhythm.tempo.1) {
+        return Err(JsonError::InvalidTempo(polyrhythm.tempo.1));
+    }
+    if !polyrhythm.starts_with_generated_lines() {
+        return Err(JsonError::PolyLinesMismatch);
+    }
+    let names = polyrhythm.rhythms.iter().flat_map(|line| line.name.iter().chain(line.approximation_names.iter().flatten()));
+    if let Some(name) = names.into_iter().find(|name| name.contains('"')) {
+        return Err(JsonError::QuoteInName(name.clone()));
+    }
+    // recorded onsets are kept like parsed ones, rounded to the nearest millisecond and in order
+    for line in &mut polyrhythm.rhythms {
+        if let Original::Recorded(onsets) = &mut line.original {
+            for onset in onsets.iter_mut() {
+                let seconds = onset.0 .0.to_f64().expect("ratios of i32s should always convert to f64");
+                if seconds < 0.0 {
+                    return Err(JsonError::NegativeOnset(seconds));
+                }
+                *onset = Time(Seconds::from_f64_rounded(seconds));
+            }
+            onsets.sort();
+        }
+    }
+    Ok(polyrhythm)
+}
+
+// the onsets and rest starts of every staff, so that they can be used without parsing the rhythms again
+#[derive(Serialize)]
+struct EventsView {
+    tempo: (NoteDuration, u32),
+    pulse: Option<Vec<EventView>>,
+    lines: Vec<LineEventsView>,
+}
+
+#[derive(Serialize)]
+struct LineEventsView {
+    original: Vec<EventView>,
+    approximations: Vec<Vec<EventView>>,
+}
+
+#[derive(Serialize)]
+struct EventView {
+    kind: EventKind,
+    // as [numerator, denominator]
+    whole_notes: Ratio<i32>,
+    seconds: f64,
+}
+
+pub fn events_json(polyrhythm: &Polyrhythm) -> String {
+    let view_events = |events: Vec<Event>| -> Vec<EventView> { events.into_iter().map(|ev| EventView { kind: ev.kind, whole_notes: ev.time.0 .0, seconds: ev.time.0.to_seconds(polyrhythm.tempo).0.to_f64().unwrap() }).collect() };
+
+    let view = EventsView {
+        tempo: polyrhythm.tempo,
+        pulse: polyrhythm.pulse.as_ref().map(|pulse| view_events(flatten_rhythm(&pulse.rhythm))),
+        lines: polyrhythm
+            .rhythms
+            .iter()
+            .map(|line| LineEventsView { original: view_events(line.original.flatten(polyrhythm.tempo)), approximations: line.approximations.iter().map(|approx| view_events(flatten_rhythm(approx))).collect() })
+            .collect(),
+    };
+    serde_json::to_string_pretty(&view).expect("events should always be serializable")
+}
+
+// deserialized note durations and tuplets go through the same checks as parsed ones
+#[derive(Serialize, Deserialize)]
+pub struct NoteDurationRepr {
+    kind: NoteDurationKind,
+    dots: u8,
+}
+
+pub struct TooManyDots(u8);
+
+impl Display for TooManyDots {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "too many dots: {} (should be at most {})", self.0, NoteDuration::MAX_DOTS)
+    }
+}
+
+impl TryFrom<NoteDurationRepr> for NoteDuration {
+    type Error = TooManyDots;
+
+    fn try_from(repr: NoteDurationRepr) -> Result<Self, Self::Error> {
+        if repr.dots <= NoteDuration::MAX_DOTS {
+            Ok(NoteDuration { kind: repr.kind, dots: repr.dots })
+        } else {
+            Err(TooManyDots(repr.dots))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RhythmSegmentRepr {
+    Note(NoteDuration),
+    TiedNote(Vec<NoteDuration>),
+    Rest(NoteDuration),
+    FreeNote(Duration<WholeNotes>),
+    FreeRest(Duration<WholeNotes>),
+    Tuplet { actual: u32, normal: u32, note_duration: NoteDuration, display: TupletDisplay, rhythm: Rhythm },
+}
+
+pub enum InvalidSegment {
+    Tuplet(TupletError),
+    NonPositiveDuration(Duration<WholeNotes>),
+}
+
+impl Display for InvalidSegment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidSegment::Tuplet(TupletError::InnerDurationMismatch(TupletInnerDurationMismatch { actual, expected })) => write!(f, "tuplet inner duration mismatch: expected duration of {expected} but got {actual}"),
+            InvalidSegment::Tuplet(TupletError::InvalidRatio(InvalidTupletRatio { actual, normal })) => write!(f, "invalid tuplet ratio: {actual}/{normal} (both numbers should be positive)"),
+            InvalidSegment::NonPositiveDuration(duration) => write!(f, "invalid duration: {duration} (should be positive)"),
+        }
+    }
+}
+
+impl TryFrom<RhythmSegmentRepr> for RhythmSegment {
+    type Error = InvalidSegment;
+
+    fn try_from(repr: RhythmSegmentRepr) -> Result<Self, Self::Error> {
+        match repr {
+            RhythmSegmentRepr::Note(dur) => Ok(RhythmSegment::Note(dur)),
+            RhythmSegmentRepr::TiedNote(durs) => Ok(RhythmSegment::TiedNote(durs)),
+            RhythmSegmentRepr::Rest(dur) => Ok(RhythmSegment::Rest(dur)),
+            RhythmSegmentRepr::FreeNote(dur) | RhythmSegmentRepr::FreeRest(dur) if dur <= Duration::ZERO => Err(InvalidSegment::NonPositiveDuration(dur)),
+            // ratios are deserialized as they are written, but parsed ones are always in lowest terms
+            RhythmSegmentRepr::FreeNote(dur) => Ok(RhythmSegment::FreeNote(Duration(WholeNotes(dur.0 .0.reduced())))),
+            RhythmSegmentRepr::FreeRest(dur) => Ok(RhythmSegment::FreeRest(Duration(WholeNotes(dur.0 .0.reduced())))),
+            RhythmSegmentRepr::Tuplet { actual, normal, note_duration, display, rhythm } => RhythmSegment::new_tuplet(actual, normal, note_duration, Some(display), rhythm).map_err(InvalidSegment::Tuplet),
+        }
+    }
+}
+
+impl From<RhythmSegment> for RhythmSegmentRepr {
+    fn from(segment: RhythmSegment) -> Self {
+        match segment {
+            RhythmSegment::Note(dur) => RhythmSegmentRepr::Note(dur),
+            RhythmSegment::TiedNote(durs) => RhythmSegmentRepr::TiedNote(durs),
+            RhythmSegment::Rest(dur) => RhythmSegmentRepr::Rest(dur),
+            RhythmSegment::FreeNote(dur) => RhythmSegmentRepr::FreeNote(dur),
+            RhythmSegment::FreeRest(dur) => RhythmSegmentRepr::FreeRest(dur),
+            RhythmSegment::Tuplet { actual, normal, note_duration, display, rhythm, do_not_construct: _ } => RhythmSegmentRepr::Tuplet { actual, normal, note_duration, display, rhythm: *rhythm },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Polyrhythm {
+        crate::parse::parse(source).unwrap_or_else(|err| panic!("could not parse {source:?}: {}", crate::parse::parse_error_text(&err)))
+    }
+
+    fn from_json_source(json: &str) -> String {
+        match from_json(json) {
+            Ok(polyrhythm) => polyrhythm.to_string(),
+            Err(err) => panic!("could not read json: {err}"),
+        }
+    }
+
+    fn from_json_error(json: &str) -> String {
+        match from_json(json) {
+            Ok(polyrhythm) => panic!("json should not be read, but got {polyrhythm}"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        for source in [
+            "tempo 4 = 120; poly 3:2 over 2; resultant;",
+            "tempo 8 = 90; tuplet 5/4 (8) [5:4 8] {8 8 r8 tuplet 3/2 (16) {16 16 16} 8} approx {8 8 8 8};",
+            r#"tempo 4. = 60; staff percussion; "clave" {4. 8 r4} at space 2 approx "even" {4 4 4} approx {3/8 r5/8} pulse 4;"#,
+            "tempo 4 = 100; recorded 0s 0.25s 0.7s approx {8 8 4};",
+        ] {
+            let polyrhythm = parse(source);
+            assert_eq!(from_json_source(&to_json(&polyrhythm)), polyrhythm.to_string(), "{source}");
+        }
+    }
+
+    #[test]
+    fn normalizes_ratios() {
+        let json = serde_json::to_string(&parse("tempo 4 = 60; {1/2 r1/4} approx {2 r4};")).expect("polyrhythm should always be serializable");
+        let unreduced = json.replace(r#""free_note":[1,2]"#, r#""free_note":[-2,-4]"#).replace(r#""free_rest":[1,4]"#, r#""free_rest":[25,100]"#);
+        assert_ne!(json, unreduced);
+        assert_eq!(from_json_source(&unreduced), "tempo 4 = 60;\n{1/2 r1/4} approx {2 r4};\n");
+
+        let json = serde_json::to_string(&parse("tempo 4 = 60; recorded 0s 0.5s approx {4 4};")).expect("polyrhythm should always be serializable");
+        let unrounded = json.replace("[[0,1],[1,2]]", "[[1,3],[0,1]]");
+        assert_ne!(json, unrounded);
+        assert_eq!(from_json_source(&unrounded), "tempo 4 = 60;\nrecorded 0s 0.333s approx {4 4};\n");
+        assert_eq!(from_json_error(&json.replace("[[0,1],[1,2]]", "[[0,1],[-1,2]]")), "invalid onset: -0.5s (should not be negative)");
+    }
+
+    #[test]
+    fn rejects_what_the_grammar_would_not_allow() {
+        let json = serde_json::to_string(&parse("tempo 4 = 60; {1/2 r1/4};")).expect("polyrhythm should always be serializable");
+        assert!(from_json_error(&json.replace(r#""free_note":[1,2]"#, r#""free_note":[-1,2]"#)).starts_with("invalid duration: -1/2 whole notes long (should be positive)"));
+        assert_eq!(from_json_error(&json.replace("60]", "0]")), "invalid tempo: 0 (should be from 1 to 1000)");
+    }
+}
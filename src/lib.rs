@@ -1,42 +1,140 @@
+use std::rc::Rc;
+
 use wasm_bindgen::{closure::Closure, prelude::wasm_bindgen, JsCast};
-use web_sys::{HtmlCanvasElement, HtmlTextAreaElement};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Document, Element, HtmlCanvasElement, HtmlInputElement, HtmlTextAreaElement};
 
+mod analysis;
+mod api;
 mod drawing;
+mod editor;
+#[cfg(feature = "raster")]
+pub mod headless;
+mod import;
+#[cfg(feature = "serde")]
+mod json;
+mod lilypond;
+mod midi;
+mod musicxml;
 mod parse;
 mod polyrhythm;
+mod resultant;
 mod rhythm;
+mod share;
 mod time;
 mod units;
 
 #[wasm_bindgen(start)]
-pub async fn main() {
+pub fn start() {
     std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+}
 
+// sets up the editor on the site, which has to have elements with the ids used here
+// other pages can use PolyrhythmEditor directly instead
+#[wasm_bindgen(js_name = runSite)]
+pub async fn run_site() {
     let window = web_sys::window().expect("global window does not exists");
     let document = window.document().expect("expecting a document on window");
 
     let codebox = document.get_element_by_id("code").expect("could not find code box").dyn_into::<HtmlTextAreaElement>().expect("code box should be a textarea");
     let canvas = document.get_element_by_id("canvas").expect("could not find canvas").dyn_into::<HtmlCanvasElement>().expect("canvas should be canvas");
     let errors = document.get_element_by_id("errors").expect("could not find errors box");
+    let analysis = document.get_element_by_id("analysis").expect("could not find analysis box");
+    let import = document.get_element_by_id("import").expect("could not find import input").dyn_into::<HtmlInputElement>().expect("import input should be an input");
+
+    import
+        .add_event_listener_with_callback(
+            "change",
+            Closure::<dyn Fn()>::new({
+                let import = import.clone();
+                let codebox = codebox.clone();
+                let errors = errors.clone();
+                let document = document.clone();
+                move || {
+                    let Some(file) = import.files().and_then(|files| files.get(0)) else {
+                        return;
+                    };
+                    // so that importing the same file again still fires a change event
+                    import.set_value("");
+
+                    let codebox = codebox.clone();
+                    let errors = errors.clone();
+                    let document = document.clone();
+                    wasm_bindgen_futures::spawn_local(async move {
+                        let contents = JsFuture::from(file.array_buffer()).await.expect("could not read imported file");
+                        let bytes = web_sys::js_sys::Uint8Array::new(&contents).to_vec();
+
+                        let name = file.name().to_lowercase();
+                        // json replaces the whole source, musicxml rhythms are added as a notated line and everything else as recorded onsets
+                        let replaces_source = name.ends_with(".json");
+                        let imported = if replaces_source {
+                            polyrhythm_source_from_json(&bytes).map(|source| (source, Vec::new()))
+                        } else if name.ends_with(".musicxml") || name.ends_with(".xml") {
+                            musicxml::from_musicxml(&String::from_utf8_lossy(&bytes)).map(|imported| (format!("{};", imported.rhythm), imported.diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect::<Vec<_>>())).map_err(|err| err.to_string())
+                        } else if name.ends_with(".mid") || name.ends_with(".midi") {
+                            import::onsets_from_midi(&bytes, None).map(|onsets| (import::recorded_line_source(&onsets), Vec::new())).map_err(|err| err.to_string())
+                        } else {
+                            import::onsets_from_csv(&String::from_utf8_lossy(&bytes)).map(|onsets| (import::recorded_line_source(&onsets), Vec::new())).map_err(|err| err.to_string())
+                        };
+                        match imported {
+                            Ok((line, diagnostics)) => {
+                                let mut code = if replaces_source { String::new() } else { codebox.value() };
+                                if !code.is_empty() && !code.ends_with('\n') {
+                                    code.push('\n');
+                                }
+                                code.push_str(&line);
+                                codebox.set_value(&code);
+                                codebox.dispatch_event(&web_sys::Event::new("input").expect("could not create input event")).expect("could not dispatch input event on code box");
+
+                                // shown after redrawing, which clears the errors box
+                                if !diagnostics.is_empty() {
+                                    errors.replace_children_with_node_1(&parse::error_text_to_div(&document, &format!("imported {name} with changes: {}", diagnostics.join("; "))).into());
+                                }
+                            }
+                            Err(err) => {
+                                errors.replace_children_with_node_1(&parse::error_text_to_div(&document, &format!("could not import {name}: {err}")).into());
+                            }
+                        }
+                    });
+                }
+            })
+            .into_js_value()
+            .dyn_ref()
+            .expect("closure should be function"),
+        )
+        .expect("could not add event listener on import input");
+
+    add_export_button(&document, &codebox, "export-musicxml", "polyrhythm.musicxml", "application/vnd.recordare.musicxml+xml", |polyrhythm| musicxml::to_musicxml(polyrhythm).into_bytes());
+    add_export_button(&document, &codebox, "export-midi", "polyrhythm.mid", "audio/midi", midi::to_midi);
+    add_export_button(&document, &codebox, "export-lilypond", "polyrhythm.ly", "text/x-lilypond", |polyrhythm| lilypond::to_lilypond(polyrhythm).into_bytes());
+    #[cfg(feature = "serde")]
+    {
+        add_export_button(&document, &codebox, "export-json", "polyrhythm.json", "application/json", |polyrhythm| json::to_json(polyrhythm).into_bytes());
+        add_export_button(&document, &codebox, "export-events", "events.json", "application/json", |polyrhythm| json::events_json(polyrhythm).into_bytes());
+    }
+
+    add_pdf_export_button(&document, &codebox, &errors);
 
-    let font = drawing::Font::load_bravura(&window).await;
+    // a shared link takes precedence over the last session
+    let storage = window.local_storage().ok().flatten();
+    let initial_source = window.location().hash().ok().and_then(|hash| share::fragment_to_source(&hash)).or_else(|| storage.as_ref().and_then(|storage| storage.get_item(share::LOCAL_STORAGE_KEY).ok().flatten()));
+    if let Some(source) = initial_source {
+        codebox.set_value(&source);
+    }
 
     codebox
         .add_event_listener_with_callback(
             "input",
             Closure::<dyn Fn()>::new({
                 let codebox = codebox.clone();
+                let history = window.history().expect("could not get history");
                 move || {
-                    let code = codebox.value();
-                    let parsed = parse::parse(&code);
-                    match parsed {
-                        Ok(polyrhythm) => {
-                            drawing::draw(&canvas, &font, &polyrhythm);
-                            errors.replace_children_with_node(&web_sys::js_sys::Array::new());
-                        }
-                        Err(err) => {
-                            errors.replace_children_with_node_1(&parse::parse_error_to_div(&document, err).into());
-                        }
+                    let source = codebox.value();
+                    // replacing the state instead of pushing a new one keeps every edit from becoming a history entry
+                    history.replace_state_with_url(&wasm_bindgen::JsValue::NULL, "", Some(&share::source_to_fragment(&source))).expect("could not update url");
+                    // storage can be unavailable or full, in which case the session is just not saved
+                    if let Some(storage) = &storage {
+                        let _ = storage.set_item(share::LOCAL_STORAGE_KEY, &source);
                     }
                 }
             })
@@ -45,4 +143,135 @@ pub async fn main() {
             .expect("closure should be function"),
         )
         .expect("could not add event listener on code box input");
+
+    // pasting a different shared link into the address bar only changes the fragment, so the page is not reloaded
+    window
+        .add_event_listener_with_callback(
+            "hashchange",
+            Closure::<dyn Fn()>::new({
+                let codebox = codebox.clone();
+                let window = window.clone();
+                move || {
+                    let Some(source) = window.location().hash().ok().and_then(|hash| share::fragment_to_source(&hash)) else {
+                        return;
+                    };
+                    if source != codebox.value() {
+                        codebox.set_value(&source);
+                        codebox.dispatch_event(&web_sys::Event::new("input").expect("could not create input event")).expect("could not dispatch input event on code box");
+                    }
+                }
+            })
+            .into_js_value()
+            .dyn_ref()
+            .expect("closure should be function"),
+        )
+        .expect("could not add event listener on window hash change");
+
+    let editor = Rc::new(editor::PolyrhythmEditor::create(codebox, canvas, errors, Some(analysis), None).await);
+    add_zoom_button(&document, &editor, "zoom-in", editor::PolyrhythmEditor::zoom_in);
+    add_zoom_button(&document, &editor, "zoom-out", editor::PolyrhythmEditor::zoom_out);
+    add_zoom_button(&document, &editor, "zoom-fit", editor::PolyrhythmEditor::fit_to_width);
+}
+
+fn add_zoom_button(document: &Document, editor: &Rc<editor::PolyrhythmEditor>, button_id: &str, zoom: fn(&editor::PolyrhythmEditor)) {
+    let button = document.get_element_by_id(button_id).expect("could not find zoom button");
+    button
+        .add_event_listener_with_callback(
+            "click",
+            Closure::<dyn Fn()>::new({
+                let editor = editor.clone();
+                move || zoom(&editor)
+            })
+            .into_js_value()
+            .dyn_ref()
+            .expect("closure should be function"),
+        )
+        .expect("could not add event listener on zoom button");
+}
+
+// makes the browser save `contents` as a file called `file_name`
+fn download(document: &Document, file_name: &str, mime_type: &str, contents: &[u8]) {
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type(mime_type);
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(&web_sys::js_sys::Array::of1(&web_sys::js_sys::Uint8Array::from(contents)), &options).expect("could not create blob for download");
+    let url = web_sys::Url::create_object_url_with_blob(&blob).expect("could not create url for download");
+
+    let link = document.create_element("a").expect("could not create link for download").dyn_into::<web_sys::HtmlAnchorElement>().expect("a should be able to be casted into a");
+    link.set_href(&url);
+    link.set_download(file_name);
+    link.click();
+
+    web_sys::Url::revoke_object_url(&url).expect("could not revoke url for download");
+}
+
+// exports the current polyrhythm as a file whenever the button with id `button_id` is clicked
+fn add_export_button(document: &Document, codebox: &HtmlTextAreaElement, button_id: &str, file_name: &'static str, mime_type: &'static str, export: fn(&polyrhythm::Polyrhythm) -> Vec<u8>) {
+    let button = document.get_element_by_id(button_id).expect("could not find export button");
+    button
+        .add_event_listener_with_callback(
+            "click",
+            Closure::<dyn Fn()>::new({
+                let codebox = codebox.clone();
+                let document = document.clone();
+                move || {
+                    // parse errors are already shown in the errors box while typing
+                    if let Ok(polyrhythm) = parse::parse(&codebox.value()) {
+                        download(&document, file_name, mime_type, &export(&polyrhythm));
+                    }
+                }
+            })
+            .into_js_value()
+            .dyn_ref()
+            .expect("closure should be function"),
+        )
+        .expect("could not add event listener on export button");
+}
+
+// the pdf needs the music font to be fetched first, so it is exported asynchronously
+fn add_pdf_export_button(document: &Document, codebox: &HtmlTextAreaElement, errors: &Element) {
+    let button = document.get_element_by_id("export-pdf").expect("could not find pdf export button");
+    let title = document.get_element_by_id("pdf-title").expect("could not find pdf title input").dyn_into::<HtmlInputElement>().expect("pdf title input should be an input");
+    let composer = document.get_element_by_id("pdf-composer").expect("could not find pdf composer input").dyn_into::<HtmlInputElement>().expect("pdf composer input should be an input");
+    let paper = document.get_element_by_id("pdf-paper").expect("could not find pdf paper select").dyn_into::<web_sys::HtmlSelectElement>().expect("pdf paper select should be a select");
+    button
+        .add_event_listener_with_callback(
+            "click",
+            Closure::<dyn Fn()>::new({
+                let codebox = codebox.clone();
+                let errors = errors.clone();
+                let document = document.clone();
+                move || {
+                    let Ok(polyrhythm) = parse::parse(&codebox.value()) else {
+                        return;
+                    };
+                    let options = drawing::PageOptions {
+                        paper: drawing::PaperSize::from_name(&paper.value()).unwrap_or(drawing::PaperSize::A4),
+                        title: Some(title.value()).filter(|title| !title.is_empty()),
+                        composer: Some(composer.value()).filter(|composer| !composer.is_empty()),
+                        ..Default::default()
+                    };
+                    let errors = errors.clone();
+                    let document = document.clone();
+                    wasm_bindgen_futures::spawn_local(async move {
+                        match api::pdf(&polyrhythm, &options, None, None).await {
+                            Ok(pdf) => download(&document, "polyrhythm.pdf", "application/pdf", &pdf),
+                            Err(err) => errors.replace_children_with_node_1(&parse::error_text_to_div(&document, &format!("could not export pdf: {err}")).into()),
+                        }
+                    });
+                }
+            })
+            .into_js_value()
+            .dyn_ref()
+            .expect("closure should be function"),
+        )
+        .expect("could not add event listener on pdf export button");
+}
+
+#[cfg(feature = "serde")]
+fn polyrhythm_source_from_json(bytes: &[u8]) -> Result<String, String> {
+    json::from_json(&String::from_utf8_lossy(bytes)).map(|polyrhythm| polyrhythm.to_string()).map_err(|err| err.to_string())
+}
+#[cfg(not(feature = "serde"))]
+fn polyrhythm_source_from_json(_bytes: &[u8]) -> Result<String, String> {
+    Err("json support is not enabled in this build".to_string())
 }
@@ -0,0 +1,176 @@
+use std::fmt::Write;
+
+use num_rational::Ratio;
+
+use crate::{
+    polyrhythm::Polyrhythm,
+    rhythm::{NoteDuration, Rhythm, SpannedSegment, TupletDisplay},
+    time::Duration,
+    units::WholeNotes,
+};
+
+// writes a LilyPond score with a RhythmicStaff for each rhythm, where a line with more than one staff (the original followed by its approximations) is a staff group
+// recorded originals have no notated rhythm, so only their approximations are written
+// the staves are named like the labels of the drawn staves
+pub fn to_lilypond(polyrhythm: &Polyrhythm) -> String {
+    let mut ly = String::new();
+    writeln!(ly, r#"\version "2.24.0""#).unwrap();
+    writeln!(ly).unwrap();
+    writeln!(ly, r"\score {{").unwrap();
+    writeln!(ly, "  <<").unwrap();
+
+    // the tempo only needs to be given once for the whole score
+    let mut tempo = Some(polyrhythm.tempo);
+    if let Some(pulse) = polyrhythm.pulse.as_ref().map(|pulse| &pulse.rhythm) {
+        write_staff(&mut ly, "    ", "pulse", pulse, tempo.take());
+    }
+    for (line_i, line) in polyrhythm.rhythms.iter().enumerate() {
+        let staves: Vec<(String, &Rhythm)> = line
+            .original
+            .notated()
+            .map(|original| (line.label(line_i), original))
+            .into_iter()
+            .chain(line.approximations.iter().enumerate().map(|(approx_i, approx)| (line.approximation_label(approx_i), approx)))
+            .collect();
+        if staves.len() > 1 {
+            writeln!(ly, r"    \new StaffGroup <<").unwrap();
+            for (name, rhythm) in staves {
+                write_staff(&mut ly, "      ", &name, rhythm, tempo.take());
+            }
+            writeln!(ly, "    >>").unwrap();
+        } else {
+            for (name, rhythm) in staves {
+                write_staff(&mut ly, "    ", &name, rhythm, tempo.take());
+            }
+        }
+    }
+
+    writeln!(ly, "  >>").unwrap();
+    writeln!(ly, r"  \layout {{").unwrap();
+    writeln!(ly, r"    \context {{ \RhythmicStaff \omit TimeSignature }}").unwrap();
+    writeln!(ly, "  }}").unwrap();
+    writeln!(ly, "}}").unwrap();
+    ly
+}
+
+fn write_staff(ly: &mut String, indent: &str, name: &str, rhythm: &Rhythm, tempo: Option<(NoteDuration, u32)>) {
+    writeln!(ly, r#"{indent}\new RhythmicStaff \with {{ instrumentName = "{}" }} {{"#, name.replace('\\', r"\\").replace('"', "\\\"")).unwrap();
+
+    // there are no measures, so invisible bar lines are added between segments to allow line breaks
+    write!(ly, r"{indent}  \cadenzaOn").unwrap();
+    if let Some((note_duration, bpm)) = tempo {
+        write!(ly, r" \tempo {} = {bpm}", duration_text(note_duration)).unwrap();
+    }
+    writeln!(ly).unwrap();
+    for SpannedSegment { segment, span: _ } in &rhythm.segments {
+        let mut text = String::new();
+        write_segment(&mut text, segment);
+        writeln!(ly, r#"{indent}  {text} \bar """#).unwrap();
+    }
+    writeln!(ly, r#"{indent}  \bar "|.""#).unwrap();
+    writeln!(ly, "{indent}}}").unwrap();
+}
+
+fn write_segment(ly: &mut String, segment: &crate::rhythm::RhythmSegment) {
+    match segment {
+        crate::rhythm::RhythmSegment::Note(dur) => write!(ly, "c{}", duration_text(*dur)).unwrap(),
+        crate::rhythm::RhythmSegment::TiedNote(durs) => write!(ly, "{}", durs.iter().map(|dur| format!("c{}", duration_text(*dur))).collect::<Vec<_>>().join("~ ")).unwrap(),
+        crate::rhythm::RhythmSegment::Rest(dur) => write!(ly, "r{}", duration_text(*dur)).unwrap(),
+        crate::rhythm::RhythmSegment::FreeNote(dur) => write!(ly, "{}", free_duration_text(*dur, "c", "~ ")).unwrap(),
+        crate::rhythm::RhythmSegment::FreeRest(dur) => write!(ly, "{}", free_duration_text(*dur, "r", " ")).unwrap(),
+        crate::rhythm::RhythmSegment::Tuplet { actual, normal, note_duration: _, display, rhythm, do_not_construct: _ } => {
+            if let Some(text) = tuplet_number_text(*actual, *display) {
+                write!(ly, r"\once \override TupletNumber.text = {text} ").unwrap();
+            }
+            write!(ly, r"\tuplet {actual}/{normal} {{ ").unwrap();
+            for SpannedSegment { segment, span: _ } in &rhythm.segments {
+                write_segment(ly, segment);
+                write!(ly, " ").unwrap();
+            }
+            write!(ly, "}}").unwrap();
+        }
+    }
+}
+
+fn duration_text(note_duration: NoteDuration) -> String {
+    format!("{}{}", note_duration.kind.to_ratio().denom(), ".".repeat(note_duration.dots as usize))
+}
+
+// free durations are written as tied notes (or consecutive rests) if possible, and otherwise as the nearest note value scaled to the exact duration
+fn free_duration_text(duration: Duration<WholeNotes>, pitch: &str, separator: &str) -> String {
+    match NoteDuration::split_duration(duration) {
+        Some(notes) => notes.into_iter().map(|note| format!("{pitch}{}", duration_text(note))).collect::<Vec<_>>().join(separator),
+        None => {
+            let nearest = NoteDuration::nearest(duration);
+            let scale: Ratio<i32> = duration.0 .0 / nearest.to_duration().0 .0;
+            format!("{pitch}{}*{}/{}", duration_text(nearest), scale.numer(), scale.denom())
+        }
+    }
+}
+
+// the scheme value for TupletNumber.text, or None if lilypond's default number already matches
+fn tuplet_number_text(actual: u32, display: TupletDisplay) -> Option<String> {
+    match display {
+        TupletDisplay::Number(number) if number == actual => None,
+        TupletDisplay::Number(number) => Some(format!(r#"\markup "{number}""#)),
+        TupletDisplay::Ratio(actual, normal) => Some(format!("#(tuplet-number::non-default-tuplet-fraction-text {actual} {normal})")),
+        TupletDisplay::RatioWithNote(actual, normal, note_duration) => Some(format!(
+            "#(tuplet-number::append-note-wrapper (tuplet-number::non-default-tuplet-fraction-text {actual} {normal}) (ly:make-duration {} {}))",
+            note_duration.kind.to_ratio().denom().trailing_zeros(),
+            note_duration.dots
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_a_staff_group_only_for_lines_with_approximations() {
+        let polyrhythm = crate::parse::parse(r#"tempo 4 = 90; "clave" tuplet 3/2 (4) {4 4 4} approx "even" {4. 8} approx {8 r8 4}; {4 r4};"#).ok().expect("source should parse");
+        let expected = r#"\version "2.24.0"
+
+\score {
+  <<
+    \new StaffGroup <<
+      \new RhythmicStaff \with { instrumentName = "clave" } {
+        \cadenzaOn \tempo 4 = 90
+        \tuplet 3/2 { c4 c4 c4 } \bar ""
+        \bar "|."
+      }
+      \new RhythmicStaff \with { instrumentName = "even" } {
+        \cadenzaOn
+        c4. \bar ""
+        c8 \bar ""
+        \bar "|."
+      }
+      \new RhythmicStaff \with { instrumentName = "approx 2" } {
+        \cadenzaOn
+        c8 \bar ""
+        r8 \bar ""
+        c4 \bar ""
+        \bar "|."
+      }
+    >>
+    \new RhythmicStaff \with { instrumentName = "line 2" } {
+      \cadenzaOn
+      c4 \bar ""
+      r4 \bar ""
+      \bar "|."
+    }
+  >>
+  \layout {
+    \context { \RhythmicStaff \omit TimeSignature }
+  }
+}
+"#;
+        assert_eq!(to_lilypond(&polyrhythm), expected);
+    }
+
+    #[test]
+    fn escapes_names() {
+        let polyrhythm = crate::parse::parse(r#"tempo 4 = 90; "a\b" 4;"#).ok().expect("source should parse");
+        assert!(to_lilypond(&polyrhythm).contains(r#"\new RhythmicStaff \with { instrumentName = "a\\b" } {"#));
+    }
+}
@@ -0,0 +1,126 @@
+use num_integer::Integer;
+use num_rational::Ratio;
+use num_traits::{ConstZero, ToPrimitive};
+
+use crate::{
+    polyrhythm::{flatten_rhythm, Event, EventKind, Polyrhythm},
+    time::{Duration, Time},
+    units::WholeNotes,
+};
+
+// the largest resolution that fits in a midi header, used if the rhythms need a finer resolution than that
+const MAX_TICKS_PER_QUARTER: i64 = 0x7fff;
+const FALLBACK_TICKS_PER_QUARTER: i64 = 960;
+
+// everything is played on the general midi percussion channel, the pulse as a closed hi-hat and every line (with its approximations) with its own instrument
+const PERCUSSION_CHANNEL: u8 = 9;
+const PULSE_KEY: u8 = 42;
+const LINE_KEYS: [u8; 6] = [76, 77, 37, 56, 75, 60];
+const VELOCITY: u8 = 100;
+
+// recorded onsets have no durations, so they are played as short notes
+const RECORDED_NOTE_LENGTH: Duration<WholeNotes> = Duration(WholeNotes(Ratio::new_raw(1, 16)));
+
+struct Staff {
+    name: String,
+    key: u8,
+    notes: Vec<(Time<WholeNotes>, Time<WholeNotes>)>,
+}
+
+// writes a multitrack midi file with one track per staff and the tempo in a separate first track
+// the tracks are named like the labels of the drawn staves, with the approximations' after their line's
+// recorded originals are included with their onsets as they were recorded
+pub fn to_midi(polyrhythm: &Polyrhythm) -> Vec<u8> {
+    let mut staves = Vec::new();
+    if let Some(pulse) = polyrhythm.pulse.as_ref().map(|pulse| &pulse.rhythm) {
+        staves.push(Staff { name: "pulse".to_string(), key: PULSE_KEY, notes: notes(&flatten_rhythm(pulse), Time::ZERO + pulse.duration(), None) });
+    }
+    for (line_i, line) in polyrhythm.rhythms.iter().enumerate() {
+        let key = LINE_KEYS[line_i % LINE_KEYS.len()];
+        let original_notes = match line.original.notated() {
+            Some(original) => notes(&flatten_rhythm(original), Time::ZERO + original.duration(), None),
+            None => notes(&line.original.flatten(polyrhythm.tempo), line.original.end(polyrhythm.tempo) + RECORDED_NOTE_LENGTH, Some(RECORDED_NOTE_LENGTH)),
+        };
+        staves.push(Staff { name: line.label(line_i), key, notes: original_notes });
+        for (approx_i, approx) in line.approximations.iter().enumerate() {
+            staves.push(Staff { name: format!("{} {}", line.label(line_i), line.approximation_label(approx_i)), key, notes: notes(&flatten_rhythm(approx), Time::ZERO + approx.duration(), None) });
+        }
+    }
+
+    let ticks_per_quarter = {
+        let ticks_per_quarter = staves.iter().flat_map(|staff| &staff.notes).flat_map(|&(start, end)| [start, end]).try_fold(1, |ticks: i64, time| {
+            let quarters = time.0 .0 * Ratio::from_integer(4);
+            Some(ticks.lcm(&(*quarters.denom() as i64))).filter(|&ticks| ticks <= MAX_TICKS_PER_QUARTER)
+        });
+        ticks_per_quarter.unwrap_or(FALLBACK_TICKS_PER_QUARTER)
+    };
+    let to_ticks = |time: Time<WholeNotes>| (time.0 .0.to_f64().unwrap() * 4.0 * ticks_per_quarter as f64).round() as u32;
+
+    let (tempo_note, bpm) = polyrhythm.tempo;
+    let quarters_per_minute = tempo_note.to_duration().0 .0.to_f64().unwrap() * 4.0 * bpm as f64;
+    let micros_per_quarter = (60_000_000.0 / quarters_per_minute).round() as u32;
+
+    let mut tracks = vec![vec![
+        midly::TrackEvent { delta: 0.into(), kind: midly::TrackEventKind::Meta(midly::MetaMessage::Tempo(micros_per_quarter.min(0xff_ffff).into())) },
+        midly::TrackEvent { delta: 0.into(), kind: midly::TrackEventKind::Meta(midly::MetaMessage::EndOfTrack) },
+    ]];
+    for staff in &staves {
+        // note offs come before note ons at the same tick so that consecutive notes do not cut each other off
+        let mut events: Vec<(u32, bool)> = staff.notes.iter().flat_map(|&(start, end)| [(to_ticks(start), true), (to_ticks(end), false)]).collect();
+        events.sort();
+
+        let mut track = vec![midly::TrackEvent { delta: 0.into(), kind: midly::TrackEventKind::Meta(midly::MetaMessage::TrackName(staff.name.as_bytes())) }];
+        let mut last_tick = 0;
+        for (tick, is_on) in events {
+            let message = if is_on { midly::MidiMessage::NoteOn { key: staff.key.into(), vel: VELOCITY.into() } } else { midly::MidiMessage::NoteOff { key: staff.key.into(), vel: 0.into() } };
+            track.push(midly::TrackEvent { delta: (tick - last_tick).into(), kind: midly::TrackEventKind::Midi { channel: PERCUSSION_CHANNEL.into(), message } });
+            last_tick = tick;
+        }
+        track.push(midly::TrackEvent { delta: 0.into(), kind: midly::TrackEventKind::Meta(midly::MetaMessage::EndOfTrack) });
+        tracks.push(track);
+    }
+
+    let smf = midly::Smf { header: midly::Header::new(midly::Format::Parallel, midly::Timing::Metrical((ticks_per_quarter as u16).into())), tracks };
+    let mut bytes = Vec::new();
+    smf.write_std(&mut bytes).expect("writing midi to memory should not fail");
+    bytes
+}
+
+// the start and end of every note, where each note lasts until the next event (or the end of the rhythm)
+fn notes(events: &[Event], end: Time<WholeNotes>, max_length: Option<Duration<WholeNotes>>) -> Vec<(Time<WholeNotes>, Time<WholeNotes>)> {
+    let next_times = events.iter().skip(1).map(|ev| ev.time).chain(std::iter::once(end));
+    events
+        .iter()
+        .zip(next_times)
+        .filter(|(ev, next_time)| ev.kind == EventKind::Start && *next_time > ev.time)
+        .map(|(ev, next_time)| match max_length {
+            Some(max_length) => (ev.time, next_time.min(ev.time + max_length)),
+            None => (ev.time, next_time),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track_names(source: &str) -> Vec<String> {
+        let polyrhythm = crate::parse::parse(source).unwrap_or_else(|err| panic!("could not parse {source:?}: {}", crate::parse::parse_error_text(&err)));
+        let midi = to_midi(&polyrhythm);
+        let smf = midly::Smf::parse(&midi).expect("could not read the written midi file");
+        smf.tracks
+            .iter()
+            .filter_map(|track| {
+                track.iter().find_map(|event| match event.kind {
+                    midly::TrackEventKind::Meta(midly::MetaMessage::TrackName(name)) => Some(String::from_utf8_lossy(name).into_owned()),
+                    _ => None,
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn names_tracks_like_the_staff_labels() {
+        assert_eq!(track_names("tempo 4 = 90; pulse rep 4 4; \"three\" {tuplet 3/2 (4) {4 4 4}} approx \"eighths\" {4. 8~4} approx {4 4}; {2};"), ["pulse", "three", "three eighths", "three approx 2", "line 2"]);
+    }
+}
@@ -0,0 +1,298 @@
+use std::fmt::Write;
+
+use num_integer::Integer;
+use num_rational::Ratio;
+use num_traits::ToPrimitive;
+
+use crate::{
+    polyrhythm::Polyrhythm,
+    rhythm::{NoteDuration, NoteDurationKind, Rhythm, SpannedSegment, TupletDisplay},
+};
+
+pub use import::from_musicxml;
+
+mod import;
+#[cfg(test)]
+mod tests;
+
+// durations are written as integer multiples of 1/divisions of a quarter note, so very fine durations (like ones from milliseconds) get rounded once this is reached
+const MAX_DIVISIONS: i64 = 1 << 24;
+// musicxml only allows tuplet numbers from 1 to 6, so more deeply nested tuplets share the last number, which still pairs up their starts and stops innermost first
+const MAX_TUPLET_NUMBER: usize = 6;
+
+struct Part<'a> {
+    name: String,
+    // a line's original comes first, followed by its approximations
+    staves: Vec<&'a Rhythm>,
+}
+
+// a single written note or rest
+struct Note {
+    is_rest: bool,
+    written: NoteDuration,
+    // the duration in quarter notes after all enclosing tuplets are applied
+    sounding_quarters: Ratio<i64>,
+    tie_stop: bool,
+    tie_start: bool,
+    // (actual, normal) of all enclosing tuplets multiplied together
+    time_modification: Option<(i64, i64)>,
+    // (tuplet number, tuplet) of the tuplets that start or stop on this note, outermost first
+    tuplet_starts: Vec<(usize, TupletStart)>,
+    tuplet_stops: Vec<usize>,
+}
+
+struct TupletStart {
+    display: TupletDisplay,
+    normal: u32,
+}
+
+// the pulse gets a part of its own, and every line gets a part named like its label with a staff for its original and one for each approximation
+// recorded originals have no notated rhythm, so only their approximations are written
+fn parts(polyrhythm: &Polyrhythm) -> Vec<Part<'_>> {
+    let mut parts = Vec::new();
+    if let Some(pulse) = polyrhythm.pulse.as_ref().map(|pulse| &pulse.rhythm) {
+        parts.push(Part { name: "pulse".to_string(), staves: vec![pulse] });
+    }
+    for (line_i, line) in polyrhythm.rhythms.iter().enumerate() {
+        let staves: Vec<&Rhythm> = line.original.notated().into_iter().chain(&line.approximations).collect();
+        if !staves.is_empty() {
+            parts.push(Part { name: line.label(line_i), staves });
+        }
+    }
+    parts
+}
+
+// writes a partwise MusicXML score with one part per line, each as a single unmetered measure
+pub fn to_musicxml(polyrhythm: &Polyrhythm) -> String {
+    let parts = parts(polyrhythm);
+
+    let mut xml = String::new();
+    writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>"#).unwrap();
+    writeln!(xml, r#"<!DOCTYPE score-partwise PUBLIC "-//Recordare//DTD MusicXML 4.0 Partwise//EN" "http://www.musicxml.org/dtds/partwise.dtd">"#).unwrap();
+    writeln!(xml, r#"<score-partwise version="4.0">"#).unwrap();
+
+    writeln!(xml, "  <part-list>").unwrap();
+    for (part_i, part) in parts.iter().enumerate() {
+        writeln!(xml, r#"    <score-part id="P{}"><part-name>{}</part-name></score-part>"#, part_i + 1, escape(&part.name)).unwrap();
+    }
+    writeln!(xml, "  </part-list>").unwrap();
+
+    for (part_i, part) in parts.iter().enumerate() {
+        let staves: Vec<Vec<Note>> = part
+            .staves
+            .iter()
+            .map(|rhythm| {
+                let mut notes = Vec::new();
+                collect_notes(rhythm, None, 0, &mut notes);
+                notes
+            })
+            .collect();
+        let is_multi_staff = staves.len() > 1;
+
+        let divisions = staves.iter().flatten().fold(1, |divisions: i64, note| {
+            let next = divisions.lcm(note.sounding_quarters.denom());
+            if next > MAX_DIVISIONS {
+                divisions
+            } else {
+                next
+            }
+        });
+
+        writeln!(xml, r#"  <part id="P{}">"#, part_i + 1).unwrap();
+        writeln!(xml, r#"    <measure number="1">"#).unwrap();
+        writeln!(xml, "      <attributes>").unwrap();
+        writeln!(xml, "        <divisions>{divisions}</divisions>").unwrap();
+        writeln!(xml, r#"        <time print-object="no"><senza-misura/></time>"#).unwrap();
+        if is_multi_staff {
+            writeln!(xml, "        <staves>{}</staves>", staves.len()).unwrap();
+            for staff in 1..=staves.len() {
+                writeln!(xml, r#"        <clef number="{staff}"><sign>percussion</sign></clef>"#).unwrap();
+            }
+            for staff in 1..=staves.len() {
+                writeln!(xml, r#"        <staff-details number="{staff}"><staff-lines>1</staff-lines></staff-details>"#).unwrap();
+            }
+        } else {
+            writeln!(xml, "        <clef><sign>percussion</sign></clef>").unwrap();
+            writeln!(xml, "        <staff-details><staff-lines>1</staff-lines></staff-details>").unwrap();
+        }
+        writeln!(xml, "      </attributes>").unwrap();
+
+        // the tempo only needs to be given once for the whole score
+        if part_i == 0 {
+            write_metronome(&mut xml, polyrhythm.tempo);
+        }
+
+        // every staff is its own voice, and the staves after the first go back to the start of the measure
+        let mut previous_staff_duration = 0;
+        for (staff_i, notes) in staves.iter().enumerate() {
+            if staff_i > 0 {
+                writeln!(xml, "      <backup><duration>{previous_staff_duration}</duration></backup>").unwrap();
+            }
+            let staff = is_multi_staff.then_some(staff_i + 1);
+            previous_staff_duration = notes.iter().map(|note| write_note(&mut xml, note, divisions, staff_i + 1, staff)).sum();
+        }
+
+        writeln!(xml, r#"      <barline location="right"><bar-style>light-heavy</bar-style></barline>"#).unwrap();
+        writeln!(xml, "    </measure>").unwrap();
+        writeln!(xml, "  </part>").unwrap();
+    }
+
+    writeln!(xml, "</score-partwise>").unwrap();
+    xml
+}
+
+fn collect_notes(rhythm: &Rhythm, time_modification: Option<(i64, i64)>, tuplet_level: usize, notes: &mut Vec<Note>) {
+    let push_notes = |notes: &mut Vec<Note>, is_rest: bool, written: &[(NoteDuration, Ratio<i32>)], tied: bool| {
+        for (i, (note, duration)) in written.iter().enumerate() {
+            let (actual, normal) = time_modification.unwrap_or((1, 1));
+            notes.push(Note {
+                is_rest,
+                written: *note,
+                sounding_quarters: Ratio::new(*duration.numer() as i64 * 4 * normal, *duration.denom() as i64 * actual),
+                tie_stop: tied && i != 0,
+                tie_start: tied && i != written.len() - 1,
+                time_modification,
+                tuplet_starts: Vec::new(),
+                tuplet_stops: Vec::new(),
+            });
+        }
+    };
+    // free durations are written as tied notes (or consecutive rests) if possible, and otherwise as the nearest note value with the exact duration
+    let free_notes = |duration: crate::time::Duration<crate::units::WholeNotes>| -> Vec<(NoteDuration, Ratio<i32>)> {
+        match NoteDuration::split_duration(duration) {
+            Some(split) => split.into_iter().map(|note| (note, note.to_duration().0 .0)).collect(),
+            None => vec![(NoteDuration::nearest(duration), duration.0 .0)],
+        }
+    };
+
+    for SpannedSegment { segment, span: _ } in &rhythm.segments {
+        match segment {
+            crate::rhythm::RhythmSegment::Note(dur) => push_notes(notes, false, &[(*dur, dur.to_duration().0 .0)], false),
+            crate::rhythm::RhythmSegment::TiedNote(durs) => push_notes(notes, false, &durs.iter().map(|dur| (*dur, dur.to_duration().0 .0)).collect::<Vec<_>>(), true),
+            crate::rhythm::RhythmSegment::Rest(dur) => push_notes(notes, true, &[(*dur, dur.to_duration().0 .0)], false),
+            crate::rhythm::RhythmSegment::FreeNote(dur) => push_notes(notes, false, &free_notes(*dur), true),
+            crate::rhythm::RhythmSegment::FreeRest(dur) => push_notes(notes, true, &free_notes(*dur), false),
+            crate::rhythm::RhythmSegment::Tuplet { actual, normal, note_duration: _, display, rhythm, do_not_construct: _ } => {
+                let (outer_actual, outer_normal) = time_modification.unwrap_or((1, 1));
+                let first = notes.len();
+                collect_notes(rhythm, Some((outer_actual * *actual as i64, outer_normal * *normal as i64)), tuplet_level + 1, notes);
+                if notes.len() > first {
+                    let last = notes.len() - 1;
+                    // outer tuplets were not added yet, so inserting at the front keeps the outermost tuplet first
+                    let number = (tuplet_level + 1).min(MAX_TUPLET_NUMBER);
+                    notes[first].tuplet_starts.insert(0, (number, TupletStart { display: *display, normal: *normal }));
+                    notes[last].tuplet_stops.push(number);
+                }
+            }
+        }
+    }
+}
+
+fn write_metronome(xml: &mut String, (note_duration, bpm): (NoteDuration, u32)) {
+    let quarters_per_minute = note_duration.to_duration().0 .0.to_f64().unwrap() * 4.0 * bpm as f64;
+
+    writeln!(xml, r#"      <direction placement="above">"#).unwrap();
+    write!(xml, "        <direction-type><metronome><beat-unit>{}</beat-unit>", note_type(note_duration.kind)).unwrap();
+    for _ in 0..note_duration.dots {
+        write!(xml, "<beat-unit-dot/>").unwrap();
+    }
+    writeln!(xml, "<per-minute>{bpm}</per-minute></metronome></direction-type>").unwrap();
+    writeln!(xml, r#"        <sound tempo="{quarters_per_minute}"/>"#).unwrap();
+    writeln!(xml, "      </direction>").unwrap();
+}
+
+// returns the written duration in divisions
+fn write_note(xml: &mut String, note: &Note, divisions: i64, voice: usize, staff: Option<usize>) -> i64 {
+    let duration = (note.sounding_quarters * Ratio::from_integer(divisions)).round().to_integer().max(1);
+
+    writeln!(xml, "      <note>").unwrap();
+    if note.is_rest {
+        writeln!(xml, "        <rest/>").unwrap();
+    } else {
+        // on a one line percussion staff, the line is the middle line of a normal staff
+        writeln!(xml, "        <unpitched><display-step>B</display-step><display-octave>4</display-octave></unpitched>").unwrap();
+    }
+    writeln!(xml, "        <duration>{duration}</duration>").unwrap();
+    if note.tie_stop {
+        writeln!(xml, r#"        <tie type="stop"/>"#).unwrap();
+    }
+    if note.tie_start {
+        writeln!(xml, r#"        <tie type="start"/>"#).unwrap();
+    }
+    writeln!(xml, "        <voice>{voice}</voice>").unwrap();
+    writeln!(xml, "        <type>{}</type>", note_type(note.written.kind)).unwrap();
+    for _ in 0..note.written.dots {
+        writeln!(xml, "        <dot/>").unwrap();
+    }
+    if let Some((actual, normal)) = note.time_modification {
+        writeln!(xml, "        <time-modification><actual-notes>{actual}</actual-notes><normal-notes>{normal}</normal-notes></time-modification>").unwrap();
+    }
+    if !note.is_rest && note.written.kind != NoteDurationKind::Whole {
+        writeln!(xml, "        <stem>up</stem>").unwrap();
+    }
+    if let Some(staff) = staff {
+        writeln!(xml, "        <staff>{staff}</staff>").unwrap();
+    }
+
+    if note.tie_stop || note.tie_start || !note.tuplet_starts.is_empty() || !note.tuplet_stops.is_empty() {
+        writeln!(xml, "        <notations>").unwrap();
+        if note.tie_stop {
+            writeln!(xml, r#"          <tied type="stop"/>"#).unwrap();
+        }
+        if note.tie_start {
+            writeln!(xml, r#"          <tied type="start"/>"#).unwrap();
+        }
+        for (number, start) in &note.tuplet_starts {
+            write_tuplet_start(xml, *number, start);
+        }
+        for number in &note.tuplet_stops {
+            writeln!(xml, r#"          <tuplet type="stop" number="{number}"/>"#).unwrap();
+        }
+        writeln!(xml, "        </notations>").unwrap();
+    }
+    writeln!(xml, "      </note>").unwrap();
+    duration
+}
+
+fn write_tuplet_start(xml: &mut String, number: usize, start: &TupletStart) {
+    let (actual, normal, shown_note) = match start.display {
+        TupletDisplay::Number(actual) => (actual, start.normal, None),
+        TupletDisplay::Ratio(actual, normal) => (actual, normal, None),
+        TupletDisplay::RatioWithNote(actual, normal, note) => (actual, normal, Some(note)),
+    };
+    let show_number = if matches!(start.display, TupletDisplay::Number(_)) { "actual" } else { "both" };
+    let show_type = if shown_note.is_some() { "normal" } else { "none" };
+
+    writeln!(xml, r#"          <tuplet type="start" number="{number}" bracket="yes" show-number="{show_number}" show-type="{show_type}">"#).unwrap();
+    for (element, count) in [("tuplet-actual", actual), ("tuplet-normal", normal)] {
+        write!(xml, "            <{element}><tuplet-number>{count}</tuplet-number>").unwrap();
+        if let Some(note) = shown_note {
+            write!(xml, "<tuplet-type>{}</tuplet-type>", note_type(note.kind)).unwrap();
+            for _ in 0..note.dots {
+                write!(xml, "<tuplet-dot/>").unwrap();
+            }
+        }
+        writeln!(xml, "</{element}>").unwrap();
+    }
+    writeln!(xml, "          </tuplet>").unwrap();
+}
+
+fn note_type(kind: NoteDurationKind) -> &'static str {
+    match kind {
+        NoteDurationKind::Whole => "whole",
+        NoteDurationKind::Half => "half",
+        NoteDurationKind::Quarter => "quarter",
+        NoteDurationKind::Eigth => "eighth",
+        NoteDurationKind::Sixteenth => "16th",
+        NoteDurationKind::Nd32 => "32nd",
+        NoteDurationKind::Nd64 => "64th",
+        NoteDurationKind::Nd128 => "128th",
+        NoteDurationKind::Nd256 => "256th",
+        NoteDurationKind::Nd512 => "512th",
+        NoteDurationKind::Nd1024 => "1024th",
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,409 @@
+use std::fmt::Display;
+
+use num_rational::Ratio;
+use num_traits::ConstZero;
+use roxmltree::Node;
+
+use crate::{
+    polyrhythm::{flatten_rhythm, EventKind},
+    rhythm::{NoteDuration, NoteDurationKind, Rhythm, RhythmSegment, TupletDisplay},
+    time::{Duration, Time},
+    units::WholeNotes,
+};
+
+pub enum MusicXmlError {
+    InvalidXml(roxmltree::Error),
+    NoPart,
+    NoNotes,
+    InvalidValue { element: String, value: String },
+    // the durations and tuplet ratios multiplied together do not fit in the duration arithmetic
+    TooLarge { element: String },
+}
+
+// numbers in the file are limited to this, which is far more than any real score needs
+const MAX_NUMBER: u32 = 1 << 20;
+
+impl Display for MusicXmlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MusicXmlError::InvalidXml(err) => write!(f, "invalid xml: {err} (compressed .mxl files are not supported)"),
+            MusicXmlError::NoPart => write!(f, "no part found, only partwise MusicXML is supported"),
+            MusicXmlError::NoNotes => write!(f, "the part has no notes or rests that could be imported"),
+            MusicXmlError::InvalidValue { element, value } => write!(f, "invalid value in <{element}>: \"{value}\" (should be from 1 to {MAX_NUMBER})"),
+            MusicXmlError::TooLarge { element } => write!(f, "the duration of a <{element}> is too large or too fine to import, together with its tuplets and divisions"),
+        }
+    }
+}
+
+// something in the file that could not be imported exactly and was left out or changed
+pub struct MusicXmlDiagnostic {
+    pub measure: String,
+    pub message: String,
+}
+
+impl Display for MusicXmlDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "measure {}: {}", self.measure, self.message)
+    }
+}
+
+pub struct ImportedRhythm {
+    pub rhythm: Rhythm,
+    pub diagnostics: Vec<MusicXmlDiagnostic>,
+}
+
+struct OpenTuplet {
+    // the number of the <tuplet> that started this tuplet, or None if it was only implied by <time-modification>
+    number: Option<String>,
+    actual: u32,
+    normal: u32,
+    note_duration: Option<NoteDuration>,
+    display: Option<TupletDisplay>,
+    segments: Vec<RhythmSegment>,
+}
+
+impl OpenTuplet {
+    fn is_full(&self) -> bool {
+        self.note_duration.is_some_and(|note_duration| self.segments.iter().map(|segment| segment.duration()).sum::<Duration<WholeNotes>>() >= note_duration.to_duration() * Ratio::from_integer(self.actual as i32))
+    }
+}
+
+struct Reader {
+    measure: String,
+    divisions: u32,
+    voice: Option<String>,
+    staff: Option<String>,
+    // the staff that was asked for, whose first voice is read while the other staves are skipped
+    only_staff: Option<String>,
+    segments: Vec<RhythmSegment>,
+    open_tuplets: Vec<OpenTuplet>,
+    diagnostics: Vec<MusicXmlDiagnostic>,
+}
+
+// reads the first part of a partwise MusicXML score as a single rhythm, using the first voice that appears in it
+pub fn from_musicxml(xml: &str) -> Result<ImportedRhythm, MusicXmlError> {
+    read_staff(xml, 0, None)
+}
+
+// reads the first voice of one staff of a part, or of the whole part if no staff is given
+pub(super) fn read_staff(xml: &str, part_index: usize, staff: Option<&str>) -> Result<ImportedRhythm, MusicXmlError> {
+    // musicxml files almost always start with a doctype
+    let document = roxmltree::Document::parse_with_options(xml, roxmltree::ParsingOptions { allow_dtd: true, ..Default::default() }).map_err(MusicXmlError::InvalidXml)?;
+    let root = document.root_element();
+    let mut parts = root.children().filter(|node| node.has_tag_name("part"));
+    let part = parts.nth(part_index).ok_or(MusicXmlError::NoPart)?;
+
+    let mut reader = Reader { measure: String::new(), divisions: 1, voice: None, staff: None, only_staff: staff.map(str::to_string), segments: Vec::new(), open_tuplets: Vec::new(), diagnostics: Vec::new() };
+    if part_index == 0 && parts.next().is_some() {
+        reader.diagnose("only the first part is imported");
+    }
+
+    for measure in part.children().filter(|node| node.has_tag_name("measure")) {
+        reader.measure = measure.attribute("number").unwrap_or("?").to_string();
+        for element in measure.children().filter(Node::is_element) {
+            match element.tag_name().name() {
+                "attributes" => {
+                    if let Some(divisions) = child(element, "divisions") {
+                        reader.divisions = parse_number(divisions)?;
+                    }
+                    if reader.only_staff.is_none() && child(element, "staves").is_some_and(|staves| staves.text() != Some("1")) {
+                        reader.diagnose("only the first staff is imported");
+                    }
+                }
+                "note" => reader.read_note(element)?,
+                "forward" => reader.read_forward(element)?,
+                // backups are only used to write other voices, which are skipped anyway
+                "backup" | "direction" | "print" | "barline" | "sound" | "harmony" => {}
+                other => reader.diagnose(&format!("<{other}> is not supported and was skipped")),
+            }
+        }
+    }
+
+    while !reader.open_tuplets.is_empty() {
+        reader.diagnose("tuplet was not closed");
+        reader.close_tuplet();
+    }
+
+    if reader.segments.is_empty() {
+        Err(MusicXmlError::NoNotes)
+    } else {
+        Ok(ImportedRhythm { rhythm: Rhythm::new(reader.segments), diagnostics: reader.diagnostics })
+    }
+}
+
+impl Reader {
+    fn diagnose(&mut self, message: &str) {
+        self.diagnostics.push(MusicXmlDiagnostic { measure: self.measure.clone(), message: message.to_string() });
+    }
+
+    fn current_segments(&mut self) -> &mut Vec<RhythmSegment> {
+        match self.open_tuplets.last_mut() {
+            Some(tuplet) => &mut tuplet.segments,
+            None => &mut self.segments,
+        }
+    }
+
+    fn read_note(&mut self, note: Node) -> Result<(), MusicXmlError> {
+        let staff = child(note, "staff").and_then(|staff| staff.text()).map(str::to_string);
+        if self.only_staff.is_some() && staff != self.only_staff {
+            return Ok(());
+        }
+        if child(note, "grace").is_some() {
+            self.diagnose("grace notes are not supported and were skipped");
+            return Ok(());
+        }
+        if child(note, "cue").is_some() {
+            self.diagnose("cue notes are not supported and were skipped");
+            return Ok(());
+        }
+        if child(note, "chord").is_some() {
+            self.diagnose("chords are imported as a single note");
+            return Ok(());
+        }
+
+        let voice = child(note, "voice").and_then(|voice| voice.text()).map(str::to_string);
+        if self.voice.is_none() && self.staff.is_none() {
+            self.voice = voice.clone();
+            self.staff = staff.clone();
+        }
+        if voice != self.voice || staff != self.staff {
+            self.diagnose("only the first voice is imported");
+            return Ok(());
+        }
+
+        let is_rest = child(note, "rest").is_some();
+        let time_modification = match child(note, "time-modification") {
+            Some(time_modification) => Some((time_modification, child_number(time_modification, "actual-notes")?.unwrap_or(1), child_number(time_modification, "normal-notes")?.unwrap_or(1))),
+            None => None,
+        };
+        let notations: Vec<Node> = note.children().filter(|node| node.has_tag_name("notations")).flat_map(|notations| notations.children().filter(Node::is_element)).collect();
+
+        for tuplet in notations.iter().filter(|node| node.has_tag_name("tuplet") && node.attribute("type") == Some("start")) {
+            let implied = self.implied_ratio(time_modification)?;
+            let actual = child(*tuplet, "tuplet-actual").map(|node| child_number(node, "tuplet-number")).transpose()?.flatten().unwrap_or(*implied.numer() as u32);
+            let normal = child(*tuplet, "tuplet-normal").map(|node| child_number(node, "tuplet-number")).transpose()?.flatten().unwrap_or(*implied.denom() as u32);
+            let tuplet_type = child(*tuplet, "tuplet-normal").and_then(note_duration_of_tuplet_type);
+            let display = match (tuplet.attribute("show-number"), tuplet_type) {
+                (Some("both"), Some(note_duration)) if tuplet.attribute("show-type").is_some_and(|show_type| show_type != "none") => TupletDisplay::RatioWithNote(actual, normal, note_duration),
+                (Some("both"), _) => TupletDisplay::Ratio(actual, normal),
+                _ => TupletDisplay::Number(actual),
+            };
+            let note_duration = tuplet_type.or_else(|| normal_type(note));
+            self.open_tuplets.push(OpenTuplet { number: Some(tuplet.attribute("number").unwrap_or("1").to_string()), actual, normal, note_duration, display: Some(display), segments: Vec::new() });
+        }
+
+        // tuplets that are only written with <time-modification> are closed once they are full
+        // this includes ones that stretch notes out, like duplets in compound meters
+        let implied = self.implied_ratio(time_modification)?;
+        if time_modification.is_some() && implied != Ratio::from_integer(1) {
+            let note_duration = normal_type(note).or_else(|| written_duration(note));
+            self.open_tuplets.push(OpenTuplet { number: None, actual: *implied.numer() as u32, normal: *implied.denom() as u32, note_duration, display: None, segments: Vec::new() });
+        }
+
+        let written = match written_duration(note) {
+            Some(written) => Some(written),
+            None => {
+                // notes without a <type> (like whole measure rests) get their duration from <duration> instead
+                let Some(duration) = self.sounding_duration(note)? else {
+                    self.diagnose("note without a type or duration was skipped");
+                    return Ok(());
+                };
+                let segments = segments_for_duration(duration, is_rest);
+                self.current_segments().extend(segments);
+                None
+            }
+        };
+
+        if let Some(written) = written {
+            let tie_stop = note.children().chain(notations.iter().copied()).any(|node| (node.has_tag_name("tie") || node.has_tag_name("tied")) && node.attribute("type") == Some("stop"));
+            let in_tuplet = !self.open_tuplets.is_empty();
+            let segments = self.current_segments();
+            let untied = match (is_rest, tie_stop, segments.last_mut()) {
+                (true, _, _) => {
+                    segments.push(RhythmSegment::Rest(written));
+                    None
+                }
+                (false, true, Some(last @ RhythmSegment::Note(_))) => {
+                    if let RhythmSegment::Note(previous) = *last {
+                        *last = RhythmSegment::TiedNote(vec![previous, written]);
+                    }
+                    None
+                }
+                (false, true, Some(RhythmSegment::TiedNote(previous))) => {
+                    previous.push(written);
+                    None
+                }
+                (false, true, last) => {
+                    let reason = match last {
+                        Some(RhythmSegment::Rest(_) | RhythmSegment::FreeRest(_)) => "tie after a rest was ignored",
+                        Some(RhythmSegment::FreeNote(_)) => "tie from a note without a written duration is not supported, the notes were left untied",
+                        Some(RhythmSegment::Tuplet { .. }) => "tie out of a tuplet is not supported, the notes were left untied",
+                        None if in_tuplet => "tie into a tuplet is not supported, the notes were left untied",
+                        _ => "tie without a note before it was ignored",
+                    };
+                    segments.push(RhythmSegment::Note(written));
+                    Some(reason)
+                }
+                (false, false, _) => {
+                    segments.push(RhythmSegment::Note(written));
+                    None
+                }
+            };
+            if let Some(reason) = untied {
+                self.diagnose(reason);
+            }
+        }
+
+        for tuplet in notations.iter().filter(|node| node.has_tag_name("tuplet") && node.attribute("type") == Some("stop")) {
+            let number = tuplet.attribute("number").unwrap_or("1");
+            match self.open_tuplets.iter().rposition(|open| open.number.as_deref() == Some(number)) {
+                Some(index) => {
+                    while self.open_tuplets.len() > index {
+                        self.close_tuplet();
+                    }
+                }
+                None => self.diagnose("tuplet stop without a matching start was ignored"),
+            }
+        }
+        while self.open_tuplets.last().is_some_and(|open| open.number.is_none() && open.is_full()) {
+            self.close_tuplet();
+        }
+
+        Ok(())
+    }
+
+    // <forward> moves the voice on without writing anything, which is the same as a rest in a single voice
+    fn read_forward(&mut self, forward: Node) -> Result<(), MusicXmlError> {
+        let voice = child(forward, "voice").and_then(|voice| voice.text()).map(str::to_string);
+        let staff = child(forward, "staff").and_then(|staff| staff.text()).map(str::to_string);
+        if (voice.is_some() && self.voice.is_some() && voice != self.voice) || (staff.is_some() && self.only_staff.is_some() && staff != self.only_staff) {
+            return Ok(());
+        }
+        match self.sounding_duration(forward)? {
+            Some(duration) => {
+                let segments = segments_for_duration(duration, true);
+                self.current_segments().extend(segments);
+                self.diagnose("<forward> was imported as a rest");
+            }
+            None => self.diagnose("<forward> without a duration was skipped"),
+        }
+        Ok(())
+    }
+
+    // (actual, normal) of all open tuplets multiplied together
+    fn open_ratio(&self, element: Node) -> Result<(i32, i32), MusicXmlError> {
+        self.open_tuplets.iter().try_fold((1, 1), |(actual, normal), open| Ok((checked_product(element, &[actual, open.actual as i32])?, checked_product(element, &[normal, open.normal as i32])?)))
+    }
+
+    // the ratio of a note's <time-modification> to the tuplets that are already open, which is the tuplet the note starts if it is not 1
+    fn implied_ratio(&self, time_modification: Option<(Node, u32, u32)>) -> Result<Ratio<i32>, MusicXmlError> {
+        let Some((element, actual, normal)) = time_modification else {
+            return Ok(Ratio::from_integer(1));
+        };
+        let (open_actual, open_normal) = self.open_ratio(element)?;
+        Ok(Ratio::new(checked_product(element, &[actual as i32, open_normal])?, checked_product(element, &[normal as i32, open_actual])?))
+    }
+
+    // the <duration> of a note or forward in whole notes, inside the open tuplets
+    fn sounding_duration(&self, element: Node) -> Result<Option<Duration<WholeNotes>>, MusicXmlError> {
+        let Some(duration) = child_number(element, "duration")? else {
+            return Ok(None);
+        };
+        let (open_actual, open_normal) = self.open_ratio(element)?;
+        let whole_notes = Ratio::new(checked_product(element, &[duration as i32, open_actual])?, checked_product(element, &[self.divisions as i32, 4, open_normal])?);
+        Ok(Some(Duration(WholeNotes(whole_notes))))
+    }
+
+    fn close_tuplet(&mut self) {
+        let open = self.open_tuplets.pop().expect("there should be an open tuplet to close");
+        let rhythm = Rhythm::new(open.segments);
+        let note_duration = open.note_duration.filter(|note_duration| note_duration.to_duration() * Ratio::from_integer(open.actual as i32) == rhythm.duration()).or_else(|| NoteDuration::from_duration(rhythm.duration() / Ratio::from_integer(open.actual as i32)));
+
+        match note_duration.map(|note_duration| RhythmSegment::new_tuplet(open.actual, open.normal, note_duration, open.display, rhythm.clone())) {
+            Some(Ok(tuplet)) => self.current_segments().push(tuplet),
+            _ => {
+                // the notes are kept at the right times even if the tuplet cannot be written
+                self.diagnose(&format!("incomplete {}:{} tuplet was imported as free durations", open.actual, open.normal));
+                let scale = Ratio::new(open.normal as i32, open.actual as i32);
+                let events = flatten_rhythm(&rhythm);
+                let end = Time::ZERO + rhythm.duration();
+                let next_times = events.iter().skip(1).map(|ev| ev.time).chain(std::iter::once(end));
+                let free: Vec<RhythmSegment> = events
+                    .iter()
+                    .zip(next_times)
+                    .map(|(ev, next_time)| {
+                        let duration = Duration(next_time.0 - ev.time.0) * scale;
+                        match ev.kind {
+                            EventKind::Start => RhythmSegment::FreeNote(duration),
+                            EventKind::Stop => RhythmSegment::FreeRest(duration),
+                        }
+                    })
+                    .collect();
+                self.current_segments().extend(free);
+            }
+        }
+    }
+}
+
+fn segments_for_duration(duration: Duration<WholeNotes>, is_rest: bool) -> Vec<RhythmSegment> {
+    match (NoteDuration::split_duration(duration), is_rest) {
+        (Some(rests), true) => rests.into_iter().map(RhythmSegment::Rest).collect(),
+        (Some(notes), false) if notes.len() == 1 => vec![RhythmSegment::Note(notes[0])],
+        (Some(notes), false) => vec![RhythmSegment::TiedNote(notes)],
+        (None, true) => vec![RhythmSegment::FreeRest(duration)],
+        (None, false) => vec![RhythmSegment::FreeNote(duration)],
+    }
+}
+
+fn checked_product(element: Node, factors: &[i32]) -> Result<i32, MusicXmlError> {
+    factors.iter().try_fold(1i32, |product, &factor| product.checked_mul(factor)).ok_or_else(|| MusicXmlError::TooLarge { element: element.tag_name().name().to_string() })
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+fn parse_number(node: Node) -> Result<u32, MusicXmlError> {
+    let text = node.text().unwrap_or("").trim();
+    text.parse().ok().filter(|number| (1..=MAX_NUMBER).contains(number)).ok_or_else(|| MusicXmlError::InvalidValue { element: node.tag_name().name().to_string(), value: text.to_string() })
+}
+
+fn child_number(node: Node, name: &str) -> Result<Option<u32>, MusicXmlError> {
+    child(node, name).map(parse_number).transpose()
+}
+
+fn note_duration_kind(node: Node) -> Option<NoteDurationKind> {
+    match node.text()?.trim() {
+        "whole" => Some(NoteDurationKind::Whole),
+        "half" => Some(NoteDurationKind::Half),
+        "quarter" => Some(NoteDurationKind::Quarter),
+        "eighth" => Some(NoteDurationKind::Eigth),
+        "16th" => Some(NoteDurationKind::Sixteenth),
+        "32nd" => Some(NoteDurationKind::Nd32),
+        "64th" => Some(NoteDurationKind::Nd64),
+        "128th" => Some(NoteDurationKind::Nd128),
+        "256th" => Some(NoteDurationKind::Nd256),
+        "512th" => Some(NoteDurationKind::Nd512),
+        "1024th" => Some(NoteDurationKind::Nd1024),
+        _ => None,
+    }
+}
+
+fn dots(node: Node, name: &str) -> u8 {
+    (node.children().filter(|child| child.has_tag_name(name)).count() as u8).min(NoteDuration::MAX_DOTS)
+}
+
+// the written <type> and <dot>s of a note
+fn written_duration(note: Node) -> Option<NoteDuration> {
+    Some(NoteDuration { kind: note_duration_kind(child(note, "type")?)?, dots: dots(note, "dot") })
+}
+
+// the <normal-type> of a note's <time-modification>, which is the note value the tuplet is counted in
+fn normal_type(note: Node) -> Option<NoteDuration> {
+    let time_modification = child(note, "time-modification")?;
+    Some(NoteDuration { kind: note_duration_kind(child(time_modification, "normal-type")?)?, dots: dots(time_modification, "normal-dot") })
+}
+
+fn note_duration_of_tuplet_type(tuplet_normal: Node) -> Option<NoteDuration> {
+    Some(NoteDuration { kind: note_duration_kind(child(tuplet_normal, "tuplet-type")?)?, dots: dots(tuplet_normal, "tuplet-dot") })
+}
@@ -0,0 +1,128 @@
+use std::{fs, path::PathBuf};
+
+use super::{from_musicxml, import::read_staff, parts, to_musicxml};
+use crate::parse;
+
+// every tests/musicxml/<name>.txt is exported and compared with tests/musicxml/<name>.musicxml
+// run with UPDATE_EXPECTED=1 to write the expected files from the current output instead
+fn fixtures() -> Vec<(String, PathBuf)> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/musicxml");
+    let mut sources: Vec<_> = fs::read_dir(&dir)
+        .expect("could not read musicxml fixtures")
+        .map(|entry| entry.expect("could not read musicxml fixture").path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "txt"))
+        .collect();
+    sources.sort();
+    assert!(!sources.is_empty(), "no musicxml fixtures in {}", dir.display());
+    sources.into_iter().map(|path| (fs::read_to_string(&path).expect("could not read musicxml fixture source"), path)).collect()
+}
+
+fn parse_fixture(source: &str, path: &std::path::Path) -> crate::polyrhythm::Polyrhythm {
+    parse::parse(source).unwrap_or_else(|err| panic!("{} does not parse: {}", path.display(), parse::parse_error_text(&err)))
+}
+
+#[test]
+fn exports_fixtures() {
+    for (source, path) in fixtures() {
+        let xml = to_musicxml(&parse_fixture(&source, &path));
+        let expected_path = path.with_extension("musicxml");
+        if std::env::var_os("UPDATE_EXPECTED").is_some() {
+            fs::write(&expected_path, &xml).expect("could not write expected musicxml");
+            continue;
+        }
+        let expected = fs::read_to_string(&expected_path).unwrap_or_else(|err| panic!("could not read {}: {err}", expected_path.display()));
+        assert_eq!(xml, expected, "{} does not export as expected", path.display());
+    }
+}
+
+// every staff of every part is imported back on its own
+#[test]
+fn imports_exported_fixtures() {
+    for (source, path) in fixtures() {
+        let polyrhythm = parse_fixture(&source, &path);
+        let xml = to_musicxml(&polyrhythm);
+        for (part_i, part) in parts(&polyrhythm).iter().enumerate() {
+            for (staff_i, rhythm) in part.staves.iter().enumerate() {
+                let staff = (part.staves.len() > 1).then(|| (staff_i + 1).to_string());
+                let imported = read_staff(&xml, part_i, staff.as_deref()).unwrap_or_else(|err| panic!("{} part {} staff {} does not import: {err}", path.display(), part_i + 1, staff_i + 1));
+                let diagnostics: Vec<_> = imported.diagnostics.iter().map(|diagnostic| diagnostic.message.as_str()).filter(|message| *message != "only the first part is imported").collect();
+                assert!(diagnostics.is_empty(), "{} part {} staff {} imports with {diagnostics:?}", path.display(), part_i + 1, staff_i + 1);
+                assert_eq!(imported.rhythm.to_string(), rhythm.to_string(), "{} part {} staff {} does not import as it was exported", path.display(), part_i + 1, staff_i + 1);
+            }
+        }
+    }
+}
+
+// a single part with one measure, written by hand rather than exported
+fn score(divisions: &str, notes: &str) -> String {
+    format!(r#"<score-partwise version="4.0"><part-list><score-part id="P1"><part-name>p</part-name></score-part></part-list><part id="P1"><measure number="1"><attributes><divisions>{divisions}</divisions></attributes>{notes}</measure></part></score-partwise>"#)
+}
+
+fn import(xml: &str) -> (String, Vec<String>) {
+    match from_musicxml(xml) {
+        Ok(imported) => (imported.rhythm.to_string(), imported.diagnostics.iter().map(|diagnostic| diagnostic.message.clone()).collect()),
+        Err(err) => panic!("could not import: {err}"),
+    }
+}
+
+fn import_error(xml: &str) -> String {
+    match from_musicxml(xml) {
+        Ok(imported) => panic!("imported {} instead of failing", imported.rhythm),
+        Err(err) => err.to_string(),
+    }
+}
+
+const QUARTER: &str = "<note><unpitched/><duration>2</duration><voice>1</voice><type>quarter</type></note>";
+
+#[test]
+fn imports_forward_as_rest() {
+    let (rhythm, diagnostics) = import(&score("2", &format!("{QUARTER}<forward><duration>2</duration></forward>{QUARTER}")));
+    assert_eq!(rhythm, "{4 r4 4}");
+    assert_eq!(diagnostics, ["<forward> was imported as a rest"]);
+}
+
+#[test]
+fn skips_forward_in_other_voices() {
+    let (rhythm, diagnostics) = import(&score("2", &format!("{QUARTER}<forward><duration>2</duration><voice>2</voice></forward>{QUARTER}")));
+    assert_eq!(rhythm, "{4 4}");
+    assert!(diagnostics.is_empty(), "{diagnostics:?}");
+}
+
+#[test]
+fn ignores_tie_after_rest() {
+    let tied = r#"<note><unpitched/><duration>2</duration><tie type="stop"/><voice>1</voice><type>quarter</type><notations><tied type="stop"/></notations></note>"#;
+    let (rhythm, diagnostics) = import(&score("2", &format!("<note><rest/><duration>2</duration><voice>1</voice><type>quarter</type></note>{tied}")));
+    assert_eq!(rhythm, "{r4 4}");
+    assert_eq!(diagnostics, ["tie after a rest was ignored"]);
+}
+
+#[test]
+fn imports_duration_without_type() {
+    let (rhythm, diagnostics) = import(&score("2", "<note><rest/><duration>6</duration><voice>1</voice></note>"));
+    assert_eq!(rhythm, "r2.");
+    assert!(diagnostics.is_empty(), "{diagnostics:?}");
+}
+
+#[test]
+fn rejects_bad_durations() {
+    assert_eq!(import_error(&score("0", QUARTER)), "invalid value in <divisions>: \"0\" (should be from 1 to 1048576)");
+    assert_eq!(import_error(&score("2", "<note><rest/><duration>-2</duration></note>")), "invalid value in <duration>: \"-2\" (should be from 1 to 1048576)");
+    assert_eq!(import_error(&score("2", "<note><rest/><duration>4294967296</duration></note>")), "invalid value in <duration>: \"4294967296\" (should be from 1 to 1048576)");
+    let zero_normal = "<note><unpitched/><duration>1</duration><type>eighth</type><time-modification><actual-notes>3</actual-notes><normal-notes>0</normal-notes></time-modification></note>";
+    assert_eq!(import_error(&score("2", zero_normal)), "invalid value in <normal-notes>: \"0\" (should be from 1 to 1048576)");
+    // each number is allowed, but multiplied together they overflow
+    let huge = "<note><rest/><duration>1048576</duration><time-modification><actual-notes>1048576</actual-notes><normal-notes>1</normal-notes></time-modification></note>";
+    assert!(import_error(&score("1048576", huge)).starts_with("the duration of a <"));
+}
+
+#[test]
+fn limits_tuplet_numbers() {
+    // every tuplet is as long as the eighth it replaces inside the next one out
+    let nested = (0..8).fold("8".to_string(), |inner, _| format!("tuplet 3/2 (16) {{{inner} 16}}"));
+    let polyrhythm = parse_fixture(&format!("tempo 4 = 60; {{{nested}}};"), std::path::Path::new("nested tuplets"));
+    let xml = to_musicxml(&polyrhythm);
+    let numbers: Vec<usize> = xml.split(r#"<tuplet type="start" number=""#).skip(1).map(|rest| rest[..rest.find('"').unwrap()].parse().unwrap()).collect();
+    assert_eq!(numbers, [1, 2, 3, 4, 5, 6, 6, 6]);
+    let imported = from_musicxml(&xml).unwrap_or_else(|err| panic!("could not import: {err}"));
+    assert_eq!(imported.rhythm.to_string(), polyrhythm.rhythms[0].original.notated().unwrap().to_string());
+}
@@ -2,13 +2,32 @@ use lalrpop_util::lalrpop_mod;
 use wasm_bindgen::JsCast;
 use web_sys::{Document, HtmlDivElement, HtmlParagraphElement};
 
-use crate::{polyrhythm::Polyrhythm, rhythm::TupletInnerDurationMismatch};
+use num_rational::Ratio;
+
+use crate::{
+    polyrhythm::{InvalidPolyComponent, Original, Polyrhythm, Pulse, RhythmLine, StaffPosition},
+    resultant::{self, ResultantError},
+    rhythm::{InvalidEuclideanRhythm, InvalidTupletRatio, NoteDuration, Rhythm, RhythmSegment, SpannedSegment, TupletError, TupletInnerDurationMismatch},
+    time::{Duration, Time},
+    units::{Seconds, WholeNotes},
+};
 
 lalrpop_mod!(grammar);
 
 pub enum RhythmError {
     TupletInnerDurationMismatch(TupletInnerDurationMismatch),
+    InvalidTupletRatio(InvalidTupletRatio),
     InvalidNoteDuration(u32),
+    TooManyDots(usize),
+    InvalidFractionalDuration(u32, u32),
+    InvalidAbsoluteDuration(u32),
+    InvalidTempo(u32),
+    InvalidPolyComponent(u32),
+    InvalidEuclideanRhythm(InvalidEuclideanRhythm),
+    InvalidSubdivision(u32),
+    EmptyPulse,
+    InvalidStaffPosition(StaffPosition),
+    InvalidResultant(ResultantError),
 }
 
 impl From<TupletInnerDurationMismatch> for RhythmError {
@@ -17,26 +36,262 @@ impl From<TupletInnerDurationMismatch> for RhythmError {
     }
 }
 
+impl From<InvalidTupletRatio> for RhythmError {
+    fn from(v: InvalidTupletRatio) -> Self {
+        Self::InvalidTupletRatio(v)
+    }
+}
+
+impl From<TupletError> for RhythmError {
+    fn from(v: TupletError) -> Self {
+        match v {
+            TupletError::InnerDurationMismatch(e) => e.into(),
+            TupletError::InvalidRatio(e) => e.into(),
+        }
+    }
+}
+
+impl From<InvalidEuclideanRhythm> for RhythmError {
+    fn from(v: InvalidEuclideanRhythm) -> Self {
+        Self::InvalidEuclideanRhythm(v)
+    }
+}
+
+impl From<ResultantError> for RhythmError {
+    fn from(v: ResultantError) -> Self {
+        Self::InvalidResultant(v)
+    }
+}
+
+impl From<InvalidPolyComponent> for RhythmError {
+    fn from(InvalidPolyComponent(component): InvalidPolyComponent) -> Self {
+        Self::InvalidPolyComponent(component)
+    }
+}
+
+// durations in milliseconds can only be converted to whole notes once the tempo is known, so lines are converted after the whole polyrhythm is parsed
+pub struct ParsedRhythmLine {
+    pub name: Option<String>,
+    pub original: ParsedOriginal,
+    pub approximations: Vec<Rhythm>,
+    pub approximation_names: Vec<Option<String>>,
+    pub pulse: Option<Pulse>,
+    pub position: Option<StaffPosition>,
+}
+pub enum ParsedOriginal {
+    Notated(Rhythm),
+    Absolute(Vec<AbsoluteSegment>),
+    Recorded(Vec<Time<Seconds>>),
+}
+
+pub struct AbsoluteSegment {
+    pub is_rest: bool,
+    pub millis: u32,
+    pub span: (usize, usize),
+}
+
+impl ParsedRhythmLine {
+    pub fn resolve(self, tempo: (NoteDuration, u32)) -> Result<RhythmLine, RhythmError> {
+        let original = match self.original {
+            ParsedOriginal::Notated(rhythm) => Original::Notated(rhythm),
+            ParsedOriginal::Recorded(mut onsets) => {
+                onsets.sort();
+                Original::Recorded(onsets)
+            }
+            ParsedOriginal::Absolute(segments) => Original::Notated(Rhythm {
+                segments: segments
+                    .into_iter()
+                    .map(|AbsoluteSegment { is_rest, millis, span }| {
+                        let duration = Duration(millis_to_whole_notes(millis, tempo).ok_or(RhythmError::InvalidAbsoluteDuration(millis))?);
+                        let segment = if is_rest { RhythmSegment::FreeRest(duration) } else { RhythmSegment::FreeNote(duration) };
+                        Ok::<_, RhythmError>(SpannedSegment { segment, span: Some(span) })
+                    })
+                    .collect::<Result<_, _>>()?,
+            }),
+        };
+        Ok(RhythmLine { name: self.name, original, approximations: self.approximations, approximation_names: self.approximation_names, pulse: self.pulse, position: self.position })
+    }
+}
+
+// None if the duration does not fit in a ratio of i32s, which long durations at fast tempos do not
+fn millis_to_whole_notes(millis: u32, tempo: (NoteDuration, u32)) -> Option<WholeNotes> {
+    let beat = tempo.0.to_duration().0.0;
+    let whole_notes = Ratio::new(i64::from(millis) * i64::from(tempo.1) * i64::from(*beat.numer()), 60_000 * i64::from(*beat.denom()));
+    Some(WholeNotes(Ratio::new(i32::try_from(*whole_notes.numer()).ok()?, i32::try_from(*whole_notes.denom()).ok()?)))
+}
+
+// the resultant is drawn from the parsed polyrhythm, so it is checked here to report it like any other error instead of leaving its staff out
+pub fn check_resultant(polyrhythm: Polyrhythm) -> Result<Polyrhythm, RhythmError> {
+    if polyrhythm.show_resultant {
+        resultant::resultant(&polyrhythm)?;
+    }
+    Ok(polyrhythm)
+}
+
 pub fn parse(code: &str) -> Result<Polyrhythm, lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'_>, RhythmError>> {
     grammar::PolyrhythmParser::new().parse(code)
 }
 
 pub fn parse_error_to_div(document: &Document, error: lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'_>, RhythmError>) -> HtmlDivElement {
-    let div: HtmlDivElement = document.create_element("div").expect("could not create div for error").dyn_into().expect("div should be able to be casted into div");
-    let p: HtmlParagraphElement = document.create_element("p").expect("could not create p for error").dyn_into().expect("p should be able to be casted into p");
+    error_text_to_div(document, &parse_error_text(&error))
+}
 
-    let error_text = match error {
+// the range of the source code that the error is about, if it is known
+pub fn parse_error_span(error: &lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'_>, RhythmError>) -> Option<(usize, usize)> {
+    match error {
+        lalrpop_util::ParseError::InvalidToken { location } | lalrpop_util::ParseError::UnrecognizedEof { location, expected: _ } => Some((*location, *location)),
+        lalrpop_util::ParseError::UnrecognizedToken { token: (start, _, end), expected: _ } | lalrpop_util::ParseError::ExtraToken { token: (start, _, end) } => Some((*start, *end)),
+        lalrpop_util::ParseError::User { error: _ } => None,
+    }
+}
+
+pub fn parse_error_text(error: &lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'_>, RhythmError>) -> String {
+    match error {
         lalrpop_util::ParseError::InvalidToken { location } => format!("invalid token at {location}"),
         lalrpop_util::ParseError::UnrecognizedEof { location, expected } => format!("unexpected eof at {location}; expected {}", expected.join(", ")),
         lalrpop_util::ParseError::UnrecognizedToken { token: (start, token, end), expected } => format!("unexpected token {token} at {start}-{end}; expected {}", expected.join(", ")),
         lalrpop_util::ParseError::ExtraToken { token: (start, token, end) } => format!("extra token {token} at {start}-{end}"),
         lalrpop_util::ParseError::User { error } => match error {
             RhythmError::TupletInnerDurationMismatch(TupletInnerDurationMismatch { actual, expected }) => format!("tuplet inner duration mismatch: expected duration of {expected} but got {actual}"),
+            RhythmError::InvalidTupletRatio(InvalidTupletRatio { actual, normal }) => format!("invalid tuplet ratio: {actual}/{normal} (both numbers should be positive)"),
             RhythmError::InvalidNoteDuration(duration) => format!("invalid note duration: {duration} (should be a power of 2)"),
+            RhythmError::TooManyDots(dots) => format!("too many dots: {dots} (should be at most {})", NoteDuration::MAX_DOTS),
+            RhythmError::InvalidFractionalDuration(numer, denom) => format!("invalid fractional duration: {numer}/{denom} (both numbers should be positive)"),
+            RhythmError::InvalidAbsoluteDuration(millis) => format!("invalid duration: {millis}ms (should be positive and not too long for the tempo)"),
+            RhythmError::InvalidTempo(bpm) => format!("invalid tempo: {bpm} (should be from {} to {})", Polyrhythm::TEMPO.start(), Polyrhythm::TEMPO.end()),
+            RhythmError::InvalidEuclideanRhythm(InvalidEuclideanRhythm { pulses, steps }) => format!("invalid euclidean rhythm: cannot fit {pulses} pulses into {steps} steps (steps should be from 1 to {})", RhythmSegment::MAX_GENERATED_NOTES),
+            RhythmError::InvalidPolyComponent(component) => format!("invalid polyrhythm component: {component} (should be from 1 to {}, and divide the span into notes)", RhythmSegment::MAX_GENERATED_NOTES),
+            RhythmError::InvalidSubdivision(subdivisions) => format!("invalid subdivision: {subdivisions} (should be from {} to {})", Pulse::SUBDIVISIONS.start(), Pulse::SUBDIVISIONS.end()),
+            RhythmError::EmptyPulse => "invalid pulse: it has no notes, so it cannot repeat".to_string(),
+            RhythmError::InvalidStaffPosition(position) => format!("invalid staff position: {position} (a five-line staff has lines 1 to 5 and spaces 1 to 4)"),
+            RhythmError::InvalidResultant(ResultantError::NoNotatedRhythm) => "invalid resultant: every line is recorded, so there are no notated onsets to combine".to_string(),
+            RhythmError::InvalidResultant(ResultantError::Unnotatable) => "invalid resultant: the combined onsets cannot be notated with one level of tuplets per beat".to_string(),
         },
-    };
-    p.set_text_content(Some(&error_text));
+    }
+}
+
+pub fn error_text_to_div(document: &Document, error_text: &str) -> HtmlDivElement {
+    let div: HtmlDivElement = document.create_element("div").expect("could not create div for error").dyn_into().expect("div should be able to be casted into div");
+    let p: HtmlParagraphElement = document.create_element("p").expect("could not create p for error").dyn_into().expect("p should be able to be casted into p");
+
+    p.set_text_content(Some(error_text));
     div.replace_children_with_node_1(&p);
 
     div
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rhythm::{NoteDurationKind, TupletDisplay};
+
+    fn error(code: &str) -> String {
+        match parse(code) {
+            Ok(_) => panic!("{code:?} should not parse"),
+            Err(error) => parse_error_text(&error),
+        }
+    }
+
+    fn original(code: &str) -> Rhythm {
+        let polyrhythm = parse(code).unwrap_or_else(|error| panic!("could not parse {code:?}: {}", parse_error_text(&error)));
+        match &polyrhythm.rhythms[0].original {
+            Original::Notated(rhythm) => rhythm.clone(),
+            Original::Recorded(_) => panic!("{code:?} should be notated"),
+        }
+    }
+
+    #[test]
+    fn converts_millis_at_the_tempo() {
+        // a quarter note lasts 500ms at 120 beats per minute
+        let rhythm = original("tempo 4 = 120; {500ms r250ms 1250ms};");
+        let durations: Vec<_> = rhythm.segments.iter().map(|spanned| spanned.segment.duration()).collect();
+        assert!(durations == [Duration(WholeNotes(Ratio::new(1, 4))), Duration(WholeNotes(Ratio::new(1, 8))), Duration(WholeNotes(Ratio::new(5, 8)))]);
+        assert!(matches!(rhythm.segments[1].segment, RhythmSegment::FreeRest(_)));
+    }
+
+    #[test]
+    fn keeps_the_span_of_every_segment() {
+        let code = "tempo 4 = 60; {4 euclid(1, 2, 8) tuplet 3/2 (8) {8 r8 8}};";
+        let rhythm = original(code);
+        let spans: Vec<_> = rhythm.segments.iter().map(|spanned| spanned.span.map(|(start, end)| &code[start..end])).collect();
+        assert_eq!(spans, [Some("4"), Some("euclid(1, 2, 8)"), Some("euclid(1, 2, 8)"), Some("tuplet 3/2 (8) {8 r8 8}")]);
+        let RhythmSegment::Tuplet { rhythm: inner, .. } = &rhythm.segments[3].segment else { panic!("the last segment should be a tuplet") };
+        let inner_spans: Vec<_> = inner.segments.iter().map(|spanned| spanned.span.map(|(start, end)| &code[start..end])).collect();
+        assert_eq!(inner_spans, [Some("8"), Some("r8"), Some("8")]);
+        assert!(original("tempo 4 = 120; {500ms r250ms};").segments.iter().all(|spanned| spanned.span.is_some()));
+    }
+
+    #[test]
+    fn rejects_zero_and_fast_tempos() {
+        assert_eq!(error("tempo 4 = 0; 500ms;"), "invalid tempo: 0 (should be from 1 to 1000)");
+        assert_eq!(error("tempo 4 = 1001; 4;"), "invalid tempo: 1001 (should be from 1 to 1000)");
+    }
+
+    #[test]
+    fn rejects_too_many_poly_notes() {
+        assert_eq!(error("tempo 4 = 60; poly 3:257 over 1;"), "invalid polyrhythm component: 257 (should be from 1 to 256, and divide the span into notes)");
+    }
+
+    #[test]
+    fn rejects_euclidean_rhythms_that_do_not_fit() {
+        assert_eq!(error("tempo 4 = 60; euclid(9, 8, 8);"), "invalid euclidean rhythm: cannot fit 9 pulses into 8 steps (steps should be from 1 to 256)");
+        assert_eq!(error("tempo 4 = 60; euclid(1, 4294967295, 8);"), "invalid euclidean rhythm: cannot fit 1 pulses into 4294967295 steps (steps should be from 1 to 256)");
+    }
+
+    #[test]
+    fn rejects_millis_that_do_not_fit() {
+        assert_eq!(error("tempo 4 = 60; 0ms;"), "invalid duration: 0ms (should be positive and not too long for the tempo)");
+        assert_eq!(error("tempo 4 = 60; 4294967295ms;"), "invalid duration: 4294967295ms (should be positive and not too long for the tempo)");
+        // fits in an i32 as milliseconds, but not as whole notes at this tempo
+        assert_eq!(error("tempo 4 = 7; 2147483647ms;"), "invalid duration: 2147483647ms (should be positive and not too long for the tempo)");
+    }
+
+    #[test]
+    fn rejects_staff_positions_off_the_staff() {
+        for position in ["line 0", "line 6", "space 0", "space 5"] {
+            assert_eq!(error(&format!("tempo 4 = 60; staff percussion; {{4}} at {position};")), format!("invalid staff position: {position} (a five-line staff has lines 1 to 5 and spaces 1 to 4)"));
+        }
+        let polyrhythm = parse("tempo 4 = 60; staff percussion; {4} at line 5; {4} at space 1; {4};").unwrap_or_else(|error| panic!("could not parse staff positions: {}", parse_error_text(&error)));
+        let positions: Vec<_> = polyrhythm.rhythms.iter().map(|line| line.position).collect();
+        assert!(positions == [Some(StaffPosition::Line(5)), Some(StaffPosition::Space(1)), None]);
+    }
+
+    fn tuplet_display(code: &str) -> TupletDisplay {
+        match &original(code).segments[0].segment {
+            RhythmSegment::Tuplet { display, .. } => *display,
+            segment => panic!("{code:?} should start with a tuplet, not {segment}"),
+        }
+    }
+
+    #[test]
+    fn rejects_tuplets_with_zero_or_huge_numbers() {
+        assert_eq!(error("tempo 4 = 60; tuplet 0/3 (8) {8};"), "invalid tuplet ratio: 0/3 (both numbers should be positive)");
+        assert_eq!(error("tempo 4 = 60; tuplet 3/0 (8) {8 8 8};"), "invalid tuplet ratio: 3/0 (both numbers should be positive)");
+        assert_eq!(error("tempo 4 = 60; tuplet 3000000000/2 (8) {8 8 8};"), "invalid tuplet ratio: 3000000000/2 (both numbers should be positive)");
+    }
+
+    #[test]
+    fn checks_the_inside_of_nested_tuplets() {
+        assert_eq!(error("tempo 4 = 60; tuplet 3/2 (8) {8 8 8 8};"), "tuplet inner duration mismatch: expected duration of 3/8 whole notes long but got 1/2 whole notes long");
+        assert_eq!(error("tempo 4 = 60; tuplet 3/2 (4) {tuplet 3/2 (8) {8 8} 4 4};"), "tuplet inner duration mismatch: expected duration of 3/8 whole notes long but got 1/4 whole notes long");
+
+        // a triplet of eighths in the space of a quarter, inside a triplet of quarters in the space of a half
+        let rhythm = original("tempo 4 = 60; tuplet 3/2 (4) {tuplet 3/2 (8) {8 8 8} 4 4};");
+        assert!(rhythm.duration() == Duration(WholeNotes(Ratio::new(1, 2))));
+        let onsets: Vec<_> = crate::polyrhythm::flatten_rhythm(&rhythm).iter().map(|event| event.time.0 .0).collect();
+        assert_eq!(onsets, [0, 1, 2, 3, 6].map(|eighteenths| Ratio::new(eighteenths, 18)));
+    }
+
+    #[test]
+    fn displays_tuplets_independently_of_their_ratio() {
+        let eighth = NoteDuration { kind: NoteDurationKind::Eigth, dots: 0 };
+        assert!(tuplet_display("tempo 4 = 60; tuplet 3/2 (8) {8 8 8};") == TupletDisplay::Number(3));
+        assert!(tuplet_display("tempo 4 = 60; tuplet 3/2 (8) [5] {8 8 8};") == TupletDisplay::Number(5));
+        assert!(tuplet_display("tempo 4 = 60; tuplet 3/2 (8) [3:2] {8 8 8};") == TupletDisplay::Ratio(3, 2));
+        assert!(tuplet_display("tempo 4 = 60; tuplet 6/4 (16) [3:2 8] {16 16 16 16 16 16};") == TupletDisplay::RatioWithNote(3, 2, eighth));
+        // the display is only written back when it is not the default
+        assert_eq!(original("tempo 4 = 60; tuplet 3/2 (8) [3] {8 8 8};").to_string(), "tuplet 3/2 (8) {8 8 8}");
+        assert_eq!(original("tempo 4 = 60; tuplet 6/4 (16) [3:2 8] {16 16 16 16 16 16};").to_string(), "tuplet 6/4 (16) [3:2 8] {16 16 16 16 16 16}");
+    }
+}
+
@@ -1,24 +1,219 @@
 use crate::{
-    rhythm::{NoteDuration, Rhythm},
+    rhythm::{NoteDuration, NoteDurationKind, Rhythm, RhythmSegment, SpannedSegment},
     time::Time,
     units::{Seconds, WholeNotes},
 };
 use num_rational::Ratio;
-use num_traits::ConstZero;
+use num_traits::{ConstZero, ToPrimitive};
+use std::fmt::Display;
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Polyrhythm {
     pub tempo: (NoteDuration, u32),
-    pub pulse: Option<Rhythm>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub staff: StaffStyle,
+    pub pulse: Option<Pulse>,
+    pub poly: Option<PolyRatio>,
+    pub show_resultant: bool,
+    // the first approximation of every line is drawn on the staff of the original, below it
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub overlay: bool,
     pub rhythms: Vec<RhythmLine>,
 }
 
+impl Polyrhythm {
+    // beats per minute; a zero tempo has no duration for a second, and a faster one would not be played anyway
+    pub const TEMPO: std::ops::RangeInclusive<u32> = 1..=1000;
+
+    // whether the line shares its staff with its first approximation
+    pub fn overlays(&self, line: &RhythmLine) -> bool {
+        self.overlay && !line.approximations.is_empty()
+    }
+
+    // parsed polyrhythms always start with the lines generated from the poly ratio, but ones from json might not
+    pub fn starts_with_generated_lines(&self) -> bool {
+        match &self.poly {
+            Some(poly) => match poly.generate_lines() {
+                Ok(generated) => generated.len() <= self.rhythms.len() && generated.iter().zip(&self.rhythms).all(|(generated, line)| generated.to_string() == line.to_string()),
+                Err(_) => false,
+            },
+            None => true,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RhythmLine {
-    pub original: Rhythm,
+    // the label of the original's staff
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub name: Option<String>,
+    pub original: Original,
     pub approximations: Vec<Rhythm>,
+    // in the same order as the approximations
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub approximation_names: Vec<Option<String>>,
+    // a pulse that is only drawn across this line's staves
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub pulse: Option<Pulse>,
+    // where the noteheads of the line and its approximations are on percussion staves, which is the middle line if not given
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub position: Option<StaffPosition>,
+}
+
+// the staff that every rhythm is drawn on
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum StaffStyle {
+    #[default]
+    OneLine,
+    // five lines, with the noteheads of every line at its own position and the rests on the middle line
+    Percussion,
+}
+
+// a line or space of a five-line staff, numbered from the bottom like lines 1 to 5 and spaces 1 to 4
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum StaffPosition {
+    Line(u32),
+    Space(u32),
+}
+
+impl StaffPosition {
+    pub fn is_on_staff(self) -> bool {
+        match self {
+            StaffPosition::Line(line) => (1..=5).contains(&line),
+            StaffPosition::Space(space) => (1..=4).contains(&space),
+        }
+    }
+
+    // in half staff spaces above the middle line
+    pub fn steps(self) -> i32 {
+        match self {
+            StaffPosition::Line(line) => 2 * line as i32 - 6,
+            StaffPosition::Space(space) => 2 * space as i32 - 5,
+        }
+    }
+}
+
+impl RhythmLine {
+    // lines without a name are labelled with their number, counting from 1, in exports that need a name for every part
+    pub fn label(&self, line_i: usize) -> String {
+        self.name.clone().unwrap_or_else(|| format!("line {}", line_i + 1))
+    }
+
+    // approximations without a name are labelled with their number
+    pub fn approximation_label(&self, approx_i: usize) -> String {
+        self.approximation_names.get(approx_i).cloned().flatten().unwrap_or_else(|| format!("approx {}", approx_i + 1))
+    }
+}
+
+// a pulse is drawn as lines across the staves that it applies to, repeated every cycle until the end of the score
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pulse {
+    pub rhythm: Rhythm,
+    // every pulse is divided into this many parts by lighter lines
+    pub subdivisions: u32,
+    // the first pulse of every cycle is drawn stronger
+    pub downbeats: bool,
+}
+
+impl Pulse {
+    // more subdivisions than this would not be told apart anyway
+    pub const SUBDIVISIONS: std::ops::RangeInclusive<u32> = 1..=32;
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Original {
+    Notated(Rhythm),
+    // onset times from a recorded performance, which have no notated durations
+    Recorded(Vec<Time<Seconds>>),
+}
+
+impl Original {
+    pub fn notated(&self) -> Option<&Rhythm> {
+        match self {
+            Original::Notated(rhythm) => Some(rhythm),
+            Original::Recorded(_) => None,
+        }
+    }
+
+    pub fn flatten(&self, tempo: (NoteDuration, u32)) -> Vec<Event> {
+        match self {
+            Original::Notated(rhythm) => flatten_rhythm(rhythm),
+            Original::Recorded(onsets) => onsets.iter().map(|onset| Event { time: Time(onset.0.to_whole_notes(tempo)), kind: EventKind::Start }).collect(),
+        }
+    }
+
+    // for recorded originals, this is the time of the last onset
+    pub fn end(&self, tempo: (NoteDuration, u32)) -> Time<WholeNotes> {
+        match self {
+            Original::Notated(rhythm) => Time::ZERO + rhythm.duration(),
+            Original::Recorded(onsets) => onsets.last().map(|onset| Time(onset.0.to_whole_notes(tempo))).unwrap_or(Time::ZERO),
+        }
+    }
+}
+
+// a polyrhythm written as a ratio like 5:4:3, where every component divides the same span into that many equal notes
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PolyRatio {
+    pub components: Vec<u32>,
+    pub span: NoteDuration,
+}
+
+pub struct InvalidPolyComponent(pub u32);
+
+impl PolyRatio {
+    pub fn generate_lines(&self) -> Result<Vec<RhythmLine>, InvalidPolyComponent> {
+        self.components.iter().map(|&component| Ok(RhythmLine { name: None, original: Original::Notated(self.generate_rhythm(component)?), approximations: Vec::new(), approximation_names: Vec::new(), pulse: None, position: None })).collect()
+    }
+
+    fn generate_rhythm(&self, component: u32) -> Result<Rhythm, InvalidPolyComponent> {
+        if component == 0 || component > RhythmSegment::MAX_GENERATED_NOTES {
+            return Err(InvalidPolyComponent(component));
+        }
+
+        let span = self.span.to_duration();
+        let component_ratio = Ratio::from_integer(component as i32);
+
+        // the notes are written with the shortest note value that is still at least as long as span / component (so 3 over a whole note becomes half note triplets),
+        // shortened further if the span is not a whole number of that note value (which can happen if the span is dotted)
+        let shortest_filling = NoteDurationKind::ALL
+            .iter()
+            .rposition(|&kind| NoteDuration { kind, dots: 0 }.to_duration().0 .0 * component_ratio >= span.0 .0)
+            .ok_or(InvalidPolyComponent(component))?;
+        let note_duration = NoteDurationKind::ALL[shortest_filling..]
+            .iter()
+            .map(|&kind| NoteDuration { kind, dots: 0 })
+            .find(|note| (span.0 .0 / note.to_duration().0 .0).is_integer())
+            .ok_or(InvalidPolyComponent(component))?;
+        let normal = (span.0 .0 / note_duration.to_duration().0 .0).to_integer() as u32;
+
+        let notes = vec![RhythmSegment::Note(note_duration); component as usize];
+        if normal == component {
+            Ok(Rhythm::new(notes))
+        } else {
+            let tuplet = RhythmSegment::new_tuplet(component, normal, note_duration, None, Rhythm::new(notes)).unwrap_or_else(|_| unreachable!("tuplet of {component} notes should always fill {component} notes"));
+            Ok(Rhythm::new(vec![tuplet]))
+        }
+    }
+
+    // every time where any of the components has an onset, in order and without duplicates
+    pub fn composite_grid(&self) -> Vec<Time<WholeNotes>> {
+        let span = self.span.to_duration();
+        let mut grid: Vec<_> = self
+            .components
+            .iter()
+            .filter(|&&component| component != 0)
+            .flat_map(|&component| (0..component).map(move |i| Time::ZERO + span * Ratio::new(i as i32, component as i32)))
+            .collect();
+        grid.sort();
+        grid.dedup();
+        grid
+    }
 }
 
-pub fn score_error(tempo: (NoteDuration, u32), original: &Rhythm, approx: &Rhythm) -> Seconds {
-    let o = flatten_rhythm(original);
+pub fn score_error(tempo: (NoteDuration, u32), original: &Original, approx: &Rhythm) -> Seconds {
+    let o = original.flatten(tempo);
     let a = flatten_rhythm(approx);
 
     let mut err = o
@@ -42,11 +237,35 @@ pub fn score_error(tempo: (NoteDuration, u32), original: &Rhythm, approx: &Rhyth
     err
 }
 
+// an event of an approximation and the event of the original that it is paired with, in the same way as in score_error
+pub struct EventDeviation {
+    pub original: Time<WholeNotes>,
+    pub approximated: Time<WholeNotes>,
+    pub original_seconds: Time<Seconds>,
+    pub approximated_seconds: Time<Seconds>,
+    // approximated minus original, in milliseconds
+    pub millis: f64,
+}
+
+pub fn event_deviations(tempo: (NoteDuration, u32), original: &Original, approx: &Rhythm) -> Vec<EventDeviation> {
+    original
+        .flatten(tempo)
+        .iter()
+        .zip(flatten_rhythm(approx))
+        .map(|(o_ev, a_ev)| {
+            let original_seconds = Time(o_ev.time.0.to_seconds(tempo));
+            let approximated_seconds = Time(a_ev.time.0.to_seconds(tempo));
+            EventDeviation { original: o_ev.time, approximated: a_ev.time, original_seconds, approximated_seconds, millis: (approximated_seconds - original_seconds).0 .0.to_f64().unwrap() * 1000.0 }
+        })
+        .collect()
+}
+
 pub struct Event {
     pub kind: EventKind,
     pub time: Time<WholeNotes>,
 }
 #[derive(PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "snake_case"))]
 pub enum EventKind {
     Start,
     Stop,
@@ -57,7 +276,7 @@ pub fn flatten_rhythm(r: &Rhythm) -> Vec<Event> {
 
     let mut events = Vec::new();
 
-    for segment in &r.segments {
+    for SpannedSegment { segment, span: _ } in &r.segments {
         match segment {
             crate::rhythm::RhythmSegment::Note(_) => {
                 events.push(Event { time: current_time, kind: EventKind::Start });
@@ -71,7 +290,15 @@ pub fn flatten_rhythm(r: &Rhythm) -> Vec<Event> {
                 events.push(Event { time: current_time, kind: EventKind::Stop });
                 current_time += segment.duration();
             }
-            crate::rhythm::RhythmSegment::Tuplet { actual, normal, note_duration: _, rhythm, do_not_construct: _ } => {
+            crate::rhythm::RhythmSegment::FreeNote(_) => {
+                events.push(Event { time: current_time, kind: EventKind::Start });
+                current_time += segment.duration();
+            }
+            crate::rhythm::RhythmSegment::FreeRest(_) => {
+                events.push(Event { time: current_time, kind: EventKind::Stop });
+                current_time += segment.duration();
+            }
+            crate::rhythm::RhythmSegment::Tuplet { actual, normal, note_duration: _, display: _, rhythm, do_not_construct: _ } => {
                 for flattened_subnote in flatten_rhythm(rhythm).into_iter() {
                     events.push(Event { time: flattened_subnote.time * Ratio::new(*normal as i32, *actual as i32) + current_time, kind: flattened_subnote.kind })
                 }
@@ -82,3 +309,147 @@ pub fn flatten_rhythm(r: &Rhythm) -> Vec<Event> {
 
     events
 }
+
+// polyrhythms are displayed in the same syntax that they are parsed from
+impl Display for Polyrhythm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "tempo {} = {};", self.tempo.0, self.tempo.1)?;
+        if self.staff == StaffStyle::Percussion {
+            writeln!(f, "staff percussion;")?;
+        }
+        if let Some(pulse) = &self.pulse {
+            writeln!(f, "pulse {pulse};")?;
+        }
+        // lines generated from the poly ratio come first and are generated again when parsing
+        // if they are not there, the ratio is left out instead, so that no line is lost
+        let generated_lines = match &self.poly {
+            Some(poly) if self.starts_with_generated_lines() => {
+                writeln!(f, "poly {} over {};", poly.components.iter().map(|component| component.to_string()).collect::<Vec<_>>().join(":"), poly.span)?;
+                poly.components.len()
+            }
+            _ => 0,
+        };
+        if self.show_resultant {
+            writeln!(f, "resultant;")?;
+        }
+        if self.overlay {
+            writeln!(f, "overlay;")?;
+        }
+        for line in self.rhythms.iter().skip(generated_lines) {
+            writeln!(f, "{line};")?;
+        }
+        Ok(())
+    }
+}
+
+// without the semicolon that ends the line
+impl Display for RhythmLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(name) = &self.name {
+            write!(f, "\"{name}\" ")?;
+        }
+        write!(f, "{}", self.original)?;
+        if let Some(position) = self.position {
+            write!(f, " at {position}")?;
+        }
+        for (approx_i, approx) in self.approximations.iter().enumerate() {
+            match self.approximation_names.get(approx_i).cloned().flatten() {
+                Some(name) => write!(f, " approx \"{name}\" {approx}")?,
+                None => write!(f, " approx {approx}")?,
+            }
+        }
+        if let Some(pulse) = &self.pulse {
+            write!(f, " pulse {pulse}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for StaffPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StaffPosition::Line(line) => write!(f, "line {line}"),
+            StaffPosition::Space(space) => write!(f, "space {space}"),
+        }
+    }
+}
+
+impl Display for Pulse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.rhythm)?;
+        if self.subdivisions > 1 {
+            write!(f, " subdivide {}", self.subdivisions)?;
+        }
+        if self.downbeats {
+            write!(f, " downbeats")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Original {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Original::Notated(rhythm) => write!(f, "{rhythm}"),
+            Original::Recorded(onsets) => write!(f, "recorded {}", onsets.iter().map(|onset| format!("{}s", onset.0 .0.to_f64().unwrap())).collect::<Vec<_>>().join(" ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rhythm::NoteDurationKind;
+
+    fn onsets(rhythm: &Rhythm) -> Vec<Ratio<i32>> {
+        flatten_rhythm(rhythm).into_iter().filter(|event| event.kind == EventKind::Start).map(|event| event.time.0 .0).collect()
+    }
+
+    fn poly(components: &[u32], kind: NoteDurationKind) -> PolyRatio {
+        PolyRatio { components: components.to_vec(), span: NoteDuration { kind, dots: 0 } }
+    }
+
+    #[test]
+    fn counts_staff_positions_from_the_middle_line() {
+        let lines = (1..=5).map(|line| StaffPosition::Line(line).steps());
+        assert_eq!(lines.collect::<Vec<_>>(), [-4, -2, 0, 2, 4]);
+        let spaces = (1..=4).map(|space| StaffPosition::Space(space).steps());
+        assert_eq!(spaces.collect::<Vec<_>>(), [-3, -1, 1, 3]);
+        assert!([StaffPosition::Line(1), StaffPosition::Line(5), StaffPosition::Space(1), StaffPosition::Space(4)].into_iter().all(StaffPosition::is_on_staff));
+        assert!(![StaffPosition::Line(0), StaffPosition::Line(6), StaffPosition::Space(0), StaffPosition::Space(5)].into_iter().any(StaffPosition::is_on_staff));
+        assert_eq!(StaffPosition::Space(2).to_string(), "space 2");
+    }
+
+    #[test]
+    fn generates_three_against_two() {
+        let poly = poly(&[3, 2], NoteDurationKind::Half);
+        let three = poly.generate_rhythm(3).ok().expect("3 should divide a half note");
+        let two = poly.generate_rhythm(2).ok().expect("2 should divide a half note");
+        assert_eq!(three.to_string(), "tuplet 3/2 (4) {4 4 4}");
+        assert_eq!(onsets(&three), [Ratio::new(0, 1), Ratio::new(1, 6), Ratio::new(1, 3)]);
+        assert_eq!(two.to_string(), "{4 4}");
+        assert_eq!(onsets(&two), [Ratio::new(0, 1), Ratio::new(1, 4)]);
+    }
+
+    #[test]
+    fn generates_four_against_three() {
+        let poly = poly(&[4, 3], NoteDurationKind::Whole);
+        let four = poly.generate_rhythm(4).ok().expect("4 should divide a whole note");
+        let three = poly.generate_rhythm(3).ok().expect("3 should divide a whole note");
+        assert_eq!(four.to_string(), "{4 4 4 4}");
+        assert_eq!(onsets(&four), [Ratio::new(0, 1), Ratio::new(1, 4), Ratio::new(1, 2), Ratio::new(3, 4)]);
+        assert_eq!(three.to_string(), "tuplet 3/2 (2) {2 2 2}");
+        assert_eq!(onsets(&three), [Ratio::new(0, 1), Ratio::new(1, 3), Ratio::new(2, 3)]);
+        let grid: Vec<_> = poly.composite_grid().into_iter().map(|time| time.0 .0).collect();
+        assert_eq!(grid, [Ratio::new(0, 1), Ratio::new(1, 4), Ratio::new(1, 3), Ratio::new(1, 2), Ratio::new(2, 3), Ratio::new(3, 4)]);
+    }
+
+    #[test]
+    fn rejects_components_out_of_range() {
+        let poly = poly(&[0], NoteDurationKind::Whole);
+        assert!(matches!(poly.generate_rhythm(0), Err(InvalidPolyComponent(0))));
+        assert!(poly.generate_rhythm(RhythmSegment::MAX_GENERATED_NOTES).is_ok());
+        assert!(matches!(poly.generate_rhythm(RhythmSegment::MAX_GENERATED_NOTES + 1), Err(InvalidPolyComponent(257))));
+        assert!(matches!(poly.generate_rhythm(u32::MAX), Err(InvalidPolyComponent(u32::MAX))));
+    }
+}
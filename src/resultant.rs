@@ -0,0 +1,179 @@
+use num_integer::Integer;
+use num_rational::Ratio;
+use num_traits::ConstZero;
+
+use crate::{
+    polyrhythm::{flatten_rhythm, Event, EventKind, Polyrhythm},
+    rhythm::{NoteDuration, Rhythm, RhythmSegment},
+    time::{Duration, Time},
+    units::WholeNotes,
+};
+
+// the union of the onsets of every notated original rhythm in a polyrhythm
+// (recorded originals are left out because their onsets almost never fall on a notatable subdivision)
+pub struct Resultant {
+    pub rhythm: Rhythm,
+    // for every onset in the resultant, the indexes (into Polyrhythm::rhythms) of the lines that have an onset at that time
+    pub sources: Vec<(Time<WholeNotes>, Vec<usize>)>,
+}
+
+pub enum ResultantError {
+    // every original is recorded, so there are no onsets to merge
+    NoNotatedRhythm,
+    // the merged onsets cannot be notated with a single level of tuplets per beat
+    Unnotatable,
+}
+
+pub fn resultant(polyrhythm: &Polyrhythm) -> Result<Resultant, ResultantError> {
+    let originals: Vec<(usize, &Rhythm)> = polyrhythm.rhythms.iter().enumerate().filter_map(|(line_i, line)| Some((line_i, line.original.notated()?))).collect();
+
+    let length = originals.iter().map(|(_, original)| original.duration()).max().ok_or(ResultantError::NoNotatedRhythm)?;
+    let end = Time::ZERO + length;
+
+    let lines: Vec<(usize, Vec<Event>)> = originals
+        .iter()
+        .map(|(line_i, original)| {
+            let mut events = flatten_rhythm(original);
+            // lines that are shorter than the longest line are silent once they end
+            let line_end = Time::ZERO + original.duration();
+            if line_end < end {
+                events.push(Event { time: line_end, kind: EventKind::Stop });
+            }
+            (*line_i, events)
+        })
+        .collect();
+
+    let mut times: Vec<_> = lines.iter().flat_map(|(_, line)| line).map(|ev| ev.time).collect();
+    times.sort();
+    times.dedup();
+
+    let mut events: Vec<Event> = Vec::new();
+    let mut sources = Vec::new();
+    for time in times {
+        let starting_lines: Vec<usize> = lines.iter().filter(|(_, line)| line.iter().any(|ev| ev.time == time && ev.kind == EventKind::Start)).map(|(line_i, _)| *line_i).collect();
+
+        if !starting_lines.is_empty() {
+            events.push(Event { time, kind: EventKind::Start });
+            sources.push((time, starting_lines));
+        } else {
+            // a rest in one line only shows up in the resultant if every other line is also silent at that point
+            let all_silent = lines.iter().all(|(_, line)| !line.iter().rev().find(|ev| ev.time <= time).is_some_and(|ev| ev.kind == EventKind::Start));
+            let already_resting = events.last().is_some_and(|ev| ev.kind == EventKind::Stop);
+            if all_silent && !already_resting {
+                events.push(Event { time, kind: EventKind::Stop });
+            }
+        }
+    }
+
+    // only the beats with onsets or rests that fall on a non power of 2 subdivision are written in tuplets, and everything in between is written with plain durations
+    let beat = polyrhythm.tempo.0.to_duration();
+    let events_between = |from: Time<WholeNotes>, to: Time<WholeNotes>| &events[events.partition_point(|ev| ev.time < from)..events.partition_point(|ev| ev.time < to)];
+    let mut segments = Vec::new();
+    let mut plain_start = Time::ZERO;
+    for (tuplet_start, tuplet_end) in tuplet_spans(&events, end, beat) {
+        segments.extend(notate_events(events_between(plain_start, tuplet_start), tuplet_start, Ratio::from_integer(1)).ok_or(ResultantError::Unnotatable)?);
+        segments.push(notate_tuplet(events_between(tuplet_start, tuplet_end), tuplet_start, tuplet_end).ok_or(ResultantError::Unnotatable)?);
+        plain_start = tuplet_end;
+    }
+    segments.extend(notate_events(events_between(plain_start, end), end, Ratio::from_integer(1)).ok_or(ResultantError::Unnotatable)?);
+
+    Ok(Resultant { rhythm: Rhythm::new(segments), sources })
+}
+
+fn is_plain(time: Time<WholeNotes>) -> bool {
+    (*time.0 .0.denom() as u32).is_power_of_two()
+}
+
+// the ranges that have to be written in tuplets, in order and without overlaps
+// every range covers whole beats and is widened to the beats around it until it starts and ends on an event (or the start or end), since a note cannot be tied into or out of a tuplet
+fn tuplet_spans(events: &[Event], end: Time<WholeNotes>, beat: Duration<WholeNotes>) -> Vec<(Time<WholeNotes>, Time<WholeNotes>)> {
+    let beat_start = |beat_i: i32| (Time::ZERO + beat * Ratio::from_integer(beat_i)).min(end);
+    let is_boundary = |time: Time<WholeNotes>| time == Time::ZERO || time == end || events.iter().any(|ev| ev.time == time);
+
+    let mut spans: Vec<(Time<WholeNotes>, Time<WholeNotes>)> = Vec::new();
+    // the end is checked too, since the last note ends there
+    let off_grid = events.iter().map(|ev| (ev.time, false)).chain(std::iter::once((end, true))).filter(|(time, _)| !is_plain(*time));
+    for (time, is_end) in off_grid {
+        let beats = (time.0 .0 / beat.0 .0).to_integer();
+        // the end belongs to the beat that it ends
+        let mut first_beat = if is_end && beat_start(beats) == time { beats - 1 } else { beats };
+        let mut last_beat = first_beat + 1;
+        while !is_boundary(beat_start(first_beat)) {
+            first_beat -= 1;
+        }
+        while !is_boundary(beat_start(last_beat)) {
+            last_beat += 1;
+        }
+        let (start, span_end) = (beat_start(first_beat), beat_start(last_beat));
+        match spans.last_mut() {
+            Some((_, last_end)) if start < *last_end => *last_end = span_end.max(*last_end),
+            _ => spans.push((start, span_end)),
+        }
+    }
+    spans
+}
+
+// if the events fall on a non power of 2 subdivision of the span, they are written inside one tuplet that turns those subdivisions into power of 2 ones
+fn notate_tuplet(events: &[Event], start: Time<WholeNotes>, end: Time<WholeNotes>) -> Option<RhythmSegment> {
+    let length = Duration((end - start).0);
+    let denominator = events.iter().map(|ev| ev.time - start).chain(std::iter::once(end - start)).fold(1, |acc, time| acc.lcm(time.0 .0.denom()));
+    let tuplet_actual = denominator >> denominator.trailing_zeros();
+
+    // prefer the tuplet that squeezes notes together (like 3:2 or 5:4) over the one that stretches them out (like 3:4)
+    let smaller_normal = 1 << (i32::BITS - 1 - tuplet_actual.leading_zeros());
+    [smaller_normal, smaller_normal * 2].into_iter().find_map(|tuplet_normal| {
+        let note_duration = NoteDuration::from_duration(length / Ratio::from_integer(tuplet_normal))?;
+        let inner = notate_events(events, end, Ratio::new(tuplet_actual, tuplet_normal))?;
+        RhythmSegment::new_tuplet(tuplet_actual as u32, tuplet_normal as u32, note_duration, None, Rhythm::new(inner)).ok()
+    })
+}
+
+fn notate_events(events: &[Event], end: Time<WholeNotes>, scale: Ratio<i32>) -> Option<Vec<RhythmSegment>> {
+    let mut segments = Vec::new();
+
+    let next_times = events.iter().skip(1).map(|ev| ev.time).chain(std::iter::once(end));
+    for (ev, next_time) in events.iter().zip(next_times) {
+        let notes = NoteDuration::split_duration(Duration(next_time.0 - ev.time.0) * scale)?;
+        match ev.kind {
+            EventKind::Start if notes.len() == 1 => segments.push(RhythmSegment::Note(notes[0])),
+            EventKind::Start => segments.push(RhythmSegment::TiedNote(notes)),
+            EventKind::Stop => segments.extend(notes.into_iter().map(RhythmSegment::Rest)),
+        }
+    }
+
+    Some(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Polyrhythm {
+        crate::parse::parse(source).unwrap_or_else(|err| panic!("could not parse {source:?}: {}", crate::parse::parse_error_text(&err)))
+    }
+
+    fn error(source: &str) -> String {
+        match crate::parse::parse(source) {
+            Ok(_) => panic!("{source:?} should not parse"),
+            Err(err) => crate::parse::parse_error_text(&err),
+        }
+    }
+
+    #[test]
+    fn merges_the_onsets_of_3_against_2() {
+        let Ok(resultant) = resultant(&parse("tempo 4 = 60; poly 3:2 over 1; resultant;")) else { panic!("the resultant of 3:2 should be notatable") };
+        // the triplets and the half notes only line up on the downbeat, and each half of the bar is a tuplet of its own
+        assert_eq!(resultant.rhythm.to_string(), "{tuplet 3/2 (4) {2 4} tuplet 3/2 (4) {4 2}}");
+        let sources: Vec<_> = resultant.sources.iter().map(|(time, lines)| (time.0 .0, lines.clone())).collect();
+        assert_eq!(sources, [(Ratio::new(0, 1), vec![0, 1]), (Ratio::new(1, 3), vec![0]), (Ratio::new(1, 2), vec![1]), (Ratio::new(2, 3), vec![0])]);
+    }
+
+    #[test]
+    fn reports_resultants_that_cannot_be_drawn() {
+        assert_eq!(error("tempo 4 = 60; poly 31:29 over 4; resultant;"), "invalid resultant: the combined onsets cannot be notated with one level of tuplets per beat");
+        assert_eq!(error("tempo 4 = 60; resultant; recorded 0s 1s;"), "invalid resultant: every line is recorded, so there are no notated onsets to combine");
+        // without a resultant, the same lines are fine
+        parse("tempo 4 = 60; poly 31:29 over 4;");
+        parse("tempo 4 = 60; recorded 0s 1s;");
+    }
+}
@@ -1,13 +1,18 @@
+use std::fmt::Display;
+
 use num_rational::Ratio;
+use num_traits::{ConstZero, ToPrimitive};
 
 use crate::{time::Duration, units::WholeNotes};
 
 #[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(try_from = "crate::json::NoteDurationRepr"))]
 pub struct NoteDuration {
     pub kind: NoteDurationKind,
-    pub dotted: bool,
+    pub dots: u8,
 }
 #[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
 pub enum NoteDurationKind {
     Whole,
     Half,
@@ -23,11 +28,75 @@ pub enum NoteDurationKind {
 }
 
 impl NoteDuration {
+    pub const MAX_DOTS: u8 = 3;
+
     pub fn to_duration(self) -> Duration<WholeNotes> {
-        Duration::WHOLE_NOTE * self.kind.to_ratio().into() * if self.dotted { Ratio::new(3, 2) } else { Ratio::new(1, 1) }
+        // every dot adds half of the previous value, so n dots multiply the duration by 2 - 1/2^n
+        let dot_multiplier = Ratio::new((1 << (self.dots + 1)) - 1, 1 << self.dots);
+        Duration::WHOLE_NOTE * self.kind.to_ratio().into() * dot_multiplier
+    }
+
+    fn all() -> impl Iterator<Item = NoteDuration> {
+        NoteDurationKind::ALL.into_iter().flat_map(|kind| (0..=NoteDuration::MAX_DOTS).map(move |dots| NoteDuration { kind, dots }))
+    }
+
+    // the single note duration that is exactly this long, if there is one
+    pub fn from_duration(duration: Duration<WholeNotes>) -> Option<NoteDuration> {
+        NoteDuration::all().find(|note| note.to_duration() == duration)
+    }
+
+    // the single note duration that is closest to this duration, used to draw durations that cannot be notated exactly
+    pub fn nearest(duration: Duration<WholeNotes>) -> NoteDuration {
+        let target = duration.0 .0.to_f64().unwrap().log2();
+        NoteDuration::all()
+            .min_by(|a, b| {
+                let a_distance = (a.to_duration().0 .0.to_f64().unwrap().log2() - target).abs();
+                let b_distance = (b.to_duration().0 .0.to_f64().unwrap().log2() - target).abs();
+                a_distance.total_cmp(&b_distance)
+            })
+            .expect("there should be at least one note duration")
+    }
+
+    // splits a duration into the fewest note durations that add up to it, longest first, so that they can be written as tied notes or consecutive rests
+    // returns None if the duration cannot be written with the available note durations
+    pub fn split_duration(duration: Duration<WholeNotes>) -> Option<Vec<NoteDuration>> {
+        if let Some(note) = NoteDuration::from_duration(duration) {
+            return Some(vec![note]);
+        }
+
+        let mut remaining = duration;
+        let mut notes = Vec::new();
+        for kind in NoteDurationKind::ALL {
+            let note = NoteDuration { kind, dots: 0 };
+            while remaining >= note.to_duration() {
+                notes.push(note);
+                remaining -= note.to_duration();
+            }
+        }
+
+        if remaining == Duration::ZERO && !notes.is_empty() {
+            Some(notes)
+        } else {
+            None
+        }
     }
 }
 impl NoteDurationKind {
+    // ordered from longest to shortest
+    pub const ALL: [NoteDurationKind; 11] = [
+        NoteDurationKind::Whole,
+        NoteDurationKind::Half,
+        NoteDurationKind::Quarter,
+        NoteDurationKind::Eigth,
+        NoteDurationKind::Sixteenth,
+        NoteDurationKind::Nd32,
+        NoteDurationKind::Nd64,
+        NoteDurationKind::Nd128,
+        NoteDurationKind::Nd256,
+        NoteDurationKind::Nd512,
+        NoteDurationKind::Nd1024,
+    ];
+
     pub fn to_ratio(self) -> Ratio<i32> {
         match self {
             NoteDurationKind::Whole => Ratio::new(1, 1),
@@ -63,16 +132,28 @@ impl NoteDurationKind {
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Rhythm {
-    pub segments: Vec<RhythmSegment>,
+    pub segments: Vec<SpannedSegment>,
+}
+// a segment with the byte range of the source code that it was written with, which is None for rhythms that were not parsed
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(from = "RhythmSegment", into = "RhythmSegment"))]
+pub struct SpannedSegment {
+    pub segment: RhythmSegment,
+    pub span: Option<(usize, usize)>,
 }
 #[derive(Clone)]
 pub struct DoNotConstruct(());
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(try_from = "crate::json::RhythmSegmentRepr", into = "crate::json::RhythmSegmentRepr"))]
 pub enum RhythmSegment {
     Note(NoteDuration),
     TiedNote(Vec<NoteDuration>),
     Rest(NoteDuration),
+    // notes and rests with durations that do not have to be expressible as a note duration, like 3/16 or a duration in milliseconds
+    FreeNote(Duration<WholeNotes>),
+    FreeRest(Duration<WholeNotes>),
     Tuplet {
         // TODO: incomplete tuplets
         // a tuplet where `actual` number of notes are found in the space that there would normally be `normal` notes
@@ -81,24 +162,86 @@ pub enum RhythmSegment {
         actual: u32,
         normal: u32,
         note_duration: NoteDuration,
+        // how the tuplet is labeled, which does not have to match how it is computed
+        display: TupletDisplay,
         rhythm: Box<Rhythm>,
         do_not_construct: DoNotConstruct,
     },
 }
 
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum TupletDisplay {
+    // shown as "3"
+    Number(u32),
+    // shown as "3:2"
+    Ratio(u32, u32),
+    // shown as "3:2♪"
+    RatioWithNote(u32, u32, NoteDuration),
+}
+
+pub enum TupletError {
+    InnerDurationMismatch(TupletInnerDurationMismatch),
+    InvalidRatio(InvalidTupletRatio),
+}
+
 pub struct TupletInnerDurationMismatch {
     pub actual: Duration<WholeNotes>,
     pub expected: Duration<WholeNotes>,
 }
 
+pub struct InvalidTupletRatio {
+    pub actual: u32,
+    pub normal: u32,
+}
+
+pub struct InvalidEuclideanRhythm {
+    pub pulses: u32,
+    pub steps: u32,
+}
+
 impl RhythmSegment {
-    pub fn new_tuplet(actual: u32, normal: u32, note_duration: NoteDuration, rhythm: Rhythm) -> Result<RhythmSegment, TupletInnerDurationMismatch> {
+    // the most notes that a single generated rhythm (like a polyrhythm component) can have, so that a typo cannot fill the page or the memory
+    pub const MAX_GENERATED_NOTES: u32 = 256;
+
+    // a euclidean rhythm: `pulses` notes spread as evenly as possible over `steps` steps of length `duration` (using bjorklund's algorithm),
+    // with the pattern rotated `rotation` steps to the left
+    pub fn euclidean(pulses: u32, steps: u32, duration: NoteDuration, rotation: u32) -> Result<Vec<RhythmSegment>, InvalidEuclideanRhythm> {
+        if steps == 0 || steps > RhythmSegment::MAX_GENERATED_NOTES || pulses > steps {
+            return Err(InvalidEuclideanRhythm { pulses, steps });
+        }
+
+        // repeatedly pair up the groups that start with a pulse with the remaining groups until there is at most one remaining group
+        let mut groups: Vec<Vec<bool>> = vec![vec![true]; pulses as usize];
+        let mut remainders: Vec<Vec<bool>> = vec![vec![false]; (steps - pulses) as usize];
+        while remainders.len() > 1 && !groups.is_empty() {
+            let pairs = groups.len().min(remainders.len());
+            let leftover = if groups.len() > pairs { groups.split_off(pairs) } else { remainders.split_off(pairs) };
+            for (group, remainder) in groups.iter_mut().zip(remainders) {
+                group.extend(remainder);
+            }
+            remainders = leftover;
+        }
+
+        let mut pattern: Vec<bool> = groups.into_iter().chain(remainders).flatten().collect();
+        pattern.rotate_left((rotation % steps) as usize);
+
+        Ok(pattern.into_iter().map(|is_pulse| if is_pulse { RhythmSegment::Note(duration) } else { RhythmSegment::Rest(duration) }).collect())
+    }
+
+    // if display is None, the tuplet is labeled with just the number of notes in it
+    pub fn new_tuplet(actual: u32, normal: u32, note_duration: NoteDuration, display: Option<TupletDisplay>, rhythm: Rhythm) -> Result<RhythmSegment, TupletError> {
+        let is_valid_number = |number: u32| number != 0 && i32::try_from(number).is_ok();
+        if !is_valid_number(actual) || !is_valid_number(normal) {
+            return Err(TupletError::InvalidRatio(InvalidTupletRatio { actual, normal }));
+        }
+
         let actual_inner_duration = rhythm.duration();
         let expected_inner_duration = note_duration.to_duration() * Ratio::from_integer(actual as i32);
         if actual_inner_duration == expected_inner_duration {
-            Ok(RhythmSegment::Tuplet { actual, normal, note_duration, rhythm: Box::new(rhythm), do_not_construct: DoNotConstruct(()) })
+            Ok(RhythmSegment::Tuplet { actual, normal, note_duration, display: display.unwrap_or(TupletDisplay::Number(actual)), rhythm: Box::new(rhythm), do_not_construct: DoNotConstruct(()) })
         } else {
-            Err(TupletInnerDurationMismatch { actual: actual_inner_duration, expected: expected_inner_duration })
+            Err(TupletError::InnerDurationMismatch(TupletInnerDurationMismatch { actual: actual_inner_duration, expected: expected_inner_duration }))
         }
     }
 
@@ -107,13 +250,152 @@ impl RhythmSegment {
             RhythmSegment::Note(dur) => dur.to_duration(),
             RhythmSegment::TiedNote(durs) => durs.iter().copied().map(NoteDuration::to_duration).sum(),
             RhythmSegment::Rest(dur) => dur.to_duration(),
-            RhythmSegment::Tuplet { actual: _, normal, note_duration, rhythm: _, do_not_construct: _ } => note_duration.to_duration() * Ratio::from_integer(*normal as i32),
+            RhythmSegment::FreeNote(dur) | RhythmSegment::FreeRest(dur) => *dur,
+            RhythmSegment::Tuplet { actual: _, normal, note_duration, display: _, rhythm: _, do_not_construct: _ } => note_duration.to_duration() * Ratio::from_integer(*normal as i32),
         }
     }
 }
 
 impl Rhythm {
+    // a rhythm that was not parsed, so none of its segments have spans
+    pub fn new(segments: Vec<RhythmSegment>) -> Rhythm {
+        Rhythm { segments: segments.into_iter().map(SpannedSegment::from).collect() }
+    }
+
     pub fn duration(&self) -> Duration<WholeNotes> {
-        self.segments.iter().map(|s| s.duration()).sum()
+        self.segments.iter().map(|s| s.segment.duration()).sum()
+    }
+}
+
+impl From<RhythmSegment> for SpannedSegment {
+    fn from(segment: RhythmSegment) -> Self {
+        SpannedSegment { segment, span: None }
     }
 }
+
+impl From<SpannedSegment> for RhythmSegment {
+    fn from(spanned: SpannedSegment) -> Self {
+        spanned.segment
+    }
+}
+
+// rhythms are displayed in the same syntax that they are parsed from
+impl Display for NoteDuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.kind.to_ratio().denom(), ".".repeat(self.dots as usize))
+    }
+}
+
+impl Display for TupletDisplay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TupletDisplay::Number(actual) => write!(f, "[{actual}]"),
+            TupletDisplay::Ratio(actual, normal) => write!(f, "[{actual}:{normal}]"),
+            TupletDisplay::RatioWithNote(actual, normal, note_duration) => write!(f, "[{actual}:{normal} {note_duration}]"),
+        }
+    }
+}
+
+impl Display for RhythmSegment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RhythmSegment::Note(dur) => write!(f, "{dur}"),
+            RhythmSegment::TiedNote(durs) => write!(f, "{}", durs.iter().map(|dur| dur.to_string()).collect::<Vec<_>>().join(" ~ ")),
+            RhythmSegment::Rest(dur) => write!(f, "r{dur}"),
+            RhythmSegment::FreeNote(dur) => write!(f, "{}/{}", dur.0 .0.numer(), dur.0 .0.denom()),
+            RhythmSegment::FreeRest(dur) => write!(f, "r{}/{}", dur.0 .0.numer(), dur.0 .0.denom()),
+            RhythmSegment::Tuplet { actual, normal, note_duration, display, rhythm, do_not_construct: _ } => {
+                write!(f, "tuplet {actual}/{normal} ({note_duration}) ")?;
+                if *display != TupletDisplay::Number(*actual) {
+                    write!(f, "{display} ")?;
+                }
+                // the rhythm inside a tuplet always has to be a single segment, so it is always braced
+                write!(f, "{{{}}}", rhythm.segments.iter().map(|spanned| spanned.segment.to_string()).collect::<Vec<_>>().join(" "))
+            }
+        }
+    }
+}
+
+impl Display for Rhythm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.segments[..] {
+            [spanned] => write!(f, "{}", spanned.segment),
+            segments => write!(f, "{{{}}}", segments.iter().map(|spanned| spanned.segment.to_string()).collect::<Vec<_>>().join(" ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EIGHTH: NoteDuration = NoteDuration { kind: NoteDurationKind::Eigth, dots: 0 };
+
+    // x for a note and . for a rest
+    fn pattern(pulses: u32, steps: u32, rotation: u32) -> String {
+        let segments = RhythmSegment::euclidean(pulses, steps, EIGHTH, rotation).ok().unwrap_or_else(|| panic!("E({pulses},{steps}) should be valid"));
+        segments
+            .iter()
+            .map(|segment| match segment {
+                RhythmSegment::Note(duration) if *duration == EIGHTH => 'x',
+                RhythmSegment::Rest(duration) if *duration == EIGHTH => '.',
+                segment => panic!("unexpected segment {segment}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn spreads_euclidean_pulses() {
+        assert_eq!(pattern(3, 8, 0), "x..x..x.");
+        assert_eq!(pattern(5, 8, 0), "x.xx.xx.");
+        assert_eq!(pattern(3, 8, 1), "..x..x.x");
+        assert_eq!(pattern(3, 8, 9), pattern(3, 8, 1));
+    }
+
+    #[test]
+    fn fills_euclidean_edge_cases() {
+        assert_eq!(pattern(0, 4, 0), "....");
+        assert_eq!(pattern(4, 4, 0), "xxxx");
+        assert_eq!(pattern(1, RhythmSegment::MAX_GENERATED_NOTES, 0).len(), 256);
+    }
+
+    #[test]
+    fn rejects_invalid_euclidean_rhythms() {
+        for (pulses, steps) in [(5, 4), (0, 0), (1, RhythmSegment::MAX_GENERATED_NOTES + 1)] {
+            assert!(matches!(RhythmSegment::euclidean(pulses, steps, EIGHTH, 0), Err(InvalidEuclideanRhythm { pulses: p, steps: s }) if p == pulses && s == steps));
+        }
+    }
+
+    fn parsed(code: &str) -> Rhythm {
+        let polyrhythm = crate::parse::parse(code).unwrap_or_else(|err| panic!("could not parse {code:?}: {}", crate::parse::parse_error_text(&err)));
+        polyrhythm.rhythms[0].original.notated().expect("the line should be notated").clone()
+    }
+
+    #[test]
+    fn adds_half_of_the_previous_value_for_every_dot() {
+        let quarter = |dots| NoteDuration { kind: NoteDurationKind::Quarter, dots }.to_duration().0 .0;
+        assert_eq!([0, 1, 2, 3].map(quarter), [Ratio::new(1, 4), Ratio::new(3, 8), Ratio::new(7, 16), Ratio::new(15, 32)]);
+        // the smallest note with the most dots is still a fraction of i32s
+        assert_eq!(NoteDuration { kind: NoteDurationKind::Nd1024, dots: NoteDuration::MAX_DOTS }.to_duration().0 .0, Ratio::new(15, 8192));
+        assert!(NoteDuration::from_duration(Duration(WholeNotes(Ratio::new(7, 16)))) == Some(NoteDuration { kind: NoteDurationKind::Quarter, dots: 2 }));
+    }
+
+    #[test]
+    fn parses_dotted_notes_and_rests() {
+        let rhythm = parsed("tempo 4 = 60; {2. 4.. r8... 16};");
+        // 3/4 + 7/16 + 15/64 + 1/16
+        assert!(rhythm.duration() == Duration(WholeNotes(Ratio::new(95, 64))));
+        assert_eq!(rhythm.to_string(), "{2. 4.. r8... 16}");
+        assert!(matches!(rhythm.segments[2].segment, RhythmSegment::Rest(NoteDuration { kind: NoteDurationKind::Eigth, dots: 3 })));
+        assert_eq!(parsed("tempo 4 = 60; {4..~8.};").to_string(), "4.. ~ 8.");
+    }
+
+    #[test]
+    fn rejects_more_than_three_dots() {
+        match crate::parse::parse("tempo 4 = 60; {4....};") {
+            Ok(_) => panic!("four dots should not parse"),
+            Err(err) => assert_eq!(crate::parse::parse_error_text(&err), "too many dots: 4 (should be at most 3)"),
+        }
+    }
+}
+
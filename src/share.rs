@@ -0,0 +1,60 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+// the source code is stored in the url fragment as #src=<base64 of the deflated source>
+const FRAGMENT_PREFIX: &str = "#src=";
+// so that a malicious link cannot make the page inflate gigabytes of data
+const MAX_SOURCE_LENGTH: usize = 1 << 20;
+
+pub const LOCAL_STORAGE_KEY: &str = "polyrhythm-source";
+
+pub fn source_to_fragment(source: &str) -> String {
+    let compressed = miniz_oxide::deflate::compress_to_vec(source.as_bytes(), 9);
+    format!("{FRAGMENT_PREFIX}{}", URL_SAFE_NO_PAD.encode(compressed))
+}
+
+// returns None if the fragment was not made by source_to_fragment
+pub fn fragment_to_source(fragment: &str) -> Option<String> {
+    let compressed = URL_SAFE_NO_PAD.decode(fragment.strip_prefix(FRAGMENT_PREFIX)?).ok()?;
+    let source = miniz_oxide::inflate::decompress_to_vec_with_limit(&compressed, MAX_SOURCE_LENGTH).ok()?;
+    String::from_utf8(source).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_sources() {
+        for source in ["", "tempo 4 = 120; poly 3:2 over 2;", "tempo 4 = 60; \"clave ♩ é 𝄞\" {4. 8 4} approx \"ça\" {4 4 4};\n"] {
+            let fragment = source_to_fragment(source);
+            assert!(fragment.starts_with(FRAGMENT_PREFIX));
+            // the fragment goes into a url as it is
+            assert!(fragment[FRAGMENT_PREFIX.len()..].chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'), "{fragment}");
+            assert_eq!(fragment_to_source(&fragment).as_deref(), Some(source));
+        }
+    }
+
+    #[test]
+    fn rejects_other_fragments() {
+        let fragment = source_to_fragment("tempo 4 = 120; 4;");
+        assert_eq!(fragment_to_source(""), None);
+        assert_eq!(fragment_to_source(&fragment[1..]), None);
+        assert_eq!(fragment_to_source("#src=not base64!"), None);
+        // valid base64, but not deflated data
+        assert_eq!(fragment_to_source(&format!("{FRAGMENT_PREFIX}{}", URL_SAFE_NO_PAD.encode([0xff; 16]))), None);
+        // cut off in the middle of the deflated data
+        assert_eq!(fragment_to_source(&fragment[..fragment.len() - 4]), None);
+        let not_utf8 = miniz_oxide::deflate::compress_to_vec(&[0xc3, 0x28], 9);
+        assert_eq!(fragment_to_source(&format!("{FRAGMENT_PREFIX}{}", URL_SAFE_NO_PAD.encode(not_utf8))), None);
+    }
+
+    #[test]
+    fn limits_the_inflated_length() {
+        let longest = "4 ".repeat(MAX_SOURCE_LENGTH / 2);
+        assert_eq!(fragment_to_source(&source_to_fragment(&longest)).map(|source| source.len()), Some(MAX_SOURCE_LENGTH));
+        // deflates to a short fragment, but would inflate past the limit
+        let too_long = format!("{longest} ");
+        assert!(source_to_fragment(&too_long).len() < 10_000);
+        assert_eq!(fragment_to_source(&source_to_fragment(&too_long)), None);
+    }
+}
@@ -11,8 +11,10 @@ use crate::units::WholeNotes;
 
 // durations are expressed in terms of whole notes
 #[derive(Copy, Clone, PartialEq, Eq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Duration<Unit>(pub Unit);
 #[derive(Copy, Clone, PartialEq, Eq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Time<Unit>(pub Unit);
 
 impl<Unit: ConstZero> ConstZero for Duration<Unit> {
@@ -10,8 +10,10 @@ use num_rational::Ratio;
 use crate::rhythm::NoteDuration;
 
 #[derive(Copy, Clone, PartialEq, Eq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct WholeNotes(pub Ratio<i32>);
 #[derive(Copy, Clone, PartialEq, Eq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Seconds(pub Ratio<i32>);
 
 impl WholeNotes {
@@ -88,6 +90,16 @@ impl Seconds {
     pub(crate) fn abs(&self) -> Seconds {
         Seconds(self.0.abs())
     }
+
+    pub fn from_f64_rounded(seconds: f64) -> Seconds {
+        // rounded to the nearest millisecond so that the ratio stays small
+        Seconds(Ratio::new((seconds * 1000.0).round() as i32, 1000))
+    }
+
+    pub fn to_whole_notes(self, tempo: (NoteDuration, u32)) -> WholeNotes {
+        let seconds_per_whole_note = WholeNotes(Ratio::from_integer(1)).to_seconds(tempo);
+        WholeNotes(self.0 / seconds_per_whole_note.0)
+    }
 }
 impl ConstZero for Seconds {
     const ZERO: Self = Seconds(Ratio::ZERO);
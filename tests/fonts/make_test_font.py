@@ -0,0 +1,201 @@
+#!/usr/bin/env python3
+# writes the small music font that the golden image tests draw with, so that they do not depend on the bravura submodule
+# every glyph the score drawing uses gets a simple shape made of straight lines at its SMuFL codepoint, with metadata like bravura_metadata.json
+# the shapes only have to be distinct and stable, so the images show where everything is placed rather than what it looks like
+# run it from anywhere with python 3 (no packages needed), then regenerate the golden images
+
+import json
+import os
+import struct
+
+UNITS_PER_EM = 1000
+# SMuFL fonts are 4 staff spaces to the em
+SPACE = UNITS_PER_EM // 4
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+FONT_PATH = os.path.join(HERE, "test_music_font.ttf")
+METADATA_PATH = os.path.join(HERE, "test_music_font_metadata.json")
+
+
+def rect(x0, y0, x1, y1):
+    # clockwise, which truetype uses for filled contours
+    return [(x0, y0), (x0, y1), (x1, y1), (x1, y0)]
+
+
+def octagon(x0, y0, x1, y1):
+    dx, dy = (x1 - x0) * 0.3, (y1 - y0) * 0.3
+    return [(x0, y0 + dy), (x0, y1 - dy), (x0 + dx, y1), (x1 - dx, y1), (x1, y1 - dy), (x1, y0 + dy), (x1 - dx, y0), (x0 + dx, y0)]
+
+
+def hollow_octagon(x0, y0, x1, y1, thickness):
+    return [octagon(x0, y0, x1, y1), list(reversed(octagon(x0 + thickness, y0 + thickness, x1 - thickness, y1 - thickness)))]
+
+
+def bars(count, x0, top, width, height, gap, direction):
+    # flags and the hooks of short rests, stacked from the top down (direction -1) or the bottom up (direction 1)
+    return [rect(x0, top + direction * i * (height + gap), x0 + width, top + direction * i * (height + gap) + height) for i in range(count)]
+
+
+# seven segment digits for the tuplet numbers
+SEGMENTS = {
+    "a": (0.1, 1.3, 0.7, 1.4),
+    "b": (0.6, 0.7, 0.7, 1.4),
+    "c": (0.6, 0.0, 0.7, 0.7),
+    "d": (0.1, 0.0, 0.7, 0.1),
+    "e": (0.1, 0.0, 0.2, 0.7),
+    "f": (0.1, 0.7, 0.2, 1.4),
+    "g": (0.1, 0.65, 0.7, 0.75),
+}
+DIGIT_SEGMENTS = ["abcdef", "bc", "abged", "abgcd", "fgbc", "afgcd", "afgedc", "abc", "abcdefg", "abcdfg"]
+
+# (name, codepoint, contours in staff spaces, anchors in staff spaces)
+GLYPHS = []
+
+
+def glyph(name, codepoint, contours, anchors=None):
+    GLYPHS.append((name, codepoint, contours, anchors or {}))
+
+
+NOTEHEAD_ANCHORS = {"stemUpSE": [1.18, 0.168], "stemDownNW": [0.0, -0.168], "stemUpNW": [0.0, 0.0], "stemDownSW": [0.0, 0.0]}
+glyph("noteheadWhole", 0xE0A2, hollow_octagon(0.0, -0.5, 1.7, 0.5, 0.2), {"noteheadOrigin": [0.0, 0.0]})
+glyph("noteheadHalf", 0xE0A3, hollow_octagon(0.0, -0.5, 1.18, 0.5, 0.15), NOTEHEAD_ANCHORS)
+glyph("noteheadBlack", 0xE0A4, [octagon(0.0, -0.5, 1.18, 0.5)], NOTEHEAD_ANCHORS)
+glyph("augmentationDot", 0xE1E7, [rect(0.0, -0.2, 0.4, 0.2)])
+
+FLAG_LENGTHS = ["8th", "16th", "32nd", "64th", "128th", "256th", "512th", "1024th"]
+for i, length in enumerate(FLAG_LENGTHS):
+    glyph(f"flag{length}Up", 0xE240 + 2 * i, bars(i + 1, 0.0, -0.3, 1.0, 0.3, 0.2, -1))
+    glyph(f"flag{length}Down", 0xE241 + 2 * i, bars(i + 1, 0.0, 0.0, 1.0, 0.3, 0.2, 1))
+
+glyph("restWhole", 0xE4E3, [rect(0.0, -0.5, 1.2, 0.0)])
+glyph("restHalf", 0xE4E4, [rect(0.0, 0.0, 1.2, 0.5)])
+glyph("restQuarter", 0xE4E5, [rect(0.0, -1.5, 0.3, 1.5), rect(0.3, 0.5, 0.9, 0.8)])
+for i, length in enumerate(FLAG_LENGTHS[:7]):
+    glyph(f"rest{length}", 0xE4E6 + i, [rect(0.0, -1.0 - 0.5 * i, 0.2, 0.5)] + bars(i + 1, 0.2, 0.3, 0.6, 0.2, 0.3, -1))
+
+for digit, segments in enumerate(DIGIT_SEGMENTS):
+    glyph(f"tuplet{digit}", 0xE880 + digit, [rect(*SEGMENTS[segment]) for segment in segments])
+glyph("tupletColon", 0xE88A, [rect(0.1, 0.2, 0.3, 0.4), rect(0.1, 0.9, 0.3, 1.1)])
+
+MET_STEM = rect(1.0, 0.0, 1.1, 2.5)
+glyph("metNoteWhole", 0xECA2, hollow_octagon(0.0, -0.4, 1.4, 0.4, 0.15))
+glyph("metNoteHalfUp", 0xECA3, hollow_octagon(0.0, -0.4, 1.1, 0.4, 0.15) + [MET_STEM])
+glyph("metNoteQuarterUp", 0xECA5, [octagon(0.0, -0.4, 1.1, 0.4), MET_STEM])
+for i, length in enumerate(FLAG_LENGTHS):
+    glyph(f"metNote{length}Up", 0xECA7 + 2 * i, [octagon(0.0, -0.4, 1.1, 0.4), MET_STEM] + bars(i + 1, 1.1, 2.2, 0.6, 0.2, 0.15, -1))
+glyph("metAugmentationDot", 0xECB7, [rect(0.0, -0.15, 0.3, 0.15)])
+
+
+def to_units(contours):
+    return [[(round(x * SPACE), round(y * SPACE)) for x, y in contour] for contour in contours]
+
+
+def bounds(contours):
+    xs = [x for contour in contours for x, _ in contour]
+    ys = [y for contour in contours for _, y in contour]
+    return min(xs), min(ys), max(xs), max(ys)
+
+
+def glyf_entry(contours):
+    x_min, y_min, x_max, y_max = bounds(contours)
+    data = struct.pack(">hhhhh", len(contours), x_min, y_min, x_max, y_max)
+    end = -1
+    for contour in contours:
+        end += len(contour)
+        data += struct.pack(">H", end)
+    data += struct.pack(">H", 0)
+    points = [point for contour in contours for point in contour]
+    # every point is on the curve, with two byte coordinate deltas
+    data += bytes([0x01] * len(points))
+    previous = 0
+    for x, _ in points:
+        data += struct.pack(">h", x - previous)
+        previous = x
+    previous = 0
+    for _, y in points:
+        data += struct.pack(">h", y - previous)
+        previous = y
+    return data + b"\0" * (-len(data) % 4)
+
+
+def cmap_table(codepoints):
+    # format 4 with one segment per glyph, which is plenty for a few dozen glyphs
+    segments = [(codepoint, codepoint, glyph_id - codepoint) for glyph_id, codepoint in codepoints] + [(0xFFFF, 0xFFFF, 1)]
+    count = len(segments)
+    search_range = 2 * 2 ** (count.bit_length() - 1)
+    subtable = struct.pack(">HHHHHHH", 4, 16 + 8 * count, 0, 2 * count, search_range, count.bit_length() - 1, 2 * count - search_range)
+    subtable += b"".join(struct.pack(">H", end) for _, end, _ in segments) + struct.pack(">H", 0)
+    subtable += b"".join(struct.pack(">H", start) for start, _, _ in segments)
+    subtable += b"".join(struct.pack(">h", (delta + 0x8000) % 0x10000 - 0x8000) for _, _, delta in segments)
+    subtable += b"".join(struct.pack(">H", 0) for _ in segments)
+    return struct.pack(">HHHHI", 0, 1, 3, 1, 12) + subtable
+
+
+def checksum(data):
+    data += b"\0" * (-len(data) % 4)
+    return sum(struct.unpack(f">{len(data) // 4}I", data)) % 2**32
+
+
+def write_font():
+    # glyph 0 is the empty .notdef
+    outlines = [[]] + [to_units(contours) for _, _, contours, _ in GLYPHS]
+    glyf = b""
+    offsets = [0]
+    for contours in outlines:
+        glyf += glyf_entry(contours) if contours else b""
+        offsets.append(len(glyf))
+    advances = [(0, 0)] + [(bounds(contours)[2] + SPACE // 5, bounds(contours)[0]) for contours in outlines[1:]]
+    all_points = [bounds(contours) for contours in outlines[1:]]
+    x_min, y_min = min(b[0] for b in all_points), min(b[1] for b in all_points)
+    x_max, y_max = max(b[2] for b in all_points), max(b[3] for b in all_points)
+    max_points = max(sum(len(contour) for contour in contours) for contours in outlines)
+    max_contours = max(len(contours) for contours in outlines)
+
+    tables = {
+        "cmap": cmap_table(sorted(((glyph_id + 1, codepoint) for glyph_id, (_, codepoint, _, _) in enumerate(GLYPHS)), key=lambda glyph: glyph[1])),
+        "glyf": glyf,
+        # long offsets, fixed dates so the file is the same every time
+        "head": struct.pack(">IIIIHHqqhhhhHHhhh", 0x00010000, 0x00010000, 0, 0x5F0F3CF5, 0, UNITS_PER_EM, 0, 0, x_min, y_min, x_max, y_max, 0, 8, 2, 1, 0),
+        "hhea": struct.pack(">IhhhHhhhhhhhhhhhH", 0x00010000, y_max, y_min, 0, max(advance for advance, _ in advances), min(lsb for _, lsb in advances), 0, x_max, 1, 0, 0, 0, 0, 0, 0, 0, len(outlines)),
+        "hmtx": b"".join(struct.pack(">Hh", advance, lsb) for advance, lsb in advances),
+        "loca": b"".join(struct.pack(">I", offset) for offset in offsets),
+        "maxp": struct.pack(">IHHHHHHHHHHHHHH", 0x00010000, len(outlines), max_points, max_contours, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0),
+        "post": struct.pack(">IIhhIIIII", 0x00030000, 0, 0, 0, 0, 0, 0, 0, 0),
+    }
+
+    count = len(tables)
+    search_range = 16 * 2 ** (count.bit_length() - 1)
+    header = struct.pack(">IHHHH", 0x00010000, count, search_range, count.bit_length() - 1, 16 * count - search_range)
+    offset = len(header) + 16 * count
+    directory = b""
+    body = b""
+    for tag in sorted(tables):
+        data = tables[tag]
+        directory += struct.pack(">4sIII", tag.encode(), checksum(data), offset + len(body), len(data))
+        body += data + b"\0" * (-len(data) % 4)
+    with open(FONT_PATH, "wb") as file:
+        file.write(header + directory + body)
+
+
+def write_metadata():
+    def staff_spaces(value):
+        return round(value / SPACE, 3)
+
+    bounding_boxes = {}
+    for name, _, contours, _ in GLYPHS:
+        x_min, y_min, x_max, y_max = bounds(to_units(contours))
+        bounding_boxes[name] = {"bBoxNE": [staff_spaces(x_max), staff_spaces(y_max)], "bBoxSW": [staff_spaces(x_min), staff_spaces(y_min)]}
+    metadata = {
+        "fontName": "Polyrhythm Test",
+        "fontVersion": 1.0,
+        "engravingDefaults": {"staffLineThickness": 0.13, "stemThickness": 0.12, "slurMidpointThickness": 0.22, "textFontFamily": []},
+        "glyphBBoxes": bounding_boxes,
+        "glyphsWithAnchors": {name: anchors for name, _, _, anchors in GLYPHS if anchors},
+    }
+    with open(METADATA_PATH, "w") as file:
+        json.dump(metadata, file, indent=2, sort_keys=True)
+        file.write("\n")
+
+
+write_font()
+write_metadata()
@@ -0,0 +1,53 @@
+#![cfg(feature = "raster")]
+
+use std::{fs, path::PathBuf};
+
+use polyrhythm::headless::{self, RasterOptions};
+
+// a small font made for these tests by tests/fonts/make_test_font.py, so that they do not depend on the bravura submodule
+// labels are not drawn, since no text font is given, so the images do not depend on the fonts installed on the machine
+const FONT_METADATA: &str = "tests/fonts/test_music_font_metadata.json";
+const MUSIC_FONT: &str = "tests/fonts/test_music_font.ttf";
+
+// every tests/golden/<name>.txt is drawn and compared pixel by pixel with tests/golden/<name>.png
+// run with UPDATE_EXPECTED=1 to write the images from the current output instead, and look at them before checking them in
+#[test]
+fn draws_golden_images() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let font_metadata = fs::read_to_string(root.join(FONT_METADATA)).unwrap_or_else(|err| panic!("could not read {FONT_METADATA}: {err}"));
+    let music_font = fs::read(root.join(MUSIC_FONT)).unwrap_or_else(|err| panic!("could not read {MUSIC_FONT}: {err}"));
+
+    let dir = root.join("tests/golden");
+    let mut sources: Vec<_> = fs::read_dir(&dir)
+        .expect("could not read golden sources")
+        .map(|entry| entry.expect("could not read golden source").path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "txt"))
+        .collect();
+    sources.sort();
+    assert!(!sources.is_empty(), "no golden sources in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for path in sources {
+        let source = fs::read_to_string(&path).expect("could not read golden source");
+        // a fixed width, so that longer sources are broken into systems the same way every time
+        let options = RasterOptions { max_width: Some(800.0), scale: 1.0, transparent: false, ..RasterOptions::default() };
+        let png = headless::to_png(&source, &font_metadata, &music_font, None, &options).unwrap_or_else(|err| panic!("could not draw {}: {err}", path.display()));
+        let expected_path = path.with_extension("png");
+        if std::env::var_os("UPDATE_EXPECTED").is_some() {
+            fs::write(&expected_path, &png).expect("could not write golden image");
+            continue;
+        }
+
+        let actual = tiny_skia::Pixmap::decode_png(&png).expect("could not decode the drawn image");
+        let expected = fs::read(&expected_path).unwrap_or_else(|err| panic!("could not read {}: {err} (UPDATE_EXPECTED=1 writes it)", expected_path.display()));
+        let expected = tiny_skia::Pixmap::decode_png(&expected).unwrap_or_else(|err| panic!("could not decode {}: {err}", expected_path.display()));
+        if actual != expected {
+            // the drawn image is kept next to the build output so it can be compared with the golden one
+            let actual_path = root.join("target/golden").join(expected_path.file_name().expect("golden images have file names"));
+            fs::create_dir_all(actual_path.parent().expect("golden output has a directory")).expect("could not create target/golden");
+            fs::write(&actual_path, &png).expect("could not write the drawn image");
+            failures.push(format!("{} differs from {}", actual_path.display(), expected_path.display()));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}